@@ -0,0 +1,364 @@
+//! Backtesting of the predicted loss distributions
+//! against realized losses.  Each historical snapshot
+//! of the portfolio gives a probability integral
+//! transform (PIT) u_t=P(L_t<=l_t) of its realized loss
+//! l_t, which should be independent and uniform if the
+//! model is well calibrated.  The exceptions (losses
+//! beyond the value at risk) are tested with Kupiec's
+//! proportion of failures and Christoffersen's
+//! independence and conditional coverage tests, and the
+//! whole distribution with Berkowitz's test on the
+//! normal transforms N^{-1}(u_t).
+
+use crate::cf_transform::{get_cdf, get_density_coefficients};
+use crate::distributions::{chi_squared_cdf, normal_inv};
+use crate::error::EconomicCapitalError;
+use crate::EconomicCapitalAttributes;
+use num_complex::Complex;
+use serde_derive::Serialize;
+
+/// Bound on the PIT values so that their normal
+/// transforms stay finite.
+const PIT_BOUND: f64 = 1e-10;
+
+/// A historical snapshot of the portfolio and its
+/// realized loss over the following period.
+#[derive(Debug, Clone)]
+pub struct BacktestPeriod {
+    /// The portfolio at the start of the period.
+    pub attributes: EconomicCapitalAttributes,
+    /// Realized loss (in dollars, positive) over the
+    /// period.
+    pub realized_loss: f64,
+}
+
+/// Likelihood ratio test statistic and its p-value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LikelihoodRatioTest {
+    /// Likelihood ratio statistic.
+    pub statistic: f64,
+    /// Probability of a statistic at least this large
+    /// if the model is correct.
+    pub p_value: f64,
+}
+
+/// Results of the backtest.
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestReport {
+    /// PIT value of each period.
+    pub pit: Vec<f64>,
+    /// Number of periods with a loss beyond the value
+    /// at risk.
+    pub num_exceptions: usize,
+    /// Kupiec's proportion of failures test.
+    pub kupiec: LikelihoodRatioTest,
+    /// Christoffersen's independence test.
+    pub independence: LikelihoodRatioTest,
+    /// Christoffersen's conditional coverage test.
+    pub conditional_coverage: LikelihoodRatioTest,
+    /// Berkowitz's test.
+    pub berkowitz: LikelihoodRatioTest,
+}
+
+fn get_likelihood_ratio_test(statistic: f64, degrees_of_freedom: f64) -> LikelihoodRatioTest {
+    let statistic = statistic.max(0.0);
+    LikelihoodRatioTest {
+        statistic,
+        p_value: 1.0 - chi_squared_cdf(statistic, degrees_of_freedom),
+    }
+}
+
+/// Returns n*ln(p), taken as zero when n is zero.
+fn get_log_likelihood_term(n: f64, p: f64) -> f64 {
+    if n > 0.0 {
+        n * p.ln()
+    } else {
+        0.0
+    }
+}
+
+/// Returns the log likelihood of n_0 non-exceptions
+/// and n_1 exceptions with exception probability p.
+fn get_bernoulli_log_likelihood(n_0: f64, n_1: f64, p: f64) -> f64 {
+    get_log_likelihood_term(n_0, 1.0 - p) + get_log_likelihood_term(n_1, p)
+}
+
+fn get_ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0.0 {
+        numerator / denominator
+    } else {
+        0.0
+    }
+}
+
+/// Returns the probability that the loss is at most
+/// the realized loss, from the discrete characteristic
+/// function of the portfolio.
+///
+/// # Arguments
+///
+/// * `discrete_cf` - The discrete characteristic
+///   function of the portfolio, eg from
+///   [get_full_cf](../struct.EconomicCapitalAttributes.html#method.get_full_cf).
+/// * `x_min` - Minimum of the (negative) loss domain.
+/// * `x_max` - Maximum of the (negative) loss domain.
+/// * `realized_loss` - Realized loss (in dollars,
+///   positive).
+pub fn get_pit(discrete_cf: &[Complex<f64>], x_min: f64, x_max: f64, realized_loss: f64) -> f64 {
+    let x = -realized_loss;
+    if x <= x_min {
+        return 1.0;
+    }
+    if x >= x_max {
+        return 0.0;
+    }
+    let coefficients = get_density_coefficients(discrete_cf, x_min, x_max);
+    (1.0 - get_cdf(&coefficients, x_min, x_max, x)).clamp(0.0, 1.0)
+}
+
+/// Returns Kupiec's proportion of failures test of
+/// the number of exceptions, where an exception is a
+/// PIT value above 1-alpha.
+///
+/// # Arguments
+///
+/// * `pit` - The PIT value of each period.
+/// * `alpha` - Tail probability of the value at risk.
+pub fn get_kupiec_test(pit: &[f64], alpha: f64) -> LikelihoodRatioTest {
+    let n = pit.len() as f64;
+    let n_1 = pit.iter().filter(|u| **u > 1.0 - alpha).count() as f64;
+    let n_0 = n - n_1;
+    get_likelihood_ratio_test(
+        2.0 * (get_bernoulli_log_likelihood(n_0, n_1, n_1 / n)
+            - get_bernoulli_log_likelihood(n_0, n_1, alpha)),
+        1.0,
+    )
+}
+
+/// Returns Christoffersen's independence test of the
+/// exceptions, against a first order Markov chain.
+///
+/// # Arguments
+///
+/// * `pit` - The PIT value of each period.
+/// * `alpha` - Tail probability of the value at risk.
+pub fn get_independence_test(pit: &[f64], alpha: f64) -> LikelihoodRatioTest {
+    let exceptions: Vec<bool> = pit.iter().map(|u| *u > 1.0 - alpha).collect();
+    //transition counts n_ij from state i to state j
+    let mut counts = [[0.0; 2]; 2];
+    exceptions
+        .windows(2)
+        .for_each(|pair| counts[pair[0] as usize][pair[1] as usize] += 1.0);
+    let [[n_00, n_01], [n_10, n_11]] = counts;
+    let p_01 = get_ratio(n_01, n_00 + n_01);
+    let p_11 = get_ratio(n_11, n_10 + n_11);
+    let p = get_ratio(n_01 + n_11, n_00 + n_01 + n_10 + n_11);
+    get_likelihood_ratio_test(
+        2.0 * (get_bernoulli_log_likelihood(n_00, n_01, p_01)
+            + get_bernoulli_log_likelihood(n_10, n_11, p_11)
+            - get_bernoulli_log_likelihood(n_00 + n_10, n_01 + n_11, p)),
+        1.0,
+    )
+}
+
+/// Returns Berkowitz's likelihood ratio test that the
+/// normal transforms of the PIT values are independent
+/// standard normal, against a Gaussian AR(1)
+/// alternative (conditional on the first period).
+///
+/// # Arguments
+///
+/// * `pit` - The PIT value of each period.
+pub fn get_berkowitz_test(pit: &[f64]) -> Result<LikelihoodRatioTest, EconomicCapitalError> {
+    if pit.len() < 3 {
+        return Err(EconomicCapitalError::new(
+            "Berkowitz test requires at least three periods.",
+        ));
+    }
+    let z: Vec<f64> = pit
+        .iter()
+        .map(|u| normal_inv(u.clamp(PIT_BOUND, 1.0 - PIT_BOUND)))
+        .collect();
+    let previous = &z[..z.len() - 1];
+    let current = &z[1..];
+    let n = current.len() as f64;
+    let mean_previous = previous.iter().sum::<f64>() / n;
+    let mean_current = current.iter().sum::<f64>() / n;
+    let covariance = previous
+        .iter()
+        .zip(current)
+        .map(|(x, y)| (x - mean_previous) * (y - mean_current))
+        .sum::<f64>();
+    let variance_previous = previous
+        .iter()
+        .map(|x| (x - mean_previous).powi(2))
+        .sum::<f64>();
+    let rho = get_ratio(covariance, variance_previous);
+    let intercept = mean_current - rho * mean_previous;
+    let residual_variance = previous
+        .iter()
+        .zip(current)
+        .map(|(x, y)| (y - intercept - rho * x).powi(2))
+        .sum::<f64>()
+        / n;
+    if residual_variance <= 0.0 {
+        return Err(EconomicCapitalError::new(
+            "PIT values are degenerate for the Berkowitz test.",
+        ));
+    }
+    let log_2_pi = (2.0 * std::f64::consts::PI).ln();
+    let unrestricted = -0.5 * n * (log_2_pi + residual_variance.ln() + 1.0);
+    let restricted = -0.5 * current.iter().map(|y| log_2_pi + y * y).sum::<f64>();
+    Ok(get_likelihood_ratio_test(
+        2.0 * (unrestricted - restricted),
+        3.0,
+    ))
+}
+
+/// Returns the backtest of the PIT values.
+///
+/// # Arguments
+///
+/// * `pit` - The PIT value of each period.
+/// * `alpha` - Tail probability of the value at risk
+///   for the coverage tests.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let pit: Vec<f64> = (0..100).map(|t| ((t * 37 % 100) as f64 + 0.5) / 100.0).collect();
+/// let report = loan_ec::backtest::get_backtest_report(&pit, 0.05).unwrap();
+/// assert_eq!(report.num_exceptions, 5);
+/// assert!(report.kupiec.p_value > 0.99);
+/// # }
+/// ```
+pub fn get_backtest_report(
+    pit: &[f64],
+    alpha: f64,
+) -> Result<BacktestReport, EconomicCapitalError> {
+    if alpha <= 0.0 || alpha >= 1.0 {
+        return Err(EconomicCapitalError::new("Alpha must be between 0 and 1."));
+    }
+    let kupiec = get_kupiec_test(pit, alpha);
+    let independence = get_independence_test(pit, alpha);
+    Ok(BacktestReport {
+        pit: pit.to_vec(),
+        num_exceptions: pit.iter().filter(|u| **u > 1.0 - alpha).count(),
+        kupiec,
+        independence,
+        conditional_coverage: get_likelihood_ratio_test(
+            kupiec.statistic + independence.statistic,
+            2.0,
+        ),
+        berkowitz: get_berkowitz_test(pit)?,
+    })
+}
+
+/// Returns the backtest of historical snapshots of the
+/// portfolio against their realized losses.
+///
+/// # Arguments
+///
+/// * `periods` - The portfolio at the start of each
+///   period and its realized loss.
+/// * `mgf_systemic` - The moment generating function
+///   of the systemic random variables.
+/// * `x_min` - Minimum of the (negative) loss domain
+///   used for the `u_domain` of the snapshots.
+/// * `x_max` - Maximum of the (negative) loss domain.
+/// * `alpha` - Tail probability of the value at risk
+///   for the coverage tests.
+pub fn get_backtest<U>(
+    periods: &[BacktestPeriod],
+    mgf_systemic: &U,
+    x_min: f64,
+    x_max: f64,
+    alpha: f64,
+) -> Result<BacktestReport, EconomicCapitalError>
+where
+    U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+{
+    let pit: Vec<f64> = periods
+        .iter()
+        .map(|period| {
+            get_pit(
+                &period.attributes.get_full_cf(mgf_systemic),
+                x_min,
+                x_max,
+                period.realized_loss,
+            )
+        })
+        .collect();
+    get_backtest_report(&pit, alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_uniform_pit(n: usize) -> Vec<f64> {
+        //a permutation of the uniform grid
+        (0..n)
+            .map(|t| ((t * 37 % n) as f64 + 0.5) / n as f64)
+            .collect()
+    }
+    #[test]
+    fn coverage_tests_detect_miscalibration() {
+        let pit = get_uniform_pit(100);
+        let kupiec = get_kupiec_test(&pit, 0.05);
+        assert_abs_diff_eq!(kupiec.statistic, 0.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(kupiec.p_value, 1.0, epsilon = 0.0000001);
+        //too many exceptions
+        let compressed: Vec<f64> = pit.iter().map(|u| u.powf(0.3)).collect();
+        assert!(get_kupiec_test(&compressed, 0.05).p_value < 0.01);
+        //clustered exceptions
+        let clustered: Vec<f64> = (0..100)
+            .map(|t| if (40..45).contains(&t) { 0.99 } else { 0.5 })
+            .collect();
+        assert_abs_diff_eq!(
+            get_kupiec_test(&clustered, 0.05).statistic,
+            0.0,
+            epsilon = 0.0000001
+        );
+        assert!(get_independence_test(&clustered, 0.05).p_value < 0.01);
+        assert!(get_independence_test(&pit, 0.05).p_value > 0.05);
+    }
+    #[test]
+    fn berkowitz_detects_miscalibration() {
+        let pit = get_uniform_pit(200);
+        assert!(get_berkowitz_test(&pit).unwrap().p_value > 0.05);
+        //too little dispersion
+        let narrow: Vec<f64> = pit.iter().map(|u| 0.5 + 0.5 * (u - 0.5)).collect();
+        assert!(get_berkowitz_test(&narrow).unwrap().p_value < 0.01);
+        assert!(get_berkowitz_test(&pit[..2]).is_err());
+    }
+    #[test]
+    fn pit_of_portfolio_losses() {
+        let num_u = 256;
+        let x_min = -500.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.3];
+        let mgf = gamma_mgf(&v);
+        let attributes = process_test_loans(
+            exponential_lgd_cf,
+            0.0,
+            0.0,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &[get_test_loan(vec![0.4, 0.6], 1000.0)],
+        );
+        let periods: Vec<BacktestPeriod> = [5.0, 25.0, 60.0, 1000.0]
+            .iter()
+            .map(|realized_loss| BacktestPeriod {
+                attributes: attributes.clone(),
+                realized_loss: *realized_loss,
+            })
+            .collect();
+        let report = get_backtest(&periods, &mgf, x_min, x_max, 0.01).unwrap();
+        assert!(report.pit.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(report.pit[0] > 0.0);
+        assert_eq!(report.pit[3], 1.0);
+        assert_eq!(report.num_exceptions, 2);
+    }
+}
@@ -0,0 +1,737 @@
+//! Calibration of the systemic variables from
+//! historical default rates.  The default rate of a
+//! segment in period t is pd*sum_k w_k X_kt (plus
+//! binomial noise when the number of obligors is
+//! finite), where the X_k are independent Gamma
+//! variables with expectation one.  The variances and
+//! weights are returned in the shapes expected by
+//! [get_full_cf](../struct.EconomicCapitalAttributes.html#method.get_full_cf)
+//! (through
+//! [systemic_gamma_mgf](../fn.systemic_gamma_mgf.html))
+//! and
+//! [get_portfolio_variance](../struct.EconomicCapitalAttributes.html#method.get_portfolio_variance).
+//!
+//! The liquidity parameters `q` and `lambda0` are
+//! calibrated from a target probability of a liquidity
+//! event at a given loss and a target expected
+//! fire-sale loss.  Conditional on the portfolio loss
+//! l, liquidity events are Poisson with intensity q*l
+//! and each costs lambda, so the probability of at
+//! least one event is 1-exp(-q*l) and the expected
+//! fire-sale loss is q*lambda*E\[l\], matching
+//! [expectation_liquidity](../fn.expectation_liquidity.html).
+
+use crate::distributions::{gamma_inv, ln_gamma};
+use crate::error::EconomicCapitalError;
+use crate::{expectation_liquidity, variance_liquidity, EconomicCapitalAttributes};
+use serde_derive::{Deserialize, Serialize};
+
+/// Smallest variance returned for a systemic variable.
+const MIN_VARIANCE: f64 = 0.0001;
+/// Largest variance searched by maximum likelihood.
+const MAX_VARIANCE: f64 = 100.0;
+/// Number of golden section steps for maximum
+/// likelihood.
+const LIKELIHOOD_ITERATIONS: usize = 100;
+/// Number of principal axis iterations for the
+/// common factor.
+const FACTOR_ITERATIONS: usize = 200;
+
+/// Historical default rates of a segment.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DefaultRateSeries {
+    /// Default rate in each period.
+    pub default_rates: Vec<f64>,
+    #[serde(default)]
+    /// Average number of obligors in each period.
+    /// When given, the binomial (idiosyncratic) part
+    /// of the variance is removed and maximum
+    /// likelihood uses default counts.
+    pub num_obligors: Option<f64>,
+}
+
+/// Method for estimating the variance of a systemic
+/// variable.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VarianceMethod {
+    /// Method of moments.
+    Moments,
+    /// Maximum likelihood (Gamma for default rates,
+    /// negative binomial for default counts).
+    MaximumLikelihood,
+}
+
+/// Calibrated systemic variables.
+#[derive(Debug, Clone, Serialize)]
+pub struct FactorCalibration {
+    /// Probability of default (average default rate)
+    /// of each segment.
+    pub pd: Vec<f64>,
+    /// Weight vector of each segment, for the `weight`
+    /// of its loans.
+    pub weights: Vec<Vec<f64>>,
+    /// Expectation of each systemic variable (`el_sys`).
+    pub expectation: Vec<f64>,
+    /// Variance of each systemic variable (`var_sys`).
+    pub variance: Vec<f64>,
+}
+
+fn get_mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn get_covariance(x: &[f64], y: &[f64]) -> f64 {
+    let (mean_x, mean_y) = (get_mean(x), get_mean(y));
+    x.iter()
+        .zip(y)
+        .map(|(a, b)| (a - mean_x) * (b - mean_y))
+        .sum::<f64>()
+        / (x.len() as f64 - 1.0)
+}
+
+impl DefaultRateSeries {
+    /// Returns the average default rate.
+    fn get_pd(&self) -> Result<f64, EconomicCapitalError> {
+        if self.default_rates.len() < 2 {
+            return Err(EconomicCapitalError::new(
+                "Default rate series must have at least two periods.",
+            ));
+        }
+        let pd = get_mean(&self.default_rates);
+        if pd <= 0.0 {
+            return Err(EconomicCapitalError::new(
+                "Average default rate must be positive.",
+            ));
+        }
+        Ok(pd)
+    }
+    /// Returns the default rates divided by their
+    /// average, ie the realizations of the systemic
+    /// variable.
+    fn get_systemic_realizations(&self) -> Result<Vec<f64>, EconomicCapitalError> {
+        let pd = self.get_pd()?;
+        Ok(self.default_rates.iter().map(|rate| rate / pd).collect())
+    }
+    /// Returns the binomial variance of the default
+    /// rate divided by pd^2.
+    fn get_idiosyncratic_variance(&self, pd: f64) -> f64 {
+        self.num_obligors
+            .map_or(0.0, |num_obligors| (1.0 - pd) / (pd * num_obligors))
+    }
+}
+
+/// Returns the variance of the systemic variable of
+/// a segment by the method of moments:
+/// (Var(DR)-pd(1-pd)/N)/pd^2.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let series = loan_ec::calibration::DefaultRateSeries{
+///     default_rates: vec![0.01, 0.03, 0.02, 0.04, 0.0],
+///     num_obligors: None
+/// };
+/// let variance = loan_ec::calibration::get_moment_variance(&series).unwrap();
+/// assert!((variance - 0.625).abs() < 0.0000001);
+/// # }
+/// ```
+pub fn get_moment_variance(series: &DefaultRateSeries) -> Result<f64, EconomicCapitalError> {
+    let pd = series.get_pd()?;
+    let realizations = series.get_systemic_realizations()?;
+    let variance =
+        get_covariance(&realizations, &realizations) - series.get_idiosyncratic_variance(pd);
+    Ok(variance.max(MIN_VARIANCE))
+}
+
+/// Returns the log likelihood of the default rates
+/// given the variance of the systemic variable.
+fn get_log_likelihood(series: &DefaultRateSeries, pd: f64, variance: f64) -> f64 {
+    let shape = 1.0 / variance;
+    match series.num_obligors {
+        Some(num_obligors) => {
+            //negative binomial: Poisson count with Gamma mixed intensity
+            let mean = pd * num_obligors;
+            series
+                .default_rates
+                .iter()
+                .map(|rate| {
+                    let count = rate * num_obligors;
+                    ln_gamma(count + shape) - ln_gamma(shape) - ln_gamma(count + 1.0)
+                        + shape * (shape / (shape + mean)).ln()
+                        + count * (mean / (shape + mean)).ln()
+                })
+                .sum()
+        }
+        None => series
+            .default_rates
+            .iter()
+            .map(|rate| {
+                let x = rate / pd;
+                (shape - 1.0) * x.ln() - x / variance - shape * variance.ln() - ln_gamma(shape)
+            })
+            .sum(),
+    }
+}
+
+/// Returns the variance of the systemic variable of
+/// a segment by maximum likelihood.  Without the
+/// number of obligors, the default rates divided by
+/// their average are Gamma distributed and must be
+/// positive.  With the number of obligors, the
+/// default counts are negative binomial.
+pub fn get_maximum_likelihood_variance(
+    series: &DefaultRateSeries,
+) -> Result<f64, EconomicCapitalError> {
+    let pd = series.get_pd()?;
+    if series.num_obligors.is_none() && series.default_rates.iter().any(|rate| *rate <= 0.0) {
+        return Err(EconomicCapitalError::new(
+            "Default rates must be positive without the number of obligors.",
+        ));
+    }
+    //golden section search over the log of the variance
+    let ratio = 0.5 * (5.0_f64.sqrt() - 1.0);
+    let objective = |log_variance: f64| get_log_likelihood(series, pd, log_variance.exp());
+    let (mut low, mut high) = (MIN_VARIANCE.ln(), MAX_VARIANCE.ln());
+    for _ in 0..LIKELIHOOD_ITERATIONS {
+        let left = high - ratio * (high - low);
+        let right = low + ratio * (high - low);
+        if objective(left) < objective(right) {
+            low = left;
+        } else {
+            high = right;
+        }
+    }
+    Ok((0.5 * (low + high)).exp())
+}
+
+fn get_variance(
+    series: &DefaultRateSeries,
+    method: VarianceMethod,
+) -> Result<f64, EconomicCapitalError> {
+    match method {
+        VarianceMethod::Moments => get_moment_variance(series),
+        VarianceMethod::MaximumLikelihood => get_maximum_likelihood_variance(series),
+    }
+}
+
+/// Returns a calibration with one systemic variable
+/// per segment.  Each segment has all its weight on
+/// its own systemic variable.
+///
+/// # Arguments
+///
+/// * `series` - The default rates of each segment.
+/// * `method` - The method for estimating the
+///   variances.
+pub fn get_segment_calibration(
+    series: &[DefaultRateSeries],
+    method: VarianceMethod,
+) -> Result<FactorCalibration, EconomicCapitalError> {
+    let num_w = series.len();
+    let pd = series
+        .iter()
+        .map(|s| s.get_pd())
+        .collect::<Result<Vec<f64>, EconomicCapitalError>>()?;
+    let variance = series
+        .iter()
+        .map(|s| get_variance(s, method))
+        .collect::<Result<Vec<f64>, EconomicCapitalError>>()?;
+    let weights = (0..num_w)
+        .map(|segment| {
+            (0..num_w)
+                .map(|k| if k == segment { 1.0 } else { 0.0 })
+                .collect()
+        })
+        .collect();
+    Ok(FactorCalibration {
+        pd,
+        weights,
+        expectation: vec![1.0; num_w],
+        variance,
+    })
+}
+
+/// Returns a calibration with a common systemic
+/// variable (the first) and a specific systemic
+/// variable per segment, by the method of moments.
+/// The loadings a_s=w_s*sqrt(v_0) on the common
+/// variable are the principal axis factor of the
+/// covariance matrix of the default rates divided by
+/// their averages.  The common variance v_0 is the
+/// largest systemic variance of a segment, so that
+/// every weight is at most one.
+///
+/// # Arguments
+///
+/// * `series` - The default rates of each segment.
+///   All series must cover the same periods.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let series: Vec<loan_ec::calibration::DefaultRateSeries> = serde_json::from_str(r#"[
+///     {"default_rates": [0.01, 0.03, 0.02, 0.04, 0.01]},
+///     {"default_rates": [0.02, 0.05, 0.03, 0.05, 0.02]},
+///     {"default_rates": [0.005, 0.01, 0.012, 0.02, 0.004]}
+/// ]"#).unwrap();
+/// let calibration = loan_ec::calibration::get_common_factor_calibration(&series).unwrap();
+/// assert_eq!(calibration.variance.len(), 4);
+/// assert_eq!(calibration.weights[0].len(), 4);
+/// # }
+/// ```
+pub fn get_common_factor_calibration(
+    series: &[DefaultRateSeries],
+) -> Result<FactorCalibration, EconomicCapitalError> {
+    let num_segments = series.len();
+    if num_segments == 0
+        || series
+            .iter()
+            .any(|s| s.default_rates.len() != series[0].default_rates.len())
+    {
+        return Err(EconomicCapitalError::new(
+            "Default rate series must be non-empty and cover the same periods.",
+        ));
+    }
+    let pd = series
+        .iter()
+        .map(|s| s.get_pd())
+        .collect::<Result<Vec<f64>, EconomicCapitalError>>()?;
+    let realizations = series
+        .iter()
+        .map(|s| s.get_systemic_realizations())
+        .collect::<Result<Vec<Vec<f64>>, EconomicCapitalError>>()?;
+    let mut covariance: Vec<Vec<f64>> = realizations
+        .iter()
+        .map(|x| realizations.iter().map(|y| get_covariance(x, y)).collect())
+        .collect();
+    let total_variance: Vec<f64> = series
+        .iter()
+        .zip(&pd)
+        .enumerate()
+        .map(|(s, (segment, pd))| {
+            (covariance[s][s] - segment.get_idiosyncratic_variance(*pd)).max(MIN_VARIANCE)
+        })
+        .collect();
+    let mut loadings: Vec<f64> = total_variance.iter().map(|v| v.sqrt()).collect();
+    for _ in 0..FACTOR_ITERATIONS {
+        (0..num_segments).for_each(|s| covariance[s][s] = loadings[s].powi(2));
+        //one power iteration on the reduced covariance matrix
+        let product: Vec<f64> = covariance
+            .iter()
+            .map(|row| row.iter().zip(&loadings).map(|(c, a)| c * a).sum())
+            .collect();
+        let norm = product.iter().map(|p| p * p).sum::<f64>().sqrt();
+        let eigenvalue = product
+            .iter()
+            .zip(&loadings)
+            .map(|(p, a)| p * a)
+            .sum::<f64>()
+            / loadings.iter().map(|a| a * a).sum::<f64>();
+        if norm <= 0.0 || eigenvalue <= 0.0 {
+            break;
+        }
+        loadings = product
+            .iter()
+            .zip(&total_variance)
+            .map(|(p, v)| (eigenvalue.sqrt() * p / norm).clamp(0.0, v.sqrt()))
+            .collect();
+    }
+    let common_variance = total_variance.iter().cloned().fold(MIN_VARIANCE, f64::max);
+    let common_weight: Vec<f64> = loadings
+        .iter()
+        .map(|a| (a / common_variance.sqrt()).min(1.0))
+        .collect();
+    let specific_variance = total_variance.iter().zip(&common_weight).map(|(v, w)| {
+        if *w < 1.0 {
+            ((v - w * w * common_variance) / (1.0 - w).powi(2)).max(MIN_VARIANCE)
+        } else {
+            MIN_VARIANCE
+        }
+    });
+    let weights = common_weight
+        .iter()
+        .enumerate()
+        .map(|(segment, w)| {
+            std::iter::once(*w)
+                .chain((0..num_segments).map(|k| if k == segment { 1.0 - w } else { 0.0 }))
+                .collect()
+        })
+        .collect();
+    Ok(FactorCalibration {
+        pd,
+        weights,
+        expectation: vec![1.0; num_segments + 1],
+        variance: std::iter::once(common_variance)
+            .chain(specific_variance)
+            .collect(),
+    })
+}
+
+/// Portfolio loss at which the probability of a
+/// liquidity event is targeted, eg `{"type":
+/// "quantile", "probability": 0.5, "alpha": 0.01}`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiquidityEventTarget {
+    /// Probability of a liquidity event when the loss
+    /// equals the expected loss.
+    ExpectedLoss {
+        /// Probability in (0, 1).
+        probability: f64,
+    },
+    /// Probability of a liquidity event when the loss
+    /// equals its quantile at tail probability
+    /// `alpha`.  The quantile is approximated by a
+    /// Gamma distribution with the portfolio's
+    /// expectation and variance.
+    Quantile {
+        /// Probability in (0, 1).
+        probability: f64,
+        /// Tail probability in (0, 1).
+        alpha: f64,
+    },
+    /// Probability of a liquidity event when the loss
+    /// equals `loss`.
+    Loss {
+        /// Probability in (0, 1).
+        probability: f64,
+        /// Loss (in dollars, positive).
+        loss: f64,
+    },
+}
+
+/// Targets for the liquidity parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct LiquidityTarget {
+    /// Probability of a liquidity event at a given
+    /// loss, which determines `q`.
+    pub event: LiquidityEventTarget,
+    /// Expected fire-sale loss (in dollars, positive),
+    /// which determines `lambda0` given `q`.
+    pub expected_fire_sale_loss: f64,
+}
+
+/// Calibrated liquidity parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LiquidityCalibration {
+    /// Probability of liquidity event (scaled by the
+    /// total portfolio loss).
+    pub q: f64,
+    /// Base loss in a liquidity event.
+    pub lambda0: f64,
+    /// Sum of `lambda0` and the portfolio's lambda, for
+    /// [get_liquidity_risk_fn](../fn.get_liquidity_risk_fn.html).
+    pub lambda: f64,
+    /// Expectation of the portfolio with liquidity
+    /// risk.
+    pub expectation: f64,
+    /// Variance of the portfolio with liquidity risk.
+    pub variance: f64,
+}
+
+/// Returns the liquidity parameters meeting the
+/// targets.
+///
+/// # Arguments
+///
+/// * `target` - The liquidity event and fire-sale
+///   targets.
+/// * `loan_lambda` - The lambda of the portfolio's
+///   loans (the `lambda` field of
+///   [EconomicCapitalAttributes](../struct.EconomicCapitalAttributes.html)).
+/// * `expectation` - Expectation of the portfolio
+///   without liquidity risk (negative).
+/// * `variance` - Variance of the portfolio without
+///   liquidity risk.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let target: loan_ec::calibration::LiquidityTarget = serde_json::from_str(r#"{
+///     "event": {"type": "expected_loss", "probability": 0.1},
+///     "expected_fire_sale_loss": 20.0
+/// }"#).unwrap();
+/// let expectation = -500.0;
+/// let liquidity = loan_ec::calibration::get_liquidity_calibration(
+///     &target, 1.0, expectation, 2000.0
+/// ).unwrap();
+/// assert!((liquidity.expectation - expectation + 20.0).abs() < 0.0000001);
+/// # }
+/// ```
+pub fn get_liquidity_calibration(
+    target: &LiquidityTarget,
+    loan_lambda: f64,
+    expectation: f64,
+    variance: f64,
+) -> Result<LiquidityCalibration, EconomicCapitalError> {
+    let expected_loss = -expectation;
+    if expected_loss <= 0.0 || variance <= 0.0 {
+        return Err(EconomicCapitalError::new(
+            "Portfolio must have a positive expected loss and variance.",
+        ));
+    }
+    let (probability, loss) = match target.event {
+        LiquidityEventTarget::ExpectedLoss { probability } => (probability, expected_loss),
+        LiquidityEventTarget::Quantile { probability, alpha } => {
+            if alpha <= 0.0 || alpha >= 1.0 {
+                return Err(EconomicCapitalError::new("Alpha must be between 0 and 1."));
+            }
+            let scale = variance / expected_loss;
+            (
+                probability,
+                gamma_inv(1.0 - alpha, expected_loss / scale, scale),
+            )
+        }
+        LiquidityEventTarget::Loss { probability, loss } => (probability, loss),
+    };
+    if probability <= 0.0 || probability >= 1.0 || loss <= 0.0 {
+        return Err(EconomicCapitalError::new(
+            "Liquidity event probability must be between 0 and 1 at a positive loss.",
+        ));
+    }
+    let q = -(1.0 - probability).ln() / loss;
+    let lambda = target.expected_fire_sale_loss / (q * expected_loss);
+    let lambda0 = lambda - loan_lambda;
+    if lambda0 < 0.0 {
+        return Err(EconomicCapitalError::new(
+            "Expected fire-sale loss is less than the loans' own liquidity loss.",
+        ));
+    }
+    Ok(LiquidityCalibration {
+        q,
+        lambda0,
+        lambda,
+        expectation: expectation_liquidity(lambda, q, expectation),
+        variance: variance_liquidity(lambda, q, expectation, variance),
+    })
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the liquidity parameters meeting the
+    /// targets for this portfolio.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The liquidity event and fire-sale
+    ///   targets.
+    /// * `el_sys` - The vector of expectations for the
+    ///   systemic random variables.
+    /// * `var_sys` - The vector of variances for the
+    ///   systemic random variables.
+    pub fn get_liquidity_calibration(
+        &self,
+        target: &LiquidityTarget,
+        el_sys: &[f64],
+        var_sys: &[f64],
+    ) -> Result<LiquidityCalibration, EconomicCapitalError> {
+        get_liquidity_calibration(
+            target,
+            self.lambda,
+            self.get_portfolio_expectation(el_sys),
+            self.get_portfolio_variance(el_sys, var_sys),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_gamma_series(pd: f64, variance: f64, num_periods: usize) -> DefaultRateSeries {
+        DefaultRateSeries {
+            default_rates: (0..num_periods)
+                .map(|t| {
+                    let p = (t as f64 + 0.5) / num_periods as f64;
+                    pd * gamma_inv(p, 1.0 / variance, variance)
+                })
+                .collect(),
+            num_obligors: None,
+        }
+    }
+    #[test]
+    fn moments_and_likelihood_recover_variance() {
+        let series = get_gamma_series(0.02, 0.3, 200);
+        let moments = get_moment_variance(&series).unwrap();
+        let likelihood = get_maximum_likelihood_variance(&series).unwrap();
+        assert_abs_diff_eq!(moments, 0.3, epsilon = 0.03);
+        assert_abs_diff_eq!(likelihood, 0.3, epsilon = 0.03);
+        let counts = DefaultRateSeries {
+            num_obligors: Some(1000000.0),
+            ..series
+        };
+        assert_abs_diff_eq!(
+            get_maximum_likelihood_variance(&counts).unwrap(),
+            likelihood,
+            epsilon = 0.01
+        );
+        assert!(get_moment_variance(&counts).unwrap() < moments);
+    }
+    #[test]
+    fn likelihood_needs_positive_rates_without_counts() {
+        let series = DefaultRateSeries {
+            default_rates: vec![0.01, 0.0, 0.02],
+            num_obligors: None,
+        };
+        assert!(get_maximum_likelihood_variance(&series).is_err());
+        let counts = DefaultRateSeries {
+            num_obligors: Some(1000.0),
+            ..series
+        };
+        assert!(get_maximum_likelihood_variance(&counts).unwrap() > 0.0);
+    }
+    #[test]
+    fn segment_calibration_has_one_variable_per_segment() {
+        let series = vec![
+            get_gamma_series(0.02, 0.3, 50),
+            get_gamma_series(0.05, 0.5, 50),
+        ];
+        let calibration = get_segment_calibration(&series, VarianceMethod::Moments).unwrap();
+        assert_eq!(calibration.weights, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+        assert_eq!(calibration.expectation, vec![1.0, 1.0]);
+        assert_abs_diff_eq!(calibration.pd[1], 0.05, epsilon = 0.001);
+        assert!(calibration.variance[1] > calibration.variance[0]);
+    }
+    #[test]
+    fn common_factor_reproduces_covariance() {
+        //orthogonal zero mean (Walsh) sequences
+        let common = [1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        let specific = [
+            [1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0],
+            [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0],
+            [1.0, 1.0, -1.0, -1.0, -1.0, -1.0, 1.0, 1.0],
+        ];
+        let common_weight = [0.6, 0.4, 0.5];
+        let pd = [0.01, 0.02, 0.03];
+        let series: Vec<DefaultRateSeries> = (0..3)
+            .map(|s| DefaultRateSeries {
+                default_rates: common
+                    .iter()
+                    .zip(&specific[s])
+                    .map(|(c, e)| {
+                        pd[s]
+                            * (1.0
+                                + common_weight[s] * 0.5 * c
+                                + (1.0 - common_weight[s]) * 0.3 * e)
+                    })
+                    .collect(),
+                num_obligors: None,
+            })
+            .collect();
+        let calibration = get_common_factor_calibration(&series).unwrap();
+        let realizations: Vec<Vec<f64>> = series
+            .iter()
+            .map(|s| s.get_systemic_realizations().unwrap())
+            .collect();
+        (0..3).for_each(|s| {
+            assert_abs_diff_eq!(
+                calibration.weights[s].iter().sum::<f64>(),
+                1.0,
+                epsilon = 0.0000001
+            );
+            (0..3).for_each(|r| {
+                //covariance implied by the calibration
+                let implied = calibration.weights[s]
+                    .iter()
+                    .zip(&calibration.weights[r])
+                    .zip(&calibration.variance)
+                    .map(|((ws, wr), v)| ws * wr * v)
+                    .sum::<f64>();
+                assert_abs_diff_eq!(
+                    implied,
+                    get_covariance(&realizations[s], &realizations[r]),
+                    epsilon = 0.0001
+                );
+            });
+        });
+    }
+    #[test]
+    fn liquidity_targets_are_met() {
+        let target = LiquidityTarget {
+            event: LiquidityEventTarget::ExpectedLoss { probability: 0.2 },
+            expected_fire_sale_loss: 30.0,
+        };
+        let liquidity = get_liquidity_calibration(&target, 2.0, -500.0, 2500.0).unwrap();
+        assert_abs_diff_eq!(1.0 - (-liquidity.q * 500.0).exp(), 0.2, epsilon = 0.0000001);
+        assert_abs_diff_eq!(
+            liquidity.lambda0 + 2.0,
+            liquidity.lambda,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(liquidity.expectation, -530.0, epsilon = 0.0000001);
+        assert!(liquidity.variance > 2500.0);
+        let stressed = get_liquidity_calibration(
+            &LiquidityTarget {
+                event: LiquidityEventTarget::Quantile {
+                    probability: 0.2,
+                    alpha: 0.01,
+                },
+                ..target
+            },
+            2.0,
+            -500.0,
+            2500.0,
+        )
+        .unwrap();
+        assert!(stressed.q < liquidity.q);
+        assert_abs_diff_eq!(stressed.expectation, -530.0, epsilon = 0.0000001);
+        assert!(get_liquidity_calibration(
+            &LiquidityTarget {
+                expected_fire_sale_loss: 0.001,
+                ..target
+            },
+            2.0,
+            -500.0,
+            2500.0
+        )
+        .is_err());
+    }
+    #[test]
+    fn calibrated_liquidity_matches_cf_expectation() {
+        let num_u = 256;
+        let x_min = -3000.0;
+        let x_max = 0.0;
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let loan = Loan {
+            pd: 0.05,
+            lgd: 0.5,
+            balance: 1.0,
+            r: 0.01,
+            lgd_variance: 0.0,
+            weight: vec![0.4, 0.6],
+            num: 1000.0,
+        };
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let mut ec = EconomicCapitalAttributes::new(num_u, 2);
+        ec.process_loan(&loan, &u_domain, |u, loan| {
+            (exponential_lgd_cf(u, loan.lgd * loan.balance, loan.lgd_variance) - 1.0) * loan.pd
+        });
+        let target: LiquidityTarget = serde_json::from_str(
+            r#"{
+                "event": {"type": "loss", "probability": 0.5, "loss": 50.0},
+                "expected_fire_sale_loss": 10.0
+            }"#,
+        )
+        .unwrap();
+        let liquidity = ec.get_liquidity_calibration(&target, &el_sys, &v).unwrap();
+        let liquid_fn = get_liquidity_risk_fn(liquidity.lambda, liquidity.q);
+        let mut ec_liquid = EconomicCapitalAttributes::new(num_u, 2);
+        ec_liquid.process_loan(
+            &loan,
+            &u_domain,
+            get_log_lpm_cf(exponential_lgd_cf, liquid_fn),
+        );
+        let cf = ec_liquid.get_full_cf(&gamma_mgf(&v));
+        assert_abs_diff_eq!(
+            cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &cf),
+            liquidity.expectation,
+            epsilon = 0.01
+        );
+        assert_abs_diff_eq!(liquidity.expectation, -35.0, epsilon = 0.0000001);
+    }
+}
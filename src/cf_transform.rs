@@ -0,0 +1,221 @@
+//! Transformations and evaluations of a discrete
+//! characteristic function.  The density is recovered
+//! with the Fang-Oosterlee (COS) expansion on the
+//! truncated domain and integrated analytically.
+
+use crate::get_liquidity_risk_fn;
+use num_complex::Complex;
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+/// Returns the integral of exp(z*y) over \[0, length\].
+fn integrate_exp(z: Complex<f64>, length: f64) -> Complex<f64> {
+    if z.norm() < 1e-12 {
+        Complex::new(length, 0.0)
+    } else {
+        ((z * length).exp() - 1.0) / z
+    }
+}
+
+/// Returns the cosine expansion coefficients of the
+/// density underlying a discrete characteristic
+/// function.  The first coefficient is already halved.
+/// The coefficients are linear in the characteristic
+/// function, so passing the derivative of the
+/// characteristic function gives the derivative of
+/// the coefficients.
+pub fn get_density_coefficients(discrete_cf: &[Complex<f64>], x_min: f64, x_max: f64) -> Vec<f64> {
+    let du = PI / (x_max - x_min);
+    discrete_cf
+        .iter()
+        .enumerate()
+        .map(|(index, cf)| {
+            let u = Complex::new(0.0, du * index as f64);
+            let coefficient = 2.0 / (x_max - x_min) * (cf * (-u * x_min).exp()).re;
+            if index == 0 {
+                0.5 * coefficient
+            } else {
+                coefficient
+            }
+        })
+        .collect()
+}
+
+/// Returns E\[exp(g(u)X)\] for each u in the domain of a
+/// discrete characteristic function, where X is the
+/// random variable described by that characteristic
+/// function on \[x_min, x_max\].
+///
+/// # Arguments
+///
+/// * `discrete_cf` - The discrete characteristic function.
+/// * `x_min` - Lower truncation of the domain.
+/// * `x_max` - Upper truncation of the domain.
+/// * `transform` - Function g applied to each u.
+pub fn get_transformed_cf<T>(
+    discrete_cf: &[Complex<f64>],
+    x_min: f64,
+    x_max: f64,
+    transform: T,
+) -> Vec<Complex<f64>>
+where
+    T: Fn(&Complex<f64>) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+{
+    let du = PI / (x_max - x_min);
+    let length = x_max - x_min;
+    let coefficients = get_density_coefficients(discrete_cf, x_min, x_max);
+    (0..discrete_cf.len())
+        .into_par_iter()
+        .map(|index| {
+            let z = transform(&Complex::new(0.0, du * index as f64));
+            (z * x_min).exp()
+                * coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, coefficient)| {
+                        let omega = Complex::new(0.0, du * k as f64);
+                        (integrate_exp(z + omega, length) + integrate_exp(z - omega, length))
+                            * 0.5
+                            * coefficient
+                    })
+                    .sum::<Complex<f64>>()
+        })
+        .collect()
+}
+
+/// Returns the discrete characteristic function of
+/// the loss including liquidity risk, with the loss
+/// multiplied by `scale`.  The characteristic function
+/// passed in must be of a portfolio processed without
+/// liquidity risk, ie with
+/// [get_liquidity_risk_fn(0.0, 0.0)](../fn.get_liquidity_risk_fn.html).
+///
+/// # Arguments
+///
+/// * `discrete_cf` - The discrete characteristic function.
+/// * `x_min` - Lower truncation of the domain.
+/// * `x_max` - Upper truncation of the domain.
+/// * `lambda` - Sum of lambda0 and the lambda of the
+///   portfolio.
+/// * `q` - Probability of liquidity event.
+/// * `scale` - The multiplier for the loss.
+pub fn get_liquidity_cf(
+    discrete_cf: &[Complex<f64>],
+    x_min: f64,
+    x_max: f64,
+    lambda: f64,
+    q: f64,
+    scale: f64,
+) -> Vec<Complex<f64>> {
+    if q == 0.0 && scale == 1.0 {
+        return discrete_cf.to_vec();
+    }
+    let liquid_fn = get_liquidity_risk_fn(lambda, q);
+    get_transformed_cf(discrete_cf, x_min, x_max, move |u| liquid_fn(u) * scale)
+}
+
+/// Returns the density at x.
+pub fn get_density(coefficients: &[f64], x_min: f64, x_max: f64, x: f64) -> f64 {
+    let du = PI / (x_max - x_min);
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(index, coefficient)| coefficient * (du * index as f64 * (x - x_min)).cos())
+        .sum()
+}
+
+/// Returns the cumulative distribution function at x.
+pub fn get_cdf(coefficients: &[f64], x_min: f64, x_max: f64, x: f64) -> f64 {
+    let du = PI / (x_max - x_min);
+    let d = x - x_min;
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(index, coefficient)| {
+            if index == 0 {
+                coefficient * d
+            } else {
+                let omega = du * index as f64;
+                coefficient * (omega * d).sin() / omega
+            }
+        })
+        .sum()
+}
+
+/// Returns the partial expectation E\[X 1_{X<x}\].
+pub fn get_partial_expectation(coefficients: &[f64], x_min: f64, x_max: f64, x: f64) -> f64 {
+    let du = PI / (x_max - x_min);
+    let d = x - x_min;
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(index, coefficient)| {
+            if index == 0 {
+                coefficient * (x_min * d + 0.5 * d * d)
+            } else {
+                let omega = du * index as f64;
+                coefficient
+                    * ((x_min + d) * (omega * d).sin() / omega
+                        + ((omega * d).cos() - 1.0) / omega.powi(2))
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    #[test]
+    fn identity_transform_recovers_cf() {
+        let mu = -5.0;
+        let sigma = 2.0;
+        let x_min = -25.0;
+        let x_max = 15.0;
+        let norm_cf = |u: &Complex<f64>| (u * mu + 0.5 * sigma * sigma * u * u).exp();
+        let discrete_cf = fang_oost::get_discrete_cf(128, x_min, x_max, norm_cf);
+        let transformed = get_transformed_cf(&discrete_cf, x_min, x_max, |u| *u);
+        discrete_cf.iter().zip(&transformed).for_each(|(cf, t)| {
+            assert_abs_diff_eq!(cf.re, t.re, epsilon = 0.00001);
+            assert_abs_diff_eq!(cf.im, t.im, epsilon = 0.00001);
+        });
+    }
+    #[test]
+    fn scaled_cf_matches_scaled_normal() {
+        let mu = -5.0;
+        let sigma = 2.0;
+        let scale = 1.5;
+        let x_min = -30.0;
+        let x_max = 10.0;
+        let norm_cf = |u: &Complex<f64>| (u * mu + 0.5 * sigma * sigma * u * u).exp();
+        let scaled_norm_cf =
+            |u: &Complex<f64>| (u * mu * scale + 0.5 * (sigma * scale).powi(2) * u * u).exp();
+        let discrete_cf = fang_oost::get_discrete_cf(128, x_min, x_max, norm_cf);
+        let expected = fang_oost::get_discrete_cf(128, x_min, x_max, scaled_norm_cf);
+        let scaled = get_liquidity_cf(&discrete_cf, x_min, x_max, 0.0, 0.0, scale);
+        expected.iter().zip(&scaled).for_each(|(e, s)| {
+            assert_abs_diff_eq!(e.re, s.re, epsilon = 0.00001);
+            assert_abs_diff_eq!(e.im, s.im, epsilon = 0.00001);
+        });
+    }
+    #[test]
+    fn density_matches_normal() {
+        let mu = -5.0;
+        let sigma = 2.0;
+        let x_min = -25.0;
+        let x_max = 15.0;
+        let norm_cf = |u: &Complex<f64>| (u * mu + 0.5 * sigma * sigma * u * u).exp();
+        let discrete_cf = fang_oost::get_discrete_cf(128, x_min, x_max, norm_cf);
+        let coefficients = get_density_coefficients(&discrete_cf, x_min, x_max);
+        assert_abs_diff_eq!(
+            get_density(&coefficients, x_min, x_max, mu),
+            1.0 / (sigma * (2.0 * PI).sqrt()),
+            epsilon = 0.00001
+        );
+        assert_abs_diff_eq!(
+            get_cdf(&coefficients, x_min, x_max, mu),
+            0.5,
+            epsilon = 0.00001
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! Credit risk mitigation for secured and guaranteed loans.
+//! Collateral reduces the loss given default while guarantees
+//! make the loss on the covered exposure conditional on the
+//! guarantor also defaulting.  A guaranteed loan is a single
+//! obligor whose loss given default is a mixture: the covered
+//! exposure is lost with the probability that the guarantor
+//! defaults given that the obligor defaults.
+
+use crate::distributions::normal_cdf;
+use crate::{
+    default_zero, get_el_from_loan, get_lambda_from_loan, get_var_from_loan,
+    EconomicCapitalAttributes, Loan,
+};
+use num_complex::Complex;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+
+/// Struct representing the collateral securing
+/// a single loan.  The collateral value at
+/// default is lognormally distributed with
+/// expectation `value*(1-haircut)`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Collateral {
+    /// Market value of the collateral (in dollars)
+    /// backing a single loan.
+    pub value: f64,
+    /// Volatility of the collateral value over
+    /// the horizon.
+    pub volatility: f64,
+    #[serde(default = "default_zero")]
+    /// Haircut applied to the collateral value
+    /// when liquidating, as a fraction of the
+    /// value.
+    pub haircut: f64,
+}
+
+/// Struct representing a guarantee on a loan.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Guarantee {
+    /// Annualized probability of default of
+    /// the guarantor.
+    pub pd: f64,
+    /// Fraction of the balance covered by
+    /// the guarantee.
+    pub coverage: f64,
+    #[serde(default = "default_zero")]
+    /// Default correlation between the obligor
+    /// and the guarantor.
+    pub correlation: f64,
+}
+
+/// Struct representing a loan with optional
+/// collateral and guarantee.  The `lgd` and
+/// `lgd_variance` of the underlying loan describe
+/// the loss on the exposure left after the
+/// collateral is liquidated.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecuredLoan {
+    #[serde(flatten)]
+    /// The underlying loan.
+    pub loan: Loan,
+    #[serde(default)]
+    /// Collateral for each loan.
+    pub collateral: Option<Collateral>,
+    #[serde(default)]
+    /// Guarantee for each loan.
+    pub guarantee: Option<Guarantee>,
+}
+
+/// Returns the first and second moments of
+/// the uncollateralized exposure max(balance-C, 0)
+/// where C is the lognormal liquidation value
+/// of the collateral.
+///
+/// # Arguments
+///
+/// * `balance` - Balance for the loan (in dollars).
+/// * `collateral` - An instance of the
+///   [Collateral](struct.Collateral.html) struct.
+fn get_uncollateralized_moments(balance: f64, collateral: &Collateral) -> (f64, f64) {
+    let forward = collateral.value * (1.0 - collateral.haircut);
+    let sigma = collateral.volatility;
+    if forward <= 0.0 {
+        return (balance, balance.powi(2));
+    }
+    if sigma <= 0.0 {
+        let exposure = (balance - forward).max(0.0);
+        return (exposure, exposure.powi(2));
+    }
+    if balance <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let d1 = ((forward / balance).ln() + 0.5 * sigma.powi(2)) / sigma;
+    let d2 = d1 - sigma;
+    let first = balance * normal_cdf(-d2) - forward * normal_cdf(-d1);
+    let second = balance.powi(2) * normal_cdf(-d2) - 2.0 * balance * forward * normal_cdf(-d1)
+        + forward.powi(2) * sigma.powi(2).exp() * normal_cdf(-d1 - sigma);
+    (first, second)
+}
+
+/// Returns the expected loss given default and the
+/// variance of the loss given default (as used by the
+/// [Loan](../struct.Loan.html) struct) after
+/// recognizing collateral.
+///
+/// # Arguments
+///
+/// * `loan` - An instance of the [Loan](../struct.Loan.html) struct.
+/// * `collateral` - An instance of the
+///   [Collateral](struct.Collateral.html) struct.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let loan=loan_ec::Loan{
+///     pd: 0.05,
+///     lgd: 0.5,
+///     lgd_variance: 0.2,
+///     balance: 1000.0,
+///     r: 0.0,
+///     weight: vec![1.0],
+///     num: 100.0
+/// };
+/// let collateral=loan_ec::collateral::Collateral{
+///     value: 600.0,
+///     volatility: 0.3,
+///     haircut: 0.1
+/// };
+/// let (lgd, lgd_variance)=loan_ec::collateral::get_secured_lgd(&loan, &collateral);
+/// # }
+/// ```
+pub fn get_secured_lgd(loan: &Loan, collateral: &Collateral) -> (f64, f64) {
+    let (first, second) = get_uncollateralized_moments(loan.balance, collateral);
+    if first <= 0.0 || loan.balance <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let lgd = loan.lgd * first / loan.balance;
+    let lgd_variance = (1.0 + loan.lgd_variance) * second / first.powi(2) - 1.0;
+    (lgd, lgd_variance.max(0.0))
+}
+
+/// Returns the probability that both the obligor
+/// and the guarantor default.
+///
+/// # Arguments
+///
+/// * `pd` - Probability of default of the obligor.
+/// * `guarantee` - An instance of the
+///   [Guarantee](struct.Guarantee.html) struct.
+pub fn get_double_default_pd(pd: f64, guarantee: &Guarantee) -> f64 {
+    let pd_g = guarantee.pd;
+    let joint = pd * pd_g + guarantee.correlation * (pd * (1.0 - pd) * pd_g * (1.0 - pd_g)).sqrt();
+    joint.max(0.0).min(pd.min(pd_g))
+}
+
+impl SecuredLoan {
+    /// Returns the loan with the collateral reflected
+    /// in its `lgd` and `lgd_variance`.
+    pub fn get_secured_loan(&self) -> Loan {
+        let (lgd, lgd_variance) = match &self.collateral {
+            Some(collateral) => get_secured_lgd(&self.loan, collateral),
+            None => (self.loan.lgd, self.loan.lgd_variance),
+        };
+        Loan {
+            lgd,
+            lgd_variance,
+            ..self.loan.clone()
+        }
+    }
+    /// Returns the fraction of the balance covered by
+    /// the guarantee and the probability that the
+    /// guarantor defaults given that the obligor
+    /// defaults.
+    fn get_guarantee_mixture(&self) -> (f64, f64) {
+        match &self.guarantee {
+            Some(guarantee) if self.loan.pd > 0.0 => (
+                guarantee.coverage.clamp(0.0, 1.0),
+                get_double_default_pd(self.loan.pd, guarantee) / self.loan.pd,
+            ),
+            _ => (0.0, 1.0),
+        }
+    }
+}
+
+impl EconomicCapitalAttributes {
+    /// Adds a new secured loan to the portfolio.  The
+    /// obligor defaults once, with the probability of
+    /// default of the loan.  Given default, the
+    /// uncovered exposure is lost and the covered
+    /// exposure is lost only if the guarantor also
+    /// defaults, so the characteristic exponent is the
+    /// mixture of `log_lpm_cf` over the two outcomes.
+    /// This requires `log_lpm_cf` to be linear in the
+    /// characteristic function of the loss given
+    /// default, as is the result from
+    /// [get_log_lpm_cf](../fn.get_log_lpm_cf.html).
+    ///
+    /// # Arguments
+    ///
+    /// * `secured_loan` - An instance of the
+    ///   [SecuredLoan](struct.SecuredLoan.html) struct.
+    /// * `u_domain` - The vector of complex values
+    ///   provided to the characteristic function.
+    /// * `log_lpm_cf` - The result from calling
+    ///   [get_log_lpm_cf](../fn.get_log_lpm_cf.html).
+    pub fn process_secured_loan<U>(
+        &mut self,
+        secured_loan: &SecuredLoan,
+        u_domain: &[Complex<f64>],
+        log_lpm_cf: U,
+    ) where
+        U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        let loan = secured_loan.get_secured_loan();
+        let (coverage, guarantor_default) = secured_loan.get_guarantee_mixture();
+        let uncovered = Loan {
+            balance: loan.balance * (1.0 - coverage),
+            ..loan.clone()
+        };
+        let vec_of_cf_u: Vec<Complex<f64>> = u_domain
+            .par_iter()
+            .map(|u| {
+                log_lpm_cf(u, &uncovered) * (1.0 - guarantor_default)
+                    + log_lpm_cf(u, &loan) * guarantor_default
+            })
+            .collect();
+        self.add_cf_increment(&vec_of_cf_u, &loan.weight, loan.num);
+        self.el_vec
+            .iter_mut()
+            .zip(&loan.weight)
+            .for_each(|(el, &w)| {
+                *el += get_el_from_loan(&uncovered, w) * (1.0 - guarantor_default)
+                    + get_el_from_loan(&loan, w) * guarantor_default;
+            });
+        self.var_vec
+            .iter_mut()
+            .zip(&loan.weight)
+            .for_each(|(var, &w)| {
+                *var += get_var_from_loan(&uncovered, w) * (1.0 - guarantor_default)
+                    + get_var_from_loan(&loan, w) * guarantor_default;
+            });
+        self.lambda += get_lambda_from_loan(&loan);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_test_log_lpm_cf, get_test_u_domain};
+    use approx::*;
+    fn get_test_loan() -> Loan {
+        Loan {
+            pd: 0.05,
+            lgd: 0.5,
+            balance: 1000.0,
+            r: 0.0,
+            lgd_variance: 0.2,
+            weight: vec![1.0],
+            num: 100.0,
+        }
+    }
+    #[test]
+    fn deterministic_collateral_reduces_lgd() {
+        let loan = get_test_loan();
+        let collateral = Collateral {
+            value: 500.0,
+            volatility: 0.0,
+            haircut: 0.2,
+        };
+        let (lgd, lgd_variance) = get_secured_lgd(&loan, &collateral);
+        assert_abs_diff_eq!(lgd, 0.5 * 600.0 / 1000.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(lgd_variance, 0.2, epsilon = 0.0000001);
+    }
+    #[test]
+    fn stochastic_collateral_matches_numerical_integration() {
+        let balance = 1000.0;
+        let collateral = Collateral {
+            value: 800.0,
+            volatility: 0.4,
+            haircut: 0.1,
+        };
+        let forward: f64 = 720.0;
+        let sigma: f64 = 0.4;
+        let num_z = 20000;
+        let z_min = -8.0;
+        let dz = 16.0 / (num_z as f64);
+        let (first, second) = (0..num_z).fold((0.0, 0.0), |(f, s), index| {
+            let z = z_min + (index as f64 + 0.5) * dz;
+            let density = (-0.5 * z * z).exp() / (2.0 * std::f64::consts::PI).sqrt();
+            let c = forward * (sigma * z - 0.5 * sigma * sigma).exp();
+            let exposure = (balance - c).max(0.0);
+            (
+                f + exposure * density * dz,
+                s + exposure.powi(2) * density * dz,
+            )
+        });
+        let (first_analytic, second_analytic) = get_uncollateralized_moments(balance, &collateral);
+        assert_abs_diff_eq!(first_analytic, first, epsilon = 0.01);
+        assert_abs_diff_eq!(second_analytic, second, epsilon = 10.0);
+    }
+    #[test]
+    fn fully_covered_collateral_has_no_loss() {
+        let loan = get_test_loan();
+        let collateral = Collateral {
+            value: 2000.0,
+            volatility: 0.0,
+            haircut: 0.0,
+        };
+        let (lgd, lgd_variance) = get_secured_lgd(&loan, &collateral);
+        assert_eq!(lgd, 0.0);
+        assert_eq!(lgd_variance, 0.0);
+    }
+    #[test]
+    fn double_default_pd_is_product_when_independent() {
+        let guarantee = Guarantee {
+            pd: 0.01,
+            coverage: 0.5,
+            correlation: 0.0,
+        };
+        assert_abs_diff_eq!(
+            get_double_default_pd(0.05, &guarantee),
+            0.0005,
+            epsilon = 0.0000001
+        );
+        let perfect = Guarantee {
+            correlation: 1.0,
+            ..guarantee
+        };
+        assert_abs_diff_eq!(
+            get_double_default_pd(0.05, &perfect),
+            0.01,
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn guaranteed_loan_mixes_loss_given_default() {
+        let secured_loan = SecuredLoan {
+            loan: Loan {
+                lgd_variance: 0.0,
+                ..get_test_loan()
+            },
+            collateral: None,
+            guarantee: Some(Guarantee {
+                pd: 0.01,
+                coverage: 0.6,
+                correlation: 0.0,
+            }),
+        };
+        let num_u: usize = 1024;
+        let x_min = -10000.0;
+        let x_max = 0.0;
+        let mut ec = EconomicCapitalAttributes::new(num_u, 1);
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        ec.process_secured_loan(&secured_loan, &u_domain, &log_lpm_cf);
+        let expected = -(0.05 * 0.4 + 0.0005 * 0.6) * 0.5 * 1000.0 * 100.0;
+        assert_abs_diff_eq!(
+            ec.get_portfolio_expectation(&[1.0]),
+            expected,
+            epsilon = 0.0000001
+        );
+        let final_cf = ec.get_full_cf(&|u: &[Complex<f64>]| u[0].exp());
+        let expectation_approx =
+            cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &final_cf);
+        assert_abs_diff_eq!(expectation_approx, expected, epsilon = 0.1);
+        //a single default loses the uncovered exposure, and the
+        //covered exposure if the guarantor also defaults
+        let guarantor_default = 0.0005 / 0.05;
+        let expected_variance = 0.05
+            * (0.5_f64 * 1000.0).powi(2)
+            * ((1.0 - guarantor_default) * 0.4_f64.powi(2) + guarantor_default)
+            * 100.0;
+        assert_abs_diff_eq!(
+            ec.get_portfolio_variance(&[1.0], &[0.0]),
+            expected_variance,
+            epsilon = 0.0000001
+        );
+        let variance_approx = cf_dist_utils::get_variance_discrete_cf(x_min, x_max, &final_cf);
+        assert_relative_eq!(variance_approx, expected_variance, max_relative = 0.001);
+    }
+}
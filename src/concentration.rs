@@ -0,0 +1,309 @@
+//! Name concentration diagnostics: Herfindahl-Hirschman
+//! indices of exposure and the granularity adjustment.
+//!
+//! Conditional on a single Gamma systemic variable X with
+//! mean one and variance v, the portfolio loss has mean
+//! a*X and variance b*X, where a=-sum(el_vec) and
+//! b=sum(var_vec).  The granularity adjustment
+//! -1/(2h(x))d/dx\[h(x)b x/a\] at the quantile x of X
+//! simplifies to b/(2a)*(x-1)/v.
+
+use crate::distributions::gamma_inv;
+use crate::obligor::Obligor;
+use crate::{EconomicCapitalAttributes, Loan};
+
+/// Decomposition of the value at risk into the
+/// systemic (infinitely granular) part and the part
+/// due to name concentration.
+#[derive(Debug, Clone)]
+pub struct ConcentrationReport {
+    /// Value at risk from the characteristic function.
+    pub value_at_risk: f64,
+    /// Value at risk of an infinitely granular
+    /// portfolio with the same expected loss.
+    pub systemic_value_at_risk: f64,
+    /// Analytic granularity adjustment.
+    pub granularity_adjustment: f64,
+    /// Value at risk in excess of the systemic value
+    /// at risk, attributable to name concentration.
+    pub name_concentration: f64,
+}
+
+/// Returns the Herfindahl-Hirschman index
+/// sum(e_i^2)/sum(e_i)^2 of a set of exposures.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let hhi = loan_ec::concentration::get_herfindahl_index(&[1.0, 1.0, 1.0, 1.0]);
+/// assert_eq!(hhi, 0.25);
+/// # }
+/// ```
+pub fn get_herfindahl_index(exposures: &[f64]) -> f64 {
+    let total: f64 = exposures.iter().sum();
+    exposures.iter().map(|e| e.powi(2)).sum::<f64>() / total.powi(2)
+}
+
+/// Returns the Herfindahl-Hirschman index of the
+/// loan balances.  Each loan counts as `num` loans
+/// with the same balance.
+pub fn get_loan_herfindahl_index(loans: &[Loan]) -> f64 {
+    let total: f64 = loans.iter().map(|loan| loan.balance * loan.num).sum();
+    loans
+        .iter()
+        .map(|loan| loan.balance.powi(2) * loan.num)
+        .sum::<f64>()
+        / total.powi(2)
+}
+
+/// Returns the Herfindahl-Hirschman index of the
+/// obligor exposures (the sum of the facility
+/// balances).  Each obligor counts as `num`
+/// obligors.
+pub fn get_obligor_herfindahl_index(obligors: &[Obligor]) -> f64 {
+    let exposure = |obligor: &Obligor| -> f64 {
+        obligor
+            .facilities
+            .iter()
+            .map(|facility| facility.balance)
+            .sum()
+    };
+    let total: f64 = obligors
+        .iter()
+        .map(|obligor| exposure(obligor) * obligor.num)
+        .sum();
+    obligors
+        .iter()
+        .map(|obligor| exposure(obligor).powi(2) * obligor.num)
+        .sum::<f64>()
+        / total.powi(2)
+}
+
+/// Returns the Herfindahl-Hirschman index of the
+/// exposure to each systemic variable, where the
+/// exposure of a loan is split by its weights.
+///
+/// # Arguments
+///
+/// * `loans` - The loans in the portfolio.
+/// * `num_w` - The number of systemic variables.
+pub fn get_factor_herfindahl_index(loans: &[Loan], num_w: usize) -> f64 {
+    let exposures = loans.iter().fold(vec![0.0; num_w], |mut exposures, loan| {
+        exposures
+            .iter_mut()
+            .zip(&loan.weight)
+            .for_each(|(exposure, w)| *exposure += w * loan.balance * loan.num);
+        exposures
+    });
+    get_herfindahl_index(&exposures)
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the value at risk of an infinitely
+    /// granular portfolio with a single Gamma
+    /// systemic variable (mean one, variance
+    /// `variance`) and the same expected loss.
+    /// Portfolios with several systemic variables
+    /// are collapsed onto the single variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `variance` - Variance of the systemic variable.
+    /// * `alpha` - Tail probability (eg, 0.01).
+    pub fn get_systemic_value_at_risk(&self, variance: f64, alpha: f64) -> f64 {
+        let expected_loss = -self.el_vec.iter().sum::<f64>();
+        expected_loss * gamma_inv(1.0 - alpha, 1.0 / variance, variance)
+    }
+    /// Returns the granularity adjustment to the
+    /// value at risk for a single Gamma systemic
+    /// variable.  Liquidity risk is not included.
+    ///
+    /// # Arguments
+    ///
+    /// * `variance` - Variance of the systemic variable.
+    /// * `alpha` - Tail probability (eg, 0.01).
+    pub fn get_granularity_adjustment(&self, variance: f64, alpha: f64) -> f64 {
+        let expected_loss = -self.el_vec.iter().sum::<f64>();
+        let conditional_variance: f64 = self.var_vec.iter().sum();
+        let quantile = gamma_inv(1.0 - alpha, 1.0 / variance, variance);
+        conditional_variance / (2.0 * expected_loss) * (quantile - 1.0) / variance
+    }
+    /// Returns the decomposition of the value at risk
+    /// into systemic risk and name concentration.
+    ///
+    /// # Arguments
+    ///
+    /// * `variance` - Variance of the systemic variable.
+    /// * `alpha` - Tail probability (eg, 0.01).
+    /// * `value_at_risk` - Value at risk computed from
+    ///   the characteristic function (without
+    ///   liquidity risk) at the same `alpha`.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// extern crate cf_dist_utils;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=256;
+    /// let x_min=-500.0;
+    /// let x_max=0.0;
+    /// let v=0.4;
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 1.0,
+    ///     lgd_variance: 0.0,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 500.0
+    /// };
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let cf = ec_attributes.get_full_cf(&|u: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u, &[v]));
+    /// let cf_dist_utils::RiskMetric{value_at_risk, ..} = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+    ///     0.01, x_min, x_max, 100, 0.0001, &cf,
+    /// ).unwrap();
+    /// let report = ec_attributes.get_concentration_report(v, 0.01, value_at_risk);
+    /// assert!(report.name_concentration > 0.0);
+    /// # }
+    /// ```
+    pub fn get_concentration_report(
+        &self,
+        variance: f64,
+        alpha: f64,
+        value_at_risk: f64,
+    ) -> ConcentrationReport {
+        let systemic_value_at_risk = self.get_systemic_value_at_risk(variance, alpha);
+        ConcentrationReport {
+            value_at_risk,
+            systemic_value_at_risk,
+            granularity_adjustment: self.get_granularity_adjustment(variance, alpha),
+            name_concentration: value_at_risk - systemic_value_at_risk,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obligor::Facility;
+    use crate::*;
+    use approx::*;
+    fn get_loan(balance: f64, num: f64, weight: Vec<f64>) -> Loan {
+        Loan {
+            pd: 0.05,
+            lgd: 1.0,
+            balance,
+            r: 0.0,
+            lgd_variance: 0.0,
+            weight,
+            num,
+        }
+    }
+    #[test]
+    fn herfindahl_indices() {
+        let loans = vec![
+            get_loan(2.0, 1.0, vec![1.0, 0.0]),
+            get_loan(1.0, 2.0, vec![0.5, 0.5]),
+        ];
+        //exposures 2, 1, 1
+        assert_abs_diff_eq!(
+            get_loan_herfindahl_index(&loans),
+            6.0 / 16.0,
+            epsilon = 0.0000001
+        );
+        //factor exposures 3 and 1
+        assert_abs_diff_eq!(
+            get_factor_herfindahl_index(&loans, 2),
+            10.0 / 16.0,
+            epsilon = 0.0000001
+        );
+        let obligor = Obligor {
+            pd: 0.05,
+            weight: vec![1.0],
+            num: 2.0,
+            facilities: vec![
+                Facility {
+                    balance: 1.0,
+                    lgd: 0.5,
+                    r: 0.0,
+                    lgd_variance: 0.0,
+                },
+                Facility {
+                    balance: 3.0,
+                    lgd: 0.5,
+                    r: 0.0,
+                    lgd_variance: 0.0,
+                },
+            ],
+        };
+        assert_abs_diff_eq!(
+            get_obligor_herfindahl_index(&[obligor]),
+            0.5,
+            epsilon = 0.0000001
+        );
+    }
+    fn get_value_at_risk(
+        num: f64,
+        balance: f64,
+        v: f64,
+        alpha: f64,
+    ) -> (f64, EconomicCapitalAttributes) {
+        let num_u: usize = 1024;
+        let x_min = -num * balance;
+        let x_max = 0.0;
+        let ec = process_test_loans(
+            exponential_lgd_cf,
+            0.0,
+            0.0,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &[get_loan(balance, num, vec![1.0])],
+        );
+        let cf = ec.get_full_cf(&gamma_mgf(&[v]));
+        let cf_dist_utils::RiskMetric { value_at_risk, .. } =
+            cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+                alpha, x_min, x_max, 100, 0.00001, &cf,
+            )
+            .unwrap();
+        (value_at_risk, ec)
+    }
+    #[test]
+    fn granularity_adjustment_explains_concentration() {
+        let v = 0.4;
+        let alpha = 0.01;
+        let (value_at_risk, ec) = get_value_at_risk(100.0, 10.0, v, alpha);
+        let report = ec.get_concentration_report(v, alpha, value_at_risk);
+        assert!(report.name_concentration > 0.0);
+        assert!(
+            (report.name_concentration - report.granularity_adjustment).abs()
+                < 0.5 * report.name_concentration
+        );
+    }
+    #[test]
+    fn granular_portfolio_has_small_adjustment() {
+        let v = 0.4;
+        let alpha = 0.01;
+        let (_, concentrated) = get_value_at_risk(100.0, 10.0, v, alpha);
+        let (_, granular) = get_value_at_risk(10000.0, 0.1, v, alpha);
+        assert_abs_diff_eq!(
+            concentrated.get_systemic_value_at_risk(v, alpha),
+            granular.get_systemic_value_at_risk(v, alpha),
+            epsilon = 0.0001
+        );
+        assert_abs_diff_eq!(
+            granular.get_granularity_adjustment(v, alpha) * 100.0,
+            concentrated.get_granularity_adjustment(v, alpha),
+            epsilon = 0.0001
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Selection of the number of points of the
+//! characteristic function.  The domain
+//! u_k=i k pi/(x_max-x_min) used by
+//! [fang_oost](https://github.com/phillyfan1138/fang_oost_rust)
+//! has the same spacing for any number of points, so the
+//! characteristic function with fewer points is a prefix
+//! of the one with more.  The loans are processed once
+//! at a high number of points and the risk measures are
+//! computed for successively longer prefixes to find the
+//! smallest number of points which meets a tolerance.
+
+use crate::error::EconomicCapitalError;
+use crate::EconomicCapitalAttributes;
+use num_complex::Complex;
+use serde_derive::Serialize;
+
+/// Risk measures for a given number of points.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ConvergenceStep {
+    /// Number of points of the characteristic
+    /// function.
+    pub num_u: usize,
+    /// Value at risk (positive).
+    pub value_at_risk: f64,
+    /// Expected shortfall (positive).
+    pub expected_shortfall: f64,
+    /// Relative difference of the value at risk from
+    /// the one with the most points.
+    pub value_at_risk_error: f64,
+    /// Relative difference of the expected shortfall
+    /// from the one with the most points.
+    pub expected_shortfall_error: f64,
+}
+
+/// Convergence of the risk measures in the number of
+/// points.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConvergenceReport {
+    /// Risk measures for each number of points, from
+    /// fewest to most.
+    pub steps: Vec<ConvergenceStep>,
+    /// Smallest number of points from which every
+    /// step is within the tolerance.
+    pub recommended_num_u: usize,
+    /// Whether a number of points below the one
+    /// processed meets the tolerance.  If not, the
+    /// loans should be processed with more points.
+    pub converged: bool,
+}
+
+fn get_relative_error(value: f64, reference: f64) -> f64 {
+    if reference == 0.0 {
+        value.abs()
+    } else {
+        ((value - reference) / reference).abs()
+    }
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the value at risk and expected shortfall
+    /// for numbers of points doubling from `min_num_u`
+    /// up to the number of points the portfolio was
+    /// created with, and recommends the smallest number
+    /// of points within a relative tolerance of the
+    /// most accurate risk measures.
+    ///
+    /// # Arguments
+    ///
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables.
+    /// * `x_min` - Lower truncation of the loss
+    ///   distribution, as used for the `u_domain`.
+    /// * `x_max` - Upper truncation of the loss
+    ///   distribution, as used for the `u_domain`.
+    /// * `alpha` - Tail probability of the risk
+    ///   measures.
+    /// * `relative_tolerance` - Largest acceptable
+    ///   relative error of the value at risk and
+    ///   expected shortfall.
+    /// * `min_num_u` - Fewest number of points tried.
+    /// * `tolerance` - Tolerance of the value at risk.
+    /// * `max_iterations` - Maximum root finding steps
+    ///   for the value at risk.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u = 512;
+    /// let x_min = -1000.0;
+    /// let x_max = 0.0;
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// let loan: loan_ec::Loan = serde_json::from_str(r#"{
+    ///     "pd": 0.05, "lgd": 0.5, "balance": 1.0, "r": 0.0,
+    ///     "lgd_variance": 0.0, "weight": [1.0], "num": 1000.0
+    /// }"#).unwrap();
+    /// let mut ec_attributes = loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let v = vec![0.3];
+    /// let systemic_mgf = |u_weights: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u_weights, &v);
+    /// let report = ec_attributes.get_convergence_report(
+    ///     &systemic_mgf, x_min, x_max, 0.01, 0.001, 16, 0.00001, 100
+    /// ).unwrap();
+    /// assert!(report.converged);
+    /// assert!(report.recommended_num_u < num_u);
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_convergence_report<U>(
+        &self,
+        mgf_systemic: &U,
+        x_min: f64,
+        x_max: f64,
+        alpha: f64,
+        relative_tolerance: f64,
+        min_num_u: usize,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<ConvergenceReport, EconomicCapitalError>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        let cf = self.get_full_cf(mgf_systemic);
+        let num_u = cf.len();
+        if min_num_u < 2 || min_num_u > num_u {
+            return Err(EconomicCapitalError::new(
+                "Minimum number of points must be between 2 and the number of points processed.",
+            ));
+        }
+        let mut grid: Vec<usize> = std::iter::successors(Some(min_num_u), |n| Some(n * 2))
+            .take_while(|n| *n < num_u)
+            .collect();
+        grid.push(num_u);
+        let risk_measures = grid
+            .iter()
+            .map(|&n| {
+                let cf_dist_utils::RiskMetric {
+                    value_at_risk,
+                    expected_shortfall,
+                } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+                    alpha,
+                    x_min,
+                    x_max,
+                    max_iterations,
+                    tolerance,
+                    &cf[..n],
+                )?;
+                Ok((value_at_risk, expected_shortfall))
+            })
+            .collect::<Result<Vec<(f64, f64)>, EconomicCapitalError>>()?;
+        let (reference_value_at_risk, reference_expected_shortfall) =
+            risk_measures[risk_measures.len() - 1];
+        let steps: Vec<ConvergenceStep> = grid
+            .iter()
+            .zip(&risk_measures)
+            .map(
+                |(&num_u, &(value_at_risk, expected_shortfall))| ConvergenceStep {
+                    num_u,
+                    value_at_risk,
+                    expected_shortfall,
+                    value_at_risk_error: get_relative_error(value_at_risk, reference_value_at_risk),
+                    expected_shortfall_error: get_relative_error(
+                        expected_shortfall,
+                        reference_expected_shortfall,
+                    ),
+                },
+            )
+            .collect();
+        //smallest step after the last one outside the tolerance
+        let first_within = steps
+            .iter()
+            .rposition(|step| {
+                step.value_at_risk_error > relative_tolerance
+                    || step.expected_shortfall_error > relative_tolerance
+            })
+            .map_or(0, |index| index + 1);
+        Ok(ConvergenceReport {
+            recommended_num_u: steps[first_within].num_u,
+            converged: first_within < steps.len() - 1,
+            steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use approx::*;
+    fn get_attributes(num_u: usize, x_min: f64, x_max: f64) -> EconomicCapitalAttributes {
+        process_test_loans(
+            exponential_lgd_cf,
+            0.0,
+            0.0,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &[get_test_loan(vec![0.4, 0.6], 1000.0)],
+        )
+    }
+    #[test]
+    fn prefix_matches_fewer_points() {
+        let x_min = -1000.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.3];
+        let mgf = gamma_mgf(&v);
+        let report = get_attributes(512, x_min, x_max)
+            .get_convergence_report(&mgf, x_min, x_max, 0.01, 0.001, 32, 0.00001, 100)
+            .unwrap();
+        let num_u: Vec<usize> = report.steps.iter().map(|step| step.num_u).collect();
+        assert_eq!(num_u, vec![32, 64, 128, 256, 512]);
+        //processing with 128 points directly gives the same risk measures
+        let direct = get_attributes(128, x_min, x_max)
+            .get_convergence_report(&mgf, x_min, x_max, 0.01, 0.001, 128, 0.00001, 100)
+            .unwrap();
+        assert_abs_diff_eq!(
+            direct.steps[0].value_at_risk,
+            report.steps[2].value_at_risk,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            direct.steps[0].expected_shortfall,
+            report.steps[2].expected_shortfall,
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn recommends_smallest_converged_num_u() {
+        let x_min = -1000.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.3];
+        let mgf = gamma_mgf(&v);
+        let ec = get_attributes(512, x_min, x_max);
+        let loose = ec
+            .get_convergence_report(&mgf, x_min, x_max, 0.01, 0.01, 16, 0.00001, 100)
+            .unwrap();
+        let tight = ec
+            .get_convergence_report(&mgf, x_min, x_max, 0.01, 0.0001, 16, 0.00001, 100)
+            .unwrap();
+        assert!(loose.converged);
+        assert!(loose.recommended_num_u <= tight.recommended_num_u);
+        let step = loose
+            .steps
+            .iter()
+            .find(|step| step.num_u == loose.recommended_num_u)
+            .unwrap();
+        assert!(step.value_at_risk_error <= 0.01 && step.expected_shortfall_error <= 0.01);
+        let impossible = ec
+            .get_convergence_report(&mgf, x_min, x_max, 0.01, 0.0, 16, 0.00001, 100)
+            .unwrap();
+        assert!(!impossible.converged);
+        assert_eq!(impossible.recommended_num_u, 512);
+        assert!(ec
+            .get_convergence_report(&mgf, x_min, x_max, 0.01, 0.01, 1024, 0.00001, 100)
+            .is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Portfolios with loans in several currencies.  Loans
+//! are converted into a reporting currency at the spot
+//! rate, and the exposure is never revalued: the loss
+//! given default of every loan is fixed at the spot
+//! rate whatever the FX outcome.
+//!
+//! A foreign currency can optionally carry its own
+//! systemic variable, which is a proxy for FX risk
+//! through the correlation of defaults only.  A share
+//! `fx_weight` of the loan's weight is moved onto the FX
+//! variable, so the default intensity (not the size of
+//! the loss) of the loans in that currency rises when
+//! the FX variable is high.  This correlates the defaults
+//! within a currency, as when borrowers are hurt by an
+//! exchange rate move, but it does not capture the
+//! translation of the exposure into the reporting
+//! currency.  The FX variables are appended after the
+//! credit systemic variables, in the order of the
+//! currency codes.
+
+use crate::error::EconomicCapitalError;
+use crate::Loan;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+
+/// Struct for a loan denominated in a currency.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CurrencyLoan {
+    #[serde(flatten)]
+    /// Risk parameters of the loan.  The balance is
+    /// in `currency`.
+    pub loan: Loan,
+    /// Currency code of the loan, eg "USD".
+    pub currency: String,
+}
+
+/// Exchange rate and FX risk of a currency.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Currency {
+    /// Units of the reporting currency per unit of
+    /// this currency.
+    pub spot: f64,
+    #[serde(default)]
+    /// Share of the loans' weight moved onto the FX
+    /// systemic variable of this currency.  This only
+    /// scales the default intensity; the balance is
+    /// converted at `spot` regardless.  Zero (the
+    /// default) means no FX variable.
+    pub fx_weight: f64,
+    #[serde(default)]
+    /// Variance of the FX systemic variable (which has
+    /// expectation one).  Must be positive when
+    /// `fx_weight` is positive.
+    pub fx_variance: f64,
+}
+
+/// Currencies of the portfolio and the reporting
+/// currency.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FxConfig {
+    /// Currency code of the reporting currency.  Loans
+    /// in this currency are not converted.
+    pub reporting_currency: String,
+    /// Exchange rate and FX risk of each foreign
+    /// currency.
+    pub currencies: BTreeMap<String, Currency>,
+}
+
+impl FxConfig {
+    /// Returns the currency codes with an FX systemic
+    /// variable, in the order of the variables.
+    pub fn get_fx_factors(&self) -> Vec<&str> {
+        self.currencies
+            .iter()
+            .filter(|(_, currency)| currency.fx_weight > 0.0)
+            .map(|(code, _)| code.as_str())
+            .collect()
+    }
+    /// Returns the number of FX systemic variables.
+    pub fn num_fx_factors(&self) -> usize {
+        self.get_fx_factors().len()
+    }
+    /// Appends the variances of the FX systemic
+    /// variables to the variances of the credit
+    /// systemic variables, for use in the moment
+    /// generating function.
+    ///
+    /// # Arguments
+    ///
+    /// * `var_sys` - The vector of variances for the
+    ///   credit systemic random variables.
+    pub fn get_systemic_variance(&self, var_sys: &[f64]) -> Vec<f64> {
+        var_sys
+            .iter()
+            .cloned()
+            .chain(
+                self.currencies
+                    .values()
+                    .filter(|currency| currency.fx_weight > 0.0)
+                    .map(|currency| currency.fx_variance),
+            )
+            .collect()
+    }
+    /// Returns the loan in the reporting currency,
+    /// with the weights of the FX systemic variables
+    /// appended to its weight vector.  The balance is
+    /// converted at the spot rate; the FX variable
+    /// only changes the probability of default, so the
+    /// expected loss is the same as without it.
+    ///
+    /// # Arguments
+    ///
+    /// * `currency_loan` - The loan and its currency.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// # fn main(){
+    /// let fx_config: loan_ec::currency::FxConfig = serde_json::from_str(r#"{
+    ///     "reporting_currency": "EUR",
+    ///     "currencies": {
+    ///         "USD": {"spot": 0.9, "fx_weight": 0.2, "fx_variance": 0.1},
+    ///         "GBP": {"spot": 1.15}
+    ///     }
+    /// }"#).unwrap();
+    /// let currency_loan: loan_ec::currency::CurrencyLoan = serde_json::from_str(r#"{
+    ///     "pd": 0.05, "lgd": 0.5, "balance": 100.0, "r": 0.0,
+    ///     "lgd_variance": 0.0, "weight": [1.0], "num": 1.0, "currency": "USD"
+    /// }"#).unwrap();
+    /// let loan = fx_config.get_reporting_loan(&currency_loan).unwrap();
+    /// assert_eq!(loan.balance, 90.0);
+    /// assert_eq!(loan.weight, vec![0.8, 0.2]);
+    /// # }
+    /// ```
+    pub fn get_reporting_loan(
+        &self,
+        currency_loan: &CurrencyLoan,
+    ) -> Result<Loan, EconomicCapitalError> {
+        let fx_factors = self.get_fx_factors();
+        let mut fx_weight = vec![0.0; fx_factors.len()];
+        let loan = &currency_loan.loan;
+        if currency_loan.currency == self.reporting_currency {
+            return Ok(Loan {
+                weight: loan.weight.iter().cloned().chain(fx_weight).collect(),
+                ..loan.clone()
+            });
+        }
+        let currency = self
+            .currencies
+            .get(&currency_loan.currency)
+            .ok_or_else(|| EconomicCapitalError::new("Currency has no exchange rate."))?;
+        if currency.fx_weight < 0.0
+            || currency.fx_weight > 1.0
+            || (currency.fx_weight > 0.0 && currency.fx_variance <= 0.0)
+        {
+            return Err(EconomicCapitalError::new(
+                "FX weight must be between 0 and 1 with a positive FX variance.",
+            ));
+        }
+        if let Some(index) = fx_factors
+            .iter()
+            .position(|code| *code == currency_loan.currency)
+        {
+            fx_weight[index] = currency.fx_weight;
+        }
+        Ok(Loan {
+            balance: loan.balance * currency.spot,
+            weight: loan
+                .weight
+                .iter()
+                .map(|w| w * (1.0 - currency.fx_weight))
+                .chain(fx_weight)
+                .collect(),
+            ..loan.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_fx_config() -> FxConfig {
+        serde_json::from_str(
+            r#"{
+                "reporting_currency": "EUR",
+                "currencies": {
+                    "USD": {"spot": 0.9, "fx_weight": 0.3, "fx_variance": 0.5},
+                    "GBP": {"spot": 1.2}
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+    fn get_currency_loan(currency: &str) -> CurrencyLoan {
+        CurrencyLoan {
+            loan: Loan {
+                pd: 0.05,
+                lgd: 0.5,
+                balance: 1.0,
+                r: 0.0,
+                lgd_variance: 0.0,
+                weight: vec![0.4, 0.6],
+                num: 1000.0,
+            },
+            currency: currency.to_string(),
+        }
+    }
+    #[test]
+    fn loans_convert_to_reporting_currency() {
+        let fx_config = get_fx_config();
+        assert_eq!(fx_config.get_fx_factors(), vec!["USD"]);
+        assert_eq!(
+            fx_config.get_systemic_variance(&[0.4, 0.3]),
+            vec![0.4, 0.3, 0.5]
+        );
+        let eur = fx_config
+            .get_reporting_loan(&get_currency_loan("EUR"))
+            .unwrap();
+        assert_eq!(eur.balance, 1.0);
+        assert_eq!(eur.weight, vec![0.4, 0.6, 0.0]);
+        let gbp = fx_config
+            .get_reporting_loan(&get_currency_loan("GBP"))
+            .unwrap();
+        assert_abs_diff_eq!(gbp.balance, 1.2, epsilon = 0.0000001);
+        assert_eq!(gbp.weight, vec![0.4, 0.6, 0.0]);
+        let usd = fx_config
+            .get_reporting_loan(&get_currency_loan("USD"))
+            .unwrap();
+        assert_abs_diff_eq!(usd.balance, 0.9, epsilon = 0.0000001);
+        assert_abs_diff_eq!(usd.weight.iter().sum::<f64>(), 1.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(usd.weight[2], 0.3, epsilon = 0.0000001);
+        assert!(fx_config
+            .get_reporting_loan(&get_currency_loan("JPY"))
+            .is_err());
+    }
+    #[test]
+    fn fx_variance_keeps_expectation_and_adds_risk() {
+        let num_u: usize = 256;
+        let x_min = -1000.0;
+        let x_max = 0.0;
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let with_fx = get_fx_config();
+        let mut low_fx = get_fx_config();
+        low_fx.currencies.get_mut("USD").unwrap().fx_variance = 0.01;
+        let get_variance = |fx_config: &FxConfig| {
+            let mut ec = EconomicCapitalAttributes::new(num_u, 2 + fx_config.num_fx_factors());
+            ["EUR", "USD", "GBP"].iter().for_each(|currency| {
+                let loan = fx_config
+                    .get_reporting_loan(&get_currency_loan(currency))
+                    .unwrap();
+                ec.process_loan(&loan, &u_domain, &log_lpm_cf);
+            });
+            let var_sys = fx_config.get_systemic_variance(&[0.4, 0.3]);
+            let el_sys = vec![1.0; var_sys.len()];
+            (
+                ec.get_portfolio_expectation(&el_sys),
+                ec.get_portfolio_variance(&el_sys, &var_sys),
+            )
+        };
+        let (expectation_fx, variance_fx) = get_variance(&with_fx);
+        let (expectation, variance) = get_variance(&low_fx);
+        //1000*0.05*0.5*(1+0.9+1.2)
+        assert_abs_diff_eq!(expectation, -77.5, epsilon = 0.0000001);
+        assert_abs_diff_eq!(expectation_fx, expectation, epsilon = 0.0000001);
+        assert!(variance_fx > variance);
+    }
+}
@@ -0,0 +1,269 @@
+//! Probability distribution helpers used across the crate.
+
+/// Complementary error function.  Uses the
+/// Chebyshev approximation with fractional
+/// error below 1.2e-7 everywhere.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let ans = t
+        * (-z * z - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+    if x >= 0.0 {
+        ans
+    } else {
+        2.0 - ans
+    }
+}
+
+/// Cumulative distribution function of the
+/// standard normal distribution.
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+/// Inverse of the standard normal cumulative
+/// distribution function.  Uses Acklam's rational
+/// approximation with relative error below 1.2e-9.
+pub fn normal_inv(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.024_25;
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Natural log of the gamma function for positive
+/// arguments.  Uses the Lanczos approximation
+/// (g=7, n=9).
+pub fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        //reflection formula
+        (std::f64::consts::PI / (std::f64::consts::PI * x).sin()).ln() - ln_gamma(1.0 - x)
+    } else {
+        let x = x - 1.0;
+        let t = x + 7.5;
+        let series = COEFFICIENTS
+            .iter()
+            .enumerate()
+            .skip(1)
+            .fold(COEFFICIENTS[0], |acc, (index, c)| {
+                acc + c / (x + index as f64)
+            });
+        0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+    }
+}
+
+/// Regularized lower incomplete gamma function
+/// P(a, x).  Uses the series expansion for x < a+1
+/// and the continued fraction otherwise.
+pub fn regularized_gamma(a: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 500;
+    const EPSILON: f64 = 1e-14;
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let log_prefix = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..MAX_ITERATIONS {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        sum * log_prefix.exp()
+    } else {
+        //modified Lentz's method
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for n in 1..MAX_ITERATIONS {
+            let an = -(n as f64) * (n as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        1.0 - log_prefix.exp() * h
+    }
+}
+
+/// Cumulative distribution function of the Gamma
+/// distribution with the given shape and scale.
+pub fn gamma_cdf(x: f64, shape: f64, scale: f64) -> f64 {
+    regularized_gamma(shape, x / scale)
+}
+
+/// Cumulative distribution function of the
+/// chi-squared distribution with the given degrees of
+/// freedom.
+pub fn chi_squared_cdf(x: f64, degrees_of_freedom: f64) -> f64 {
+    if x <= 0.0 {
+        0.0
+    } else {
+        gamma_cdf(x, 0.5 * degrees_of_freedom, 2.0)
+    }
+}
+
+/// Quantile of the Gamma distribution with the
+/// given shape and scale, found by bisection.
+pub fn gamma_inv(p: f64, shape: f64, scale: f64) -> f64 {
+    let mean = shape * scale;
+    let mut low = 0.0;
+    let mut high = mean.max(scale);
+    while gamma_cdf(high, shape, scale) < p {
+        low = high;
+        high *= 2.0;
+    }
+    for _ in 0..200 {
+        let mid = 0.5 * (low + high);
+        if gamma_cdf(mid, shape, scale) < p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+        if high - low < 1e-12 * high {
+            break;
+        }
+    }
+    0.5 * (low + high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    #[test]
+    fn normal_cdf_at_zero() {
+        assert_abs_diff_eq!(normal_cdf(0.0), 0.5, epsilon = 0.0000001);
+    }
+    #[test]
+    fn normal_cdf_known_values() {
+        assert_abs_diff_eq!(normal_cdf(1.0), 0.841_344_746, epsilon = 0.0000001);
+        assert_abs_diff_eq!(normal_cdf(-1.96), 0.024_997_895, epsilon = 0.0000001);
+        assert_abs_diff_eq!(normal_cdf(3.0), 0.998_650_102, epsilon = 0.0000001);
+    }
+    #[test]
+    fn normal_inv_known_values() {
+        assert_abs_diff_eq!(normal_inv(0.5), 0.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(normal_inv(0.999), 3.090_232_306, epsilon = 0.0000001);
+        assert_abs_diff_eq!(normal_inv(0.01), -2.326_347_874, epsilon = 0.0000001);
+        assert_abs_diff_eq!(normal_inv(0.9), 1.281_551_566, epsilon = 0.0000001);
+    }
+    #[test]
+    fn ln_gamma_known_values() {
+        assert_abs_diff_eq!(ln_gamma(1.0), 0.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(ln_gamma(5.0), 24.0_f64.ln(), epsilon = 0.0000001);
+        assert_abs_diff_eq!(
+            ln_gamma(0.5),
+            std::f64::consts::PI.sqrt().ln(),
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn gamma_cdf_known_values() {
+        //exponential distribution
+        assert_abs_diff_eq!(
+            gamma_cdf(1.0, 1.0, 1.0),
+            1.0 - (-1.0_f64).exp(),
+            epsilon = 1e-10
+        );
+        assert_abs_diff_eq!(
+            gamma_cdf(10.0, 1.0, 2.0),
+            1.0 - (-5.0_f64).exp(),
+            epsilon = 1e-10
+        );
+    }
+    #[test]
+    fn gamma_inv_inverts_cdf() {
+        let shape = 2.5;
+        let scale = 0.4;
+        [0.01, 0.5, 0.999].iter().for_each(|p| {
+            assert_abs_diff_eq!(
+                gamma_cdf(gamma_inv(*p, shape, scale), shape, scale),
+                p,
+                epsilon = 1e-9
+            );
+        });
+    }
+    #[test]
+    fn chi_squared_cdf_known_values() {
+        assert_abs_diff_eq!(chi_squared_cdf(3.841_458_821, 1.0), 0.95, epsilon = 1e-7);
+        assert_abs_diff_eq!(chi_squared_cdf(5.991_464_547, 2.0), 0.95, epsilon = 1e-7);
+        assert_eq!(chi_squared_cdf(0.0, 3.0), 0.0);
+    }
+}
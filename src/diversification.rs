@@ -0,0 +1,204 @@
+//! Diversification across systemic variables.  Since
+//! the default intensity of each loan is linear in the
+//! systemic variables, the portfolio loss is the sum of
+//! one sub-portfolio per systemic variable, each
+//! holding the share of the loans attributable to that
+//! variable.  The standalone risk of each sub-portfolio
+//! is computed from the accumulated attributes without
+//! reprocessing the loans.
+
+use crate::EconomicCapitalAttributes;
+use num_complex::Complex;
+use rayon::prelude::*;
+use serde_derive::Serialize;
+
+/// Standalone and diversified risk of the portfolio.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiversificationReport {
+    /// Standalone risk measure of the sub-portfolio
+    /// for each systemic variable.
+    pub standalone: Vec<f64>,
+    /// Sum of the standalone risk measures.
+    pub standalone_total: f64,
+    /// Risk measure of the whole portfolio.
+    pub diversified: f64,
+    /// Standalone total less the diversified risk
+    /// measure.
+    pub diversification_benefit: f64,
+    /// Diversified risk measure as a fraction of the
+    /// standalone total.
+    pub diversification_ratio: f64,
+}
+
+impl EconomicCapitalAttributes {
+    /// Gets the discrete characteristic function for
+    /// the sub-portfolio attributable to one systemic
+    /// variable.  The other systemic variables are
+    /// passed a zero exponent.  This should be called
+    /// after processing all the loans in the portfolio.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the systemic variable.
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the (independent) systemic random
+    ///   variables.
+    pub fn get_factor_cf<U>(&self, index: usize, mgf_systemic: &U) -> Vec<Complex<f64>>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        self.cf
+            .par_chunks(self.num_w)
+            .map(|u_weights| {
+                let factor_weights: Vec<Complex<f64>> = u_weights
+                    .iter()
+                    .enumerate()
+                    .map(|(k, u)| {
+                        if k == index {
+                            *u
+                        } else {
+                            Complex::new(0.0, 0.0)
+                        }
+                    })
+                    .collect();
+                mgf_systemic(&factor_weights)
+            })
+            .collect()
+    }
+    /// Returns the standalone risk measure of each
+    /// systemic variable's sub-portfolio next to the
+    /// risk measure of the whole portfolio.
+    ///
+    /// # Arguments
+    ///
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the (independent) systemic random
+    ///   variables.
+    /// * `risk_measure_fn` - Function which computes
+    ///   the risk measure (eg, VaR or economic capital)
+    ///   from a characteristic function.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// extern crate cf_dist_utils;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=256;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 2);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.2,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![0.5, 0.5],
+    ///     num: 10000.0
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let v = vec![0.4, 0.3];
+    /// let systemic_mgf = |u_weights: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u_weights, &v);
+    /// let risk_measure_fn = |final_cf: &[Complex<f64>]| {
+    ///     let cf_dist_utils::RiskMetric{value_at_risk, ..} = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+    ///         0.01, x_min, x_max, 100, 0.0001, final_cf,
+    ///     ).unwrap();
+    ///     value_at_risk
+    /// };
+    /// let report = ec_attributes.get_diversification_report(&systemic_mgf, &risk_measure_fn);
+    /// assert!(report.diversification_ratio < 1.0);
+    /// # }
+    /// ```
+    pub fn get_diversification_report<U, T>(
+        &self,
+        mgf_systemic: &U,
+        risk_measure_fn: T,
+    ) -> DiversificationReport
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64,
+    {
+        let standalone: Vec<f64> = (0..self.num_w)
+            .map(|index| risk_measure_fn(&self.get_factor_cf(index, mgf_systemic)))
+            .collect();
+        let standalone_total: f64 = standalone.iter().sum();
+        let diversified = risk_measure_fn(&self.get_full_cf(mgf_systemic));
+        DiversificationReport {
+            standalone,
+            standalone_total,
+            diversified,
+            diversification_benefit: standalone_total - diversified,
+            diversification_ratio: diversified / standalone_total,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use approx::*;
+    use num_complex::Complex;
+    fn get_test_attributes(
+        num_u: usize,
+        x_min: f64,
+        x_max: f64,
+        num_w: usize,
+    ) -> EconomicCapitalAttributes {
+        process_test_loans(
+            exponential_lgd_cf,
+            0.0,
+            0.0,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &[get_test_loan(vec![1.0 / num_w as f64; num_w], 10000.0)],
+        )
+    }
+    fn value_at_risk_fn(x_min: f64, x_max: f64) -> impl Fn(&[Complex<f64>]) -> f64 {
+        move |cf: &[Complex<f64>]| {
+            let cf_dist_utils::RiskMetric { value_at_risk, .. } =
+                cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+                    0.01, x_min, x_max, 100, 0.00001, cf,
+                )
+                .unwrap();
+            value_at_risk
+        }
+    }
+    #[test]
+    fn expectations_are_additive() {
+        let num_u: usize = 256;
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(num_u, x_min, x_max, 2);
+        let v = vec![0.4, 0.3];
+        let report = ec.get_diversification_report(&gamma_mgf(&v), |cf: &[Complex<f64>]| {
+            -cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, cf)
+        });
+        assert_abs_diff_eq!(report.standalone[0], 125.0, epsilon = 0.01);
+        assert_abs_diff_eq!(report.standalone_total, report.diversified, epsilon = 0.01);
+        assert_abs_diff_eq!(report.diversification_ratio, 1.0, epsilon = 0.0001);
+    }
+    #[test]
+    fn independent_factors_diversify() {
+        let num_u: usize = 256;
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.4];
+        let single = get_test_attributes(num_u, x_min, x_max, 1)
+            .get_diversification_report(&gamma_mgf(&v[..1]), value_at_risk_fn(x_min, x_max));
+        assert_abs_diff_eq!(single.diversification_ratio, 1.0, epsilon = 0.0000001);
+        let report = get_test_attributes(num_u, x_min, x_max, 2)
+            .get_diversification_report(&gamma_mgf(&v), value_at_risk_fn(x_min, x_max));
+        assert_abs_diff_eq!(report.standalone[0], report.standalone[1], epsilon = 0.01);
+        assert!(report.diversification_ratio < 1.0);
+        assert!(report.diversification_benefit > 0.0);
+        assert!(report.diversified < single.diversified);
+    }
+}
@@ -0,0 +1,483 @@
+//! Expected credit loss for IFRS 9 and CECL
+//! provisioning from the same loan data used for
+//! economic capital.  Loans are staged, and the 12
+//! month or lifetime expected loss is weighted over
+//! macroeconomic scenarios.  The expected loss in
+//! each period uses the same logic as the portfolio
+//! expectation, with the period's marginal
+//! probability of default and outstanding balance
+//! from a [TermLoan](../horizon/struct.TermLoan.html).
+
+use crate::error::EconomicCapitalError;
+use crate::horizon::TermLoan;
+use crate::{default_one, get_el_from_loan};
+use serde_derive::{Deserialize, Serialize};
+
+fn default_significant_increase() -> f64 {
+    2.0
+}
+fn default_watch_days() -> u32 {
+    30
+}
+fn default_default_days() -> u32 {
+    90
+}
+
+/// Largest difference of the sum of the scenario
+/// probabilities from one.
+const PROBABILITY_TOLERANCE: f64 = 1e-8;
+
+/// IFRS 9 stage of a loan.  Under CECL every loan
+/// uses the lifetime loss, which is available in
+/// the [EclResult](struct.EclResult.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Stage {
+    /// Performing: 12 month expected loss.
+    One,
+    /// Significant increase in credit risk:
+    /// lifetime expected loss.
+    Two,
+    /// Credit impaired: lifetime expected loss
+    /// given default.
+    Three,
+}
+
+/// Rules for assigning stages.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StagingRules {
+    #[serde(default = "default_significant_increase")]
+    /// Ratio of the current to the origination 12
+    /// month probability of default beyond which
+    /// credit risk has increased significantly.
+    pub significant_increase_ratio: f64,
+    #[serde(default = "default_watch_days")]
+    /// Days past due at which a loan moves to stage 2.
+    pub watch_days_past_due: u32,
+    #[serde(default = "default_default_days")]
+    /// Days past due at which a loan moves to stage 3.
+    pub default_days_past_due: u32,
+}
+
+impl Default for StagingRules {
+    fn default() -> Self {
+        StagingRules {
+            significant_increase_ratio: default_significant_increase(),
+            watch_days_past_due: default_watch_days(),
+            default_days_past_due: default_default_days(),
+        }
+    }
+}
+
+/// Struct for a loan with the attributes needed
+/// for provisioning.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EclLoan {
+    #[serde(flatten)]
+    /// The loan with its term structure.
+    pub term_loan: TermLoan,
+    /// 12 month probability of default at origination.
+    pub origination_pd: f64,
+    #[serde(default)]
+    /// Days past due.
+    pub days_past_due: u32,
+    #[serde(default)]
+    /// Whether the loan is credit impaired.
+    pub credit_impaired: bool,
+    #[serde(default)]
+    /// Effective interest rate per period used
+    /// for discounting.
+    pub discount_rate: f64,
+}
+
+/// A probability weighted macroeconomic scenario.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MacroScenario {
+    /// Probability of the scenario.  The
+    /// probabilities over all scenarios must
+    /// add to one.
+    pub probability: f64,
+    #[serde(default = "default_one")]
+    /// Multiplier for the cumulative probabilities
+    /// of default.
+    pub pd_multiplier: f64,
+    #[serde(default = "default_one")]
+    /// Multiplier for the loss given default.
+    pub lgd_multiplier: f64,
+}
+
+/// Expected credit loss of a loan.
+#[derive(Debug, Clone, Serialize)]
+pub struct EclResult {
+    /// Stage of the loan.
+    pub stage: Stage,
+    /// Probability weighted 12 month expected loss.
+    pub twelve_month: f64,
+    /// Probability weighted lifetime expected loss.
+    pub lifetime: f64,
+    /// Provision for the stage of the loan.
+    pub ecl: f64,
+}
+
+/// Expected credit loss of a portfolio.
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioEcl {
+    /// Result for each loan.
+    pub loans: Vec<EclResult>,
+    /// Provision for stage 1 loans.
+    pub stage_one: f64,
+    /// Provision for stage 2 loans.
+    pub stage_two: f64,
+    /// Provision for stage 3 loans.
+    pub stage_three: f64,
+    /// Total provision.
+    pub total: f64,
+}
+
+/// Returns the cumulative probability of default
+/// over the first `periods` periods.
+fn get_cumulative_pd(term_loan: &TermLoan, periods: usize) -> f64 {
+    match periods {
+        0 => 0.0,
+        _ => term_loan
+            .cumulative_pd
+            .get(periods - 1)
+            .or_else(|| term_loan.cumulative_pd.last())
+            .cloned()
+            .unwrap_or(0.0),
+    }
+}
+
+/// Returns the stage of a loan.
+///
+/// # Arguments
+///
+/// * `ecl_loan` - The loan.
+/// * `rules` - The staging rules.
+/// * `periods_per_year` - Number of periods in 12
+///   months.
+pub fn get_stage(ecl_loan: &EclLoan, rules: &StagingRules, periods_per_year: usize) -> Stage {
+    if ecl_loan.credit_impaired || ecl_loan.days_past_due >= rules.default_days_past_due {
+        Stage::Three
+    } else if ecl_loan.days_past_due >= rules.watch_days_past_due
+        || get_cumulative_pd(&ecl_loan.term_loan, periods_per_year)
+            >= rules.significant_increase_ratio * ecl_loan.origination_pd
+    {
+        Stage::Two
+    } else {
+        Stage::One
+    }
+}
+
+/// Returns the discounted expected loss (positive,
+/// in dollars) over the first `periods` periods
+/// under a scenario.
+fn get_scenario_expected_loss(
+    ecl_loan: &EclLoan,
+    scenario: &MacroScenario,
+    periods: usize,
+) -> Result<f64, EconomicCapitalError> {
+    let term_loan = &ecl_loan.term_loan;
+    let stressed = TermLoan {
+        cumulative_pd: term_loan
+            .cumulative_pd
+            .iter()
+            .map(|pd| (pd * scenario.pd_multiplier).min(1.0))
+            .collect(),
+        lgd: (term_loan.lgd * scenario.lgd_multiplier).min(1.0),
+        ..term_loan.clone()
+    };
+    (0..periods.min(stressed.cumulative_pd.len()))
+        .map(|period| {
+            let loan = stressed.get_period_loan(period)?;
+            let expected_loss = -loan
+                .weight
+                .iter()
+                .map(|&w| get_el_from_loan(&loan, w))
+                .sum::<f64>();
+            Ok(expected_loss / (1.0 + ecl_loan.discount_rate).powi(period as i32 + 1))
+        })
+        .sum()
+}
+
+/// Returns the expected loss given default of a
+/// credit impaired loan under a scenario.
+fn get_scenario_impaired_loss(ecl_loan: &EclLoan, scenario: &MacroScenario) -> f64 {
+    let term_loan = &ecl_loan.term_loan;
+    let balance = term_loan.balance_schedule.first().cloned().unwrap_or(0.0);
+    let total_weight: f64 = term_loan.weight.iter().sum();
+    (term_loan.lgd * scenario.lgd_multiplier).min(1.0) * balance * term_loan.num * total_weight
+}
+
+/// Checks that the scenario probabilities are
+/// positive and add to one.
+fn check_scenarios(scenarios: &[MacroScenario]) -> Result<(), EconomicCapitalError> {
+    let total_probability: f64 = scenarios.iter().map(|s| s.probability).sum();
+    if scenarios.iter().any(|s| s.probability <= 0.0)
+        || (total_probability - 1.0).abs() > PROBABILITY_TOLERANCE
+    {
+        return Err(EconomicCapitalError::new(
+            "Scenario probabilities must be positive and add to one.",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the expected credit loss of a loan,
+/// weighted over the scenarios.  Returns an error
+/// unless the scenario probabilities are positive
+/// and add to one and the cumulative probabilities
+/// of default are non-decreasing and between zero
+/// and one.
+///
+/// # Arguments
+///
+/// * `ecl_loan` - The loan.
+/// * `rules` - The staging rules.
+/// * `scenarios` - The macroeconomic scenarios.
+/// * `periods_per_year` - Number of periods in 12
+///   months.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let ecl_loan: loan_ec::ecl::EclLoan = serde_json::from_str(r#"{
+///     "lgd": 0.5, "weight": [1.0], "num": 1.0,
+///     "cumulative_pd": [0.01, 0.025, 0.045],
+///     "balance_schedule": [100.0, 70.0, 40.0],
+///     "origination_pd": 0.01
+/// }"#).unwrap();
+/// let scenarios: Vec<loan_ec::ecl::MacroScenario> = serde_json::from_str(r#"[
+///     {"probability": 0.7},
+///     {"probability": 0.3, "pd_multiplier": 2.0}
+/// ]"#).unwrap();
+/// let result = loan_ec::ecl::get_ecl(
+///     &ecl_loan, &loan_ec::ecl::StagingRules::default(), &scenarios, 1
+/// ).unwrap();
+/// assert_eq!(result.stage, loan_ec::ecl::Stage::One);
+/// assert!(result.lifetime > result.twelve_month);
+/// # }
+/// ```
+pub fn get_ecl(
+    ecl_loan: &EclLoan,
+    rules: &StagingRules,
+    scenarios: &[MacroScenario],
+    periods_per_year: usize,
+) -> Result<EclResult, EconomicCapitalError> {
+    check_scenarios(scenarios)?;
+    let stage = get_stage(ecl_loan, rules, periods_per_year);
+    let weighted = |loss: &dyn Fn(&MacroScenario) -> Result<f64, EconomicCapitalError>| {
+        scenarios
+            .iter()
+            .map(|scenario| Ok(scenario.probability * loss(scenario)?))
+            .sum::<Result<f64, EconomicCapitalError>>()
+    };
+    let twelve_month =
+        weighted(&|scenario| get_scenario_expected_loss(ecl_loan, scenario, periods_per_year))?;
+    let lifetime = match stage {
+        Stage::Three => weighted(&|scenario| Ok(get_scenario_impaired_loss(ecl_loan, scenario)))?,
+        _ => weighted(&|scenario| {
+            get_scenario_expected_loss(ecl_loan, scenario, ecl_loan.term_loan.cumulative_pd.len())
+        })?,
+    };
+    Ok(EclResult {
+        stage,
+        twelve_month,
+        lifetime,
+        ecl: match stage {
+            Stage::One => twelve_month,
+            _ => lifetime,
+        },
+    })
+}
+
+/// Returns the expected credit loss of each loan
+/// and the provisions by stage.  The arguments are
+/// the same as those for [get_ecl](fn.get_ecl.html).
+pub fn get_portfolio_ecl(
+    ecl_loans: &[EclLoan],
+    rules: &StagingRules,
+    scenarios: &[MacroScenario],
+    periods_per_year: usize,
+) -> Result<PortfolioEcl, EconomicCapitalError> {
+    let loans = ecl_loans
+        .iter()
+        .map(|ecl_loan| get_ecl(ecl_loan, rules, scenarios, periods_per_year))
+        .collect::<Result<Vec<EclResult>, EconomicCapitalError>>()?;
+    let stage_total = |stage: Stage| -> f64 {
+        loans
+            .iter()
+            .filter(|result| result.stage == stage)
+            .map(|result| result.ecl)
+            .sum()
+    };
+    let stage_one = stage_total(Stage::One);
+    let stage_two = stage_total(Stage::Two);
+    let stage_three = stage_total(Stage::Three);
+    Ok(PortfolioEcl {
+        loans,
+        stage_one,
+        stage_two,
+        stage_three,
+        total: stage_one + stage_two + stage_three,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    fn get_ecl_loan(origination_pd: f64) -> EclLoan {
+        EclLoan {
+            term_loan: TermLoan {
+                lgd: 0.5,
+                r: 0.0,
+                lgd_variance: 0.0,
+                weight: vec![0.4, 0.6],
+                num: 10.0,
+                cumulative_pd: vec![0.02, 0.05, 0.09],
+                balance_schedule: vec![100.0, 60.0, 30.0],
+            },
+            origination_pd,
+            days_past_due: 0,
+            credit_impaired: false,
+            discount_rate: 0.0,
+        }
+    }
+    fn base_scenario() -> Vec<MacroScenario> {
+        vec![MacroScenario {
+            probability: 1.0,
+            pd_multiplier: 1.0,
+            lgd_multiplier: 1.0,
+        }]
+    }
+    #[test]
+    fn stages_follow_rules() {
+        let rules = StagingRules::default();
+        assert_eq!(get_stage(&get_ecl_loan(0.02), &rules, 1), Stage::One);
+        assert_eq!(get_stage(&get_ecl_loan(0.005), &rules, 1), Stage::Two);
+        let late = EclLoan {
+            days_past_due: 45,
+            ..get_ecl_loan(0.02)
+        };
+        assert_eq!(get_stage(&late, &rules, 1), Stage::Two);
+        let defaulted = EclLoan {
+            days_past_due: 95,
+            ..get_ecl_loan(0.02)
+        };
+        assert_eq!(get_stage(&defaulted, &rules, 1), Stage::Three);
+    }
+    #[test]
+    fn twelve_month_and_lifetime_losses() {
+        let result = get_ecl(
+            &get_ecl_loan(0.02),
+            &StagingRules::default(),
+            &base_scenario(),
+            1,
+        )
+        .unwrap();
+        assert_abs_diff_eq!(
+            result.twelve_month,
+            0.02 * 0.5 * 100.0 * 10.0,
+            epsilon = 0.0000001
+        );
+        let lifetime = (0.02 * 100.0 + 0.03 * 60.0 + 0.04 * 30.0) * 0.5 * 10.0;
+        assert_abs_diff_eq!(result.lifetime, lifetime, epsilon = 0.0000001);
+        assert_abs_diff_eq!(result.ecl, result.twelve_month, epsilon = 0.0000001);
+        let stage_two = get_ecl(
+            &get_ecl_loan(0.005),
+            &StagingRules::default(),
+            &base_scenario(),
+            1,
+        )
+        .unwrap();
+        assert_abs_diff_eq!(stage_two.ecl, lifetime, epsilon = 0.0000001);
+    }
+    #[test]
+    fn scenarios_are_probability_weighted() {
+        let scenarios = vec![
+            MacroScenario {
+                probability: 0.6,
+                pd_multiplier: 1.0,
+                lgd_multiplier: 1.0,
+            },
+            MacroScenario {
+                probability: 0.4,
+                pd_multiplier: 2.0,
+                lgd_multiplier: 1.2,
+            },
+        ];
+        let result = get_ecl(&get_ecl_loan(0.02), &StagingRules::default(), &scenarios, 1).unwrap();
+        let base = 0.02 * 0.5 * 100.0 * 10.0;
+        assert_abs_diff_eq!(
+            result.twelve_month,
+            0.6 * base + 0.4 * base * 2.0 * 1.2,
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn scenario_probabilities_are_validated() {
+        let get_scenarios = |probabilities: &[f64]| -> Vec<MacroScenario> {
+            probabilities
+                .iter()
+                .map(|&probability| MacroScenario {
+                    probability,
+                    pd_multiplier: 1.0,
+                    lgd_multiplier: 1.0,
+                })
+                .collect()
+        };
+        let get_result = |probabilities: &[f64]| {
+            get_ecl(
+                &get_ecl_loan(0.02),
+                &StagingRules::default(),
+                &get_scenarios(probabilities),
+                1,
+            )
+        };
+        assert!(get_result(&[0.6, 0.4]).is_ok());
+        assert!(get_result(&[0.6, 0.3]).is_err());
+        assert!(get_result(&[1.2, -0.2]).is_err());
+        assert!(get_result(&[1.0, 0.0]).is_err());
+        assert!(get_result(&[]).is_err());
+        assert!(get_portfolio_ecl(
+            &[get_ecl_loan(0.02)],
+            &StagingRules::default(),
+            &get_scenarios(&[0.5]),
+            1
+        )
+        .is_err());
+    }
+    #[test]
+    fn invalid_cumulative_pd_is_an_error() {
+        let mut ecl_loan = get_ecl_loan(0.02);
+        ecl_loan.term_loan.cumulative_pd = vec![0.02, 0.05, 0.03];
+        assert!(get_ecl(&ecl_loan, &StagingRules::default(), &base_scenario(), 1).is_err());
+    }
+    #[test]
+    fn portfolio_totals_by_stage() {
+        let impaired = EclLoan {
+            credit_impaired: true,
+            discount_rate: 0.05,
+            ..get_ecl_loan(0.02)
+        };
+        let portfolio = get_portfolio_ecl(
+            &[get_ecl_loan(0.02), get_ecl_loan(0.005), impaired],
+            &StagingRules::default(),
+            &base_scenario(),
+            1,
+        )
+        .unwrap();
+        assert_abs_diff_eq!(
+            portfolio.stage_three,
+            0.5 * 100.0 * 10.0,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            portfolio.total,
+            portfolio.loans.iter().map(|loan| loan.ecl).sum::<f64>(),
+            epsilon = 0.0000001
+        );
+        assert!(portfolio.stage_two > portfolio.stage_one);
+    }
+}
@@ -0,0 +1,36 @@
+//! Error type for the routines which can fail, for
+//! example those that search for a root or require
+//! parameters within a given range.
+
+use std::error::Error;
+use std::fmt;
+
+/// Error returned when inputs are out of range or a
+/// numerical search does not converge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EconomicCapitalError {
+    msg: String,
+}
+
+impl EconomicCapitalError {
+    /// Creates an error with the given message.
+    pub fn new(msg: &str) -> Self {
+        EconomicCapitalError {
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl Error for EconomicCapitalError {}
+
+impl fmt::Display for EconomicCapitalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl From<cf_dist_utils::ValueAtRiskError> for EconomicCapitalError {
+    fn from(error: cf_dist_utils::ValueAtRiskError) -> Self {
+        EconomicCapitalError::new(&error.to_string())
+    }
+}
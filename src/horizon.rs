@@ -0,0 +1,551 @@
+//! Losses over a multi period horizon.  Each loan has a
+//! term structure of probabilities of default and an
+//! amortizing balance, and is accumulated into one
+//! [EconomicCapitalAttributes](../struct.EconomicCapitalAttributes.html)
+//! per period.  The systemic variables are drawn once
+//! for the whole horizon so that cumulative losses are
+//! obtained by merging the periods.
+
+use crate::error::EconomicCapitalError;
+use crate::{default_one, default_zero, EconomicCapitalAttributes, Loan};
+use num_complex::Complex;
+use serde_derive::Deserialize;
+
+/// Struct representing a loan over a multi
+/// period horizon.  The probability of default
+/// and the balance of each period come from the
+/// term structures.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TermLoan {
+    /// Expected value of the loss given
+    /// default for the loan.  A positive
+    /// number.
+    pub lgd: f64,
+    /// Vector of weights describing the
+    /// exposure of this loan to each
+    /// systemic variable.
+    pub weight: Vec<f64>,
+    #[serde(default = "default_zero")]
+    /// Amount of liquidity exposure as a
+    /// percent of the balance.  A positive
+    /// number.
+    pub r: f64,
+    #[serde(default = "default_zero")]
+    /// Variance of the loss given
+    /// default for the loan.
+    pub lgd_variance: f64,
+    #[serde(default = "default_one")]
+    /// The number of loans that have these
+    /// features.
+    pub num: f64,
+    /// Cumulative probability of default at
+    /// the end of each period.
+    pub cumulative_pd: Vec<f64>,
+    /// Balance (in dollars) outstanding during
+    /// each period.  The loan has matured in
+    /// periods beyond the end of the schedule.
+    pub balance_schedule: Vec<f64>,
+}
+
+/// Returns the balance outstanding during each period
+/// for a loan with level payments.
+///
+/// # Arguments
+///
+/// * `balance` - Initial balance (in dollars).
+/// * `rate` - Interest rate per period.
+/// * `maturity` - Number of periods until the loan
+///   is fully repaid.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let balances=loan_ec::horizon::get_amortization_schedule(1000.0, 0.05, 5);
+/// assert_eq!(balances.len(), 5);
+/// # }
+/// ```
+pub fn get_amortization_schedule(balance: f64, rate: f64, maturity: usize) -> Vec<f64> {
+    let payment = if rate == 0.0 {
+        balance / (maturity as f64)
+    } else {
+        balance * rate / (1.0 - (1.0 + rate).powi(-(maturity as i32)))
+    };
+    (0..maturity)
+        .scan(balance, |outstanding, _| {
+            let current = *outstanding;
+            *outstanding = current * (1.0 + rate) - payment;
+            Some(current)
+        })
+        .collect()
+}
+
+impl TermLoan {
+    /// Returns an error unless the cumulative
+    /// probabilities of default are non-decreasing and
+    /// between zero and one.
+    fn check_cumulative_pd(&self) -> Result<(), EconomicCapitalError> {
+        let is_valid = self
+            .cumulative_pd
+            .iter()
+            .try_fold(0.0, |previous, &pd| match pd >= previous && pd <= 1.0 {
+                true => Some(pd),
+                false => None,
+            })
+            .is_some();
+        if is_valid {
+            Ok(())
+        } else {
+            Err(EconomicCapitalError::new(
+                "Cumulative probabilities of default must be non-decreasing and between 0 and 1.",
+            ))
+        }
+    }
+    /// Returns the loan as seen in a given period: the
+    /// probability of default is the marginal probability
+    /// of defaulting during the period and the balance is
+    /// the balance outstanding during the period.
+    /// Returns an error unless the cumulative
+    /// probabilities of default are non-decreasing and
+    /// between zero and one.
+    ///
+    /// # Arguments
+    ///
+    /// * `period` - The (zero based) period.
+    pub fn get_period_loan(&self, period: usize) -> Result<Loan, EconomicCapitalError> {
+        self.check_cumulative_pd()?;
+        let cumulative_pd = |t: usize| self.cumulative_pd.get(t).cloned().unwrap_or(0.0);
+        let pd = if period >= self.cumulative_pd.len() {
+            0.0
+        } else if period == 0 {
+            cumulative_pd(0)
+        } else {
+            cumulative_pd(period) - cumulative_pd(period - 1)
+        };
+        Ok(Loan {
+            pd,
+            balance: self.balance_schedule.get(period).cloned().unwrap_or(0.0),
+            lgd: self.lgd,
+            weight: self.weight.clone(),
+            r: self.r,
+            lgd_variance: self.lgd_variance,
+            num: self.num,
+        })
+    }
+}
+
+/// Holds the attributes for each period of
+/// the horizon.
+#[derive(Debug, Clone)]
+pub struct MultiPeriodAttributes {
+    /// Attributes of the losses in each period.
+    pub periods: Vec<EconomicCapitalAttributes>,
+}
+
+/// Risk measures for a single period of
+/// the horizon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HorizonRiskMeasure {
+    /// Risk measure for the losses during the
+    /// period.
+    pub period: f64,
+    /// Risk measure for the losses from the
+    /// start of the horizon through the end of
+    /// the period.
+    pub cumulative: f64,
+}
+
+impl MultiPeriodAttributes {
+    /// Creates a new multi period struct
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// # fn main(){
+    /// let num_u:usize=100;
+    /// let num_w:usize=3;
+    /// let num_periods:usize=5;
+    /// let attributes=loan_ec::horizon::MultiPeriodAttributes::new(num_u, num_w, num_periods);
+    /// # }
+    /// ```
+    pub fn new(num_u: usize, num_w: usize, num_periods: usize) -> Self {
+        MultiPeriodAttributes {
+            periods: (0..num_periods)
+                .map(|_| EconomicCapitalAttributes::new(num_u, num_w))
+                .collect(),
+        }
+    }
+    /// Adds a new loan to each period of the horizon.
+    /// Returns an error unless the cumulative
+    /// probabilities of default of the loan are
+    /// non-decreasing and between zero and one.
+    ///
+    /// # Arguments
+    ///
+    /// * `term_loan` - An instance of the [TermLoan](struct.TermLoan.html) struct.
+    /// * `u_domain` - The vector of complex values
+    ///   provided to the characteristic function.
+    /// * `log_lpm_cf` - The result from calling
+    ///   [get_log_lpm_cf](../fn.get_log_lpm_cf.html).
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=100;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let mut attributes=loan_ec::horizon::MultiPeriodAttributes::new(num_u, 1, 3);
+    /// let term_loan=loan_ec::horizon::TermLoan{
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.2,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 10000.0,
+    ///     cumulative_pd: vec![0.02, 0.05, 0.09],
+    ///     balance_schedule: loan_ec::horizon::get_amortization_schedule(1.0, 0.05, 3)
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// attributes.process_loan(&term_loan, &u_domain, &log_lpm_cf).unwrap();
+    /// # }
+    /// ```
+    pub fn process_loan<U>(
+        &mut self,
+        term_loan: &TermLoan,
+        u_domain: &[Complex<f64>],
+        log_lpm_cf: U,
+    ) -> Result<(), EconomicCapitalError>
+    where
+        U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        term_loan.check_cumulative_pd()?;
+        self.periods
+            .iter_mut()
+            .enumerate()
+            .try_for_each(|(period, attributes)| {
+                let loan = term_loan.get_period_loan(period)?;
+                if loan.pd > 0.0 && loan.balance > 0.0 {
+                    attributes.process_loan(&loan, u_domain, &log_lpm_cf);
+                }
+                Ok(())
+            })
+    }
+    /// Returns an error if the period is beyond the
+    /// end of the horizon.
+    fn check_period(&self, period: usize) -> Result<(), EconomicCapitalError> {
+        if period < self.periods.len() {
+            Ok(())
+        } else {
+            Err(EconomicCapitalError::new(
+                "Period is beyond the end of the horizon.",
+            ))
+        }
+    }
+    /// Applies `f` to the attributes of each period and
+    /// to the attributes of the cumulative losses
+    /// through the end of that period.  The periods
+    /// are merged one at a time.
+    fn map_cumulative<F, T>(&self, mut f: F) -> Vec<T>
+    where
+        F: FnMut(&EconomicCapitalAttributes, &EconomicCapitalAttributes) -> T,
+    {
+        self.periods
+            .iter()
+            .scan(
+                None,
+                |cumulative: &mut Option<EconomicCapitalAttributes>, attributes| {
+                    match cumulative {
+                        Some(cumulative) => cumulative.merge(attributes),
+                        None => *cumulative = Some(attributes.clone()),
+                    };
+                    cumulative
+                        .as_ref()
+                        .map(|cumulative| f(attributes, cumulative))
+                },
+            )
+            .collect()
+    }
+    /// Returns the attributes of the losses from
+    /// the start of the horizon through the end
+    /// of a given period.  Returns an error if the
+    /// period is beyond the end of the horizon.
+    ///
+    /// # Arguments
+    ///
+    /// * `period` - The (zero based) period.
+    pub fn get_cumulative_attributes(
+        &self,
+        period: usize,
+    ) -> Result<EconomicCapitalAttributes, EconomicCapitalError> {
+        self.check_period(period)?;
+        let mut cumulative = self.periods[0].clone();
+        self.periods[1..=period]
+            .iter()
+            .for_each(|attributes| cumulative.merge(attributes));
+        Ok(cumulative)
+    }
+    /// Gets the discrete characteristic function of
+    /// the losses during a given period.  Returns an
+    /// error if the period is beyond the end of the
+    /// horizon.
+    ///
+    /// # Arguments
+    ///
+    /// * `period` - The (zero based) period.
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables.
+    pub fn get_period_full_cf<U>(
+        &self,
+        period: usize,
+        mgf_systemic: &U,
+    ) -> Result<Vec<Complex<f64>>, EconomicCapitalError>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        self.check_period(period)?;
+        Ok(self.periods[period].get_full_cf(mgf_systemic))
+    }
+    /// Gets the discrete characteristic function of
+    /// the losses from the start of the horizon
+    /// through the end of a given period.  Returns
+    /// an error if the period is beyond the end of
+    /// the horizon.
+    ///
+    /// # Arguments
+    ///
+    /// * `period` - The (zero based) period.
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables.
+    pub fn get_cumulative_full_cf<U>(
+        &self,
+        period: usize,
+        mgf_systemic: &U,
+    ) -> Result<Vec<Complex<f64>>, EconomicCapitalError>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        Ok(self
+            .get_cumulative_attributes(period)?
+            .get_full_cf(mgf_systemic))
+    }
+    /// Gets the expected value of the cumulative
+    /// losses through the end of each period
+    /// (without liquidity risk).
+    ///
+    /// # Arguments
+    ///
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    pub fn get_cumulative_expectation(&self, el_sys: &[f64]) -> Vec<f64> {
+        self.periods
+            .iter()
+            .scan(0.0, |cumulative, attributes| {
+                *cumulative += attributes.get_portfolio_expectation(el_sys);
+                Some(*cumulative)
+            })
+            .collect()
+    }
+    /// Gets the variance of the cumulative losses
+    /// through the end of each period (without
+    /// liquidity risk).
+    ///
+    /// # Arguments
+    ///
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    /// * `var_sys` - The vector of variances for the
+    ///   systemic random variables.
+    pub fn get_cumulative_variance(&self, el_sys: &[f64], var_sys: &[f64]) -> Vec<f64> {
+        self.map_cumulative(|_, cumulative| cumulative.get_portfolio_variance(el_sys, var_sys))
+    }
+    /// Returns the risk measure for the losses during
+    /// each period and for the cumulative losses
+    /// through the end of each period.
+    ///
+    /// # Arguments
+    ///
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables.
+    /// * `risk_measure_fn` - Function which computes
+    ///   the risk measure for the portfolio, e.g. VaR.
+    pub fn get_risk_measures<U, T>(
+        &self,
+        mgf_systemic: &U,
+        risk_measure_fn: T,
+    ) -> Vec<HorizonRiskMeasure>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64,
+    {
+        self.map_cumulative(|attributes, cumulative| HorizonRiskMeasure {
+            period: risk_measure_fn(&attributes.get_full_cf(mgf_systemic)),
+            cumulative: risk_measure_fn(&cumulative.get_full_cf(mgf_systemic)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_test_term_loan() -> TermLoan {
+        TermLoan {
+            lgd: 0.5,
+            r: 0.0,
+            lgd_variance: 0.2,
+            weight: vec![0.4, 0.6],
+            num: 10000.0,
+            cumulative_pd: vec![0.02, 0.05, 0.09, 0.12],
+            balance_schedule: vec![1.0, 0.8, 0.5],
+        }
+    }
+    #[test]
+    fn amortization_schedule_pays_off_loan() {
+        let balances = get_amortization_schedule(1000.0, 0.05, 5);
+        let payment = 1000.0 * 0.05 / (1.0 - 1.05_f64.powi(-5));
+        assert_eq!(balances.len(), 5);
+        assert_abs_diff_eq!(balances[0], 1000.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(balances[4] * 1.05, payment, epsilon = 0.0000001);
+    }
+    #[test]
+    fn amortization_schedule_zero_rate_is_linear() {
+        let balances = get_amortization_schedule(1000.0, 0.0, 4);
+        assert_eq!(balances, vec![1000.0, 750.0, 500.0, 250.0]);
+    }
+    #[test]
+    fn period_loan_has_marginal_pd_and_matures() {
+        let term_loan = get_test_term_loan();
+        let pds: Vec<f64> = (0..5)
+            .map(|t| term_loan.get_period_loan(t).unwrap().pd)
+            .collect();
+        let expected = [0.02, 0.03, 0.04, 0.03, 0.0];
+        pds.iter()
+            .zip(&expected)
+            .for_each(|(pd, e)| assert_abs_diff_eq!(pd, e, epsilon = 0.0000001));
+        assert_eq!(term_loan.get_period_loan(3).unwrap().balance, 0.0);
+    }
+    #[test]
+    fn invalid_cumulative_pd_is_an_error() {
+        let decreasing = TermLoan {
+            cumulative_pd: vec![0.02, 0.05, 0.04],
+            ..get_test_term_loan()
+        };
+        assert!(decreasing.get_period_loan(0).is_err());
+        let above_one = TermLoan {
+            cumulative_pd: vec![0.5, 1.2],
+            ..get_test_term_loan()
+        };
+        assert!(above_one.get_period_loan(0).is_err());
+        let u_domain = get_test_u_domain(64, -2000.0, 0.0);
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let mut attributes = MultiPeriodAttributes::new(64, 2, 3);
+        assert!(attributes
+            .process_loan(&decreasing, &u_domain, &log_lpm_cf)
+            .is_err());
+        assert_eq!(
+            attributes.get_cumulative_expectation(&[1.0, 1.0]),
+            vec![0.0; 3]
+        );
+    }
+    #[test]
+    fn cumulative_expectation_matches_cf() {
+        let num_u: usize = 1024;
+        let x_min = -2000.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.3];
+        let systemic_expectation = vec![1.0, 1.0];
+        let term_loan = get_test_term_loan();
+        let liquid_fn = get_liquidity_risk_fn(0.0, 0.0);
+        let log_lpm_cf = get_log_lpm_cf(gamma_lgd_cf, &liquid_fn);
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let mut attributes = MultiPeriodAttributes::new(num_u, v.len(), 4);
+        attributes
+            .process_loan(&term_loan, &u_domain, &log_lpm_cf)
+            .unwrap();
+        let expectations = attributes.get_cumulative_expectation(&systemic_expectation);
+        let variances = attributes.get_cumulative_variance(&systemic_expectation, &v);
+        let expected = -(0.02 * 1.0 + 0.03 * 0.8 + 0.04 * 0.5) * 0.5 * 10000.0;
+        assert_abs_diff_eq!(expectations[3], expected, epsilon = 0.0000001);
+        assert_abs_diff_eq!(expectations[2], expectations[3], epsilon = 0.0000001);
+        let v_mgf = gamma_mgf(&v);
+        let final_cf = attributes.get_cumulative_full_cf(3, &v_mgf).unwrap();
+        let expectation_approx =
+            cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &final_cf);
+        let variance_approx = cf_dist_utils::get_variance_discrete_cf(x_min, x_max, &final_cf);
+        assert_abs_diff_eq!(expectation_approx, expected, epsilon = 0.00001);
+        assert_abs_diff_eq!(variance_approx, variances[3], epsilon = 0.1);
+    }
+    #[test]
+    fn risk_measures_increase_with_horizon() {
+        let num_u: usize = 256;
+        let x_min = -2000.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.3];
+        let term_loan = get_test_term_loan();
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let mut attributes = MultiPeriodAttributes::new(num_u, v.len(), 3);
+        attributes
+            .process_loan(&term_loan, &u_domain, &log_lpm_cf)
+            .unwrap();
+        let risk_measure_fn = |final_cf: &[Complex<f64>]| {
+            let cf_dist_utils::RiskMetric { value_at_risk, .. } =
+                cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+                    0.01, x_min, x_max, 100, 0.0001, final_cf,
+                )
+                .unwrap();
+            value_at_risk
+        };
+        let risk_measures = attributes.get_risk_measures(&gamma_mgf(&v), risk_measure_fn);
+        assert_eq!(risk_measures.len(), 3);
+        assert_abs_diff_eq!(
+            risk_measures[0].period,
+            risk_measures[0].cumulative,
+            epsilon = 0.0000001
+        );
+        assert!(risk_measures[1].cumulative > risk_measures[0].cumulative);
+        assert!(risk_measures[2].cumulative > risk_measures[1].cumulative);
+        assert!(risk_measures[2].cumulative > risk_measures[2].period);
+        assert!(attributes.get_cumulative_attributes(3).is_err());
+        assert!(attributes.get_period_full_cf(3, &gamma_mgf(&v)).is_err());
+        let empty = MultiPeriodAttributes::new(num_u, v.len(), 0);
+        assert!(empty.get_cumulative_attributes(0).is_err());
+        assert!(empty
+            .get_risk_measures(&gamma_mgf(&v), risk_measure_fn)
+            .is_empty());
+    }
+    #[test]
+    fn running_cumulative_matches_direct_merge() {
+        let num_u: usize = 64;
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let u_domain = get_test_u_domain(num_u, -2000.0, 0.0);
+        let mut attributes = MultiPeriodAttributes::new(num_u, v.len(), 4);
+        attributes
+            .process_loan(&get_test_term_loan(), &u_domain, &log_lpm_cf)
+            .unwrap();
+        attributes
+            .get_cumulative_variance(&el_sys, &v)
+            .iter()
+            .enumerate()
+            .for_each(|(period, variance)| {
+                assert_abs_diff_eq!(
+                    *variance,
+                    attributes
+                        .get_cumulative_attributes(period)
+                        .unwrap()
+                        .get_portfolio_variance(&el_sys, &v),
+                    epsilon = 0.0000001
+                );
+            });
+    }
+}
@@ -0,0 +1,351 @@
+//! Basel II/III internal ratings based (IRB) capital
+//! for each loan, using the asymptotic single risk
+//! factor (ASRF) formula at 99.9%, as a benchmark for
+//! the economic capital from this crate.
+//!
+//! K = (LGD*N((G(PD)+sqrt(R)*G(0.999))/sqrt(1-R))-PD*LGD)*MA
+//! where R is the asset correlation of the asset class
+//! and MA is the maturity adjustment (corporate only).
+//! Basel II multiplies the risk weighted assets by a
+//! scaling factor of 1.06, which Basel III removed, so
+//! the scaling factor is an argument: pass
+//! [BASEL_II_SCALING_FACTOR](constant.BASEL_II_SCALING_FACTOR.html)
+//! or 1.0.
+
+use crate::distributions::{normal_cdf, normal_inv};
+use crate::error::EconomicCapitalError;
+use crate::Loan;
+use serde_derive::{Deserialize, Serialize};
+
+/// Confidence level of the regulatory capital.
+const CONFIDENCE: f64 = 0.999;
+/// Minimum probability of default.
+const PD_FLOOR: f64 = 0.0003;
+/// Maturity (in years) assumed by the formula.
+const DEFAULT_MATURITY: f64 = 2.5;
+/// Scaling factor applied to IRB risk weighted assets
+/// under Basel II.  Basel III uses no scaling (1.0).
+pub const BASEL_II_SCALING_FACTOR: f64 = 1.06;
+
+fn default_maturity() -> f64 {
+    DEFAULT_MATURITY
+}
+
+/// Asset classes with their own correlation function.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetClass {
+    /// Corporate, sovereign and bank exposures.
+    Corporate,
+    /// Retail exposures secured by residential
+    /// real estate.
+    ResidentialMortgage,
+    /// Qualifying revolving retail exposures.
+    QualifyingRevolving,
+    /// Other retail exposures.
+    OtherRetail,
+}
+
+/// Struct for a loan with the attributes needed
+/// for the IRB formula.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IrbLoan {
+    #[serde(flatten)]
+    /// Risk parameters of the loan.  The pd is
+    /// assumed to be a one year probability.
+    pub loan: Loan,
+    /// Asset class of the loan.
+    pub asset_class: AssetClass,
+    #[serde(default = "default_maturity")]
+    /// Effective maturity in years.  Defaults to 2.5.
+    pub maturity: f64,
+    #[serde(default)]
+    /// Annual sales (in millions of euros) of the
+    /// borrower.  Sales below 50 reduce the
+    /// correlation of corporate exposures.
+    pub sales: Option<f64>,
+}
+
+/// IRB capital for a loan.
+#[derive(Debug, Clone, Serialize)]
+pub struct RegulatoryCapital {
+    /// Asset correlation.
+    pub correlation: f64,
+    /// Capital requirement per unit of exposure (K).
+    pub capital_requirement: f64,
+    /// Capital (K times exposure times the scaling
+    /// factor).
+    pub regulatory_capital: f64,
+    /// Risk weighted assets (12.5 times capital).
+    pub risk_weighted_assets: f64,
+}
+
+/// Regulatory capital next to economic capital.
+#[derive(Debug, Clone, Serialize)]
+pub struct CapitalComparison {
+    /// Economic capital from this crate.
+    pub economic_capital: f64,
+    /// IRB regulatory capital.
+    pub regulatory_capital: f64,
+    /// Ratio of economic to regulatory capital.
+    pub ratio: f64,
+}
+
+impl CapitalComparison {
+    fn new(economic_capital: f64, regulatory_capital: f64) -> Self {
+        CapitalComparison {
+            economic_capital,
+            regulatory_capital,
+            ratio: economic_capital / regulatory_capital,
+        }
+    }
+}
+
+/// Comparison of economic and regulatory capital
+/// per loan and for the portfolio.
+#[derive(Debug, Clone, Serialize)]
+pub struct IrbReport {
+    /// Comparison for each loan.
+    pub loans: Vec<CapitalComparison>,
+    /// Comparison for the portfolio.
+    pub portfolio: CapitalComparison,
+}
+
+/// Interpolates between two correlations with the
+/// exponential weight used by the IRB formula.
+fn interpolate_correlation(pd: f64, decay: f64, low: f64, high: f64) -> f64 {
+    let weight = (1.0 - (-decay * pd).exp()) / (1.0 - (-decay).exp());
+    low * weight + high * (1.0 - weight)
+}
+
+/// Returns the asset correlation of a loan.
+///
+/// # Arguments
+///
+/// * `asset_class` - The asset class of the loan.
+/// * `pd` - The (floored) probability of default.
+/// * `sales` - Annual sales in millions of euros.
+pub fn get_correlation(asset_class: AssetClass, pd: f64, sales: Option<f64>) -> f64 {
+    match asset_class {
+        AssetClass::Corporate => {
+            let size_adjustment =
+                sales.map_or(0.0, |s| 0.04 * (1.0 - (s.clamp(5.0, 50.0) - 5.0) / 45.0));
+            interpolate_correlation(pd, 50.0, 0.12, 0.24) - size_adjustment
+        }
+        AssetClass::ResidentialMortgage => 0.15,
+        AssetClass::QualifyingRevolving => 0.04,
+        AssetClass::OtherRetail => interpolate_correlation(pd, 35.0, 0.03, 0.16),
+    }
+}
+
+/// Returns the maturity adjustment.  Retail
+/// exposures have no maturity adjustment.
+///
+/// # Arguments
+///
+/// * `asset_class` - The asset class of the loan.
+/// * `pd` - The (floored) probability of default.
+/// * `maturity` - The effective maturity in years.
+pub fn get_maturity_adjustment(asset_class: AssetClass, pd: f64, maturity: f64) -> f64 {
+    match asset_class {
+        AssetClass::Corporate => {
+            let b = (0.118_52 - 0.054_78 * pd.ln()).powi(2);
+            (1.0 + (maturity.clamp(1.0, 5.0) - DEFAULT_MATURITY) * b) / (1.0 - 1.5 * b)
+        }
+        _ => 1.0,
+    }
+}
+
+/// Returns the IRB capital of a loan.  The
+/// exposure at default is balance times num.  A
+/// defaulted loan (PD of one) has no unexpected loss,
+/// so its capital requirement is zero: max(0, LGD-EL)
+/// with the best estimate of the expected loss equal
+/// to the LGD.
+///
+/// # Arguments
+///
+/// * `irb_loan` - The loan.
+/// * `scaling_factor` - Multiplier of the capital and
+///   risk weighted assets; 1.06 under Basel II and 1.0
+///   under Basel III.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let irb_loan: loan_ec::irb::IrbLoan = serde_json::from_str(r#"{
+///     "pd": 0.01, "lgd": 0.45, "balance": 100.0, "r": 0.0,
+///     "lgd_variance": 0.0, "weight": [1.0], "num": 1.0,
+///     "asset_class": "corporate"
+/// }"#).unwrap();
+/// let capital = loan_ec::irb::get_irb_capital(&irb_loan, 1.0);
+/// //risk weight of about 92% for a 1% PD corporate
+/// assert!((capital.risk_weighted_assets - 92.3).abs() < 0.1);
+/// # }
+/// ```
+pub fn get_irb_capital(irb_loan: &IrbLoan, scaling_factor: f64) -> RegulatoryCapital {
+    let Loan {
+        pd,
+        lgd,
+        balance,
+        num,
+        ..
+    } = irb_loan.loan;
+    let pd = pd.clamp(PD_FLOOR, 1.0);
+    let correlation = get_correlation(irb_loan.asset_class, pd, irb_loan.sales);
+    let capital_requirement = if pd == 1.0 {
+        0.0
+    } else {
+        let conditional_pd = normal_cdf(
+            (normal_inv(pd) + correlation.sqrt() * normal_inv(CONFIDENCE))
+                / (1.0 - correlation).sqrt(),
+        );
+        (lgd * conditional_pd - pd * lgd)
+            * get_maturity_adjustment(irb_loan.asset_class, pd, irb_loan.maturity)
+    };
+    let regulatory_capital = capital_requirement * balance * num * scaling_factor;
+    RegulatoryCapital {
+        correlation,
+        capital_requirement,
+        regulatory_capital,
+        risk_weighted_assets: 12.5 * regulatory_capital,
+    }
+}
+
+/// Returns the economic capital next to the IRB
+/// capital for each loan and for the portfolio.
+/// The economic capital of each loan is typically
+/// its risk contribution (eg, from
+/// [get_risk_contributions](../struct.EconomicCapitalAttributes.html#method.get_risk_contributions))
+/// net of its expected loss, since the IRB formula
+/// covers unexpected loss only.
+///
+/// # Arguments
+///
+/// * `irb_loans` - The loans in the portfolio.
+/// * `economic_capital` - The economic capital of
+///   each loan.  Must have an element per loan.
+/// * `scaling_factor` - Multiplier of the regulatory
+///   capital; 1.06 under Basel II and 1.0 under
+///   Basel III.
+pub fn get_irb_report(
+    irb_loans: &[IrbLoan],
+    economic_capital: &[f64],
+    scaling_factor: f64,
+) -> Result<IrbReport, EconomicCapitalError> {
+    if irb_loans.len() != economic_capital.len() {
+        return Err(EconomicCapitalError::new(
+            "Economic capital must have an element per loan.",
+        ));
+    }
+    let loans: Vec<CapitalComparison> = irb_loans
+        .iter()
+        .zip(economic_capital)
+        .map(|(irb_loan, ec)| {
+            CapitalComparison::new(
+                *ec,
+                get_irb_capital(irb_loan, scaling_factor).regulatory_capital,
+            )
+        })
+        .collect();
+    let portfolio = CapitalComparison::new(
+        loans.iter().map(|loan| loan.economic_capital).sum(),
+        loans.iter().map(|loan| loan.regulatory_capital).sum(),
+    );
+    Ok(IrbReport { loans, portfolio })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    fn get_irb_loan(pd: f64, asset_class: AssetClass) -> IrbLoan {
+        IrbLoan {
+            loan: Loan {
+                pd,
+                lgd: 0.45,
+                balance: 1.0,
+                r: 0.0,
+                lgd_variance: 0.0,
+                weight: vec![1.0],
+                num: 1.0,
+            },
+            asset_class,
+            maturity: DEFAULT_MATURITY,
+            sales: None,
+        }
+    }
+    #[test]
+    fn corporate_matches_published_risk_weights() {
+        //Basel Committee, "An Explanatory Note on the Basel II IRB Risk Weight Functions"
+        let capital = get_irb_capital(&get_irb_loan(0.01, AssetClass::Corporate), 1.0);
+        assert_abs_diff_eq!(capital.correlation, 0.1928, epsilon = 0.0001);
+        assert_abs_diff_eq!(capital.risk_weighted_assets, 0.9230, epsilon = 0.001);
+        let capital = get_irb_capital(&get_irb_loan(0.0003, AssetClass::Corporate), 1.0);
+        assert_abs_diff_eq!(capital.risk_weighted_assets, 0.1444, epsilon = 0.001);
+    }
+    #[test]
+    fn retail_has_no_maturity_adjustment() {
+        assert_eq!(
+            get_maturity_adjustment(AssetClass::ResidentialMortgage, 0.01, 5.0),
+            1.0
+        );
+        let capital = get_irb_capital(&get_irb_loan(0.01, AssetClass::ResidentialMortgage), 1.0);
+        assert_abs_diff_eq!(capital.correlation, 0.15, epsilon = 0.0000001);
+        let short = get_irb_capital(
+            &IrbLoan {
+                maturity: 1.0,
+                ..get_irb_loan(0.01, AssetClass::Corporate)
+            },
+            1.0,
+        );
+        let long = get_irb_capital(&get_irb_loan(0.01, AssetClass::Corporate), 1.0);
+        assert!(short.capital_requirement < long.capital_requirement);
+    }
+    #[test]
+    fn sme_adjustment_lowers_correlation() {
+        let large = get_correlation(AssetClass::Corporate, 0.01, None);
+        let small = get_correlation(AssetClass::Corporate, 0.01, Some(5.0));
+        assert_abs_diff_eq!(large - small, 0.04, epsilon = 0.0000001);
+    }
+    #[test]
+    fn report_sums_portfolio() {
+        let loans = vec![
+            get_irb_loan(0.01, AssetClass::Corporate),
+            get_irb_loan(0.02, AssetClass::OtherRetail),
+        ];
+        let report = get_irb_report(&loans, &[0.05, 0.03], 1.0).unwrap();
+        assert_abs_diff_eq!(report.portfolio.economic_capital, 0.08, epsilon = 0.0000001);
+        assert_abs_diff_eq!(
+            report.portfolio.regulatory_capital,
+            report.loans[0].regulatory_capital + report.loans[1].regulatory_capital,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            report.loans[0].ratio,
+            0.05 / report.loans[0].regulatory_capital,
+            epsilon = 0.0000001
+        );
+        assert!(get_irb_report(&loans, &[0.05], 1.0).is_err());
+        assert!(get_irb_report(&loans, &[0.05, 0.03, 0.01], 1.0).is_err());
+    }
+    #[test]
+    fn defaulted_loan_has_no_unexpected_loss() {
+        let capital = get_irb_capital(&get_irb_loan(1.0, AssetClass::Corporate), 1.0);
+        assert_eq!(capital.capital_requirement, 0.0);
+        assert_eq!(capital.risk_weighted_assets, 0.0);
+    }
+    #[test]
+    fn scaling_factor_scales_capital() {
+        let loan = get_irb_loan(0.01, AssetClass::Corporate);
+        let unscaled = get_irb_capital(&loan, 1.0);
+        let scaled = get_irb_capital(&loan, BASEL_II_SCALING_FACTOR);
+        assert_eq!(scaled.capital_requirement, unscaled.capital_requirement);
+        assert_abs_diff_eq!(
+            scaled.risk_weighted_assets,
+            1.06 * unscaled.risk_weighted_assets,
+            epsilon = 0.0000001
+        );
+    }
+}
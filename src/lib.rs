@@ -6,10 +6,36 @@
 use num_complex::Complex;
 use rayon::prelude::*;
 use serde_derive::Deserialize;
+pub mod backtest;
+pub mod calibration;
+mod cf_transform;
+pub mod collateral;
+pub mod concentration;
+pub mod convergence;
+pub mod currency;
+mod distributions;
+pub mod diversification;
+pub mod ecl;
+pub mod error;
+pub mod horizon;
+pub mod irb;
+pub mod limits;
+pub mod migration;
+pub mod obligor;
+pub mod pricing;
+pub mod regime;
+pub mod reverse_stress;
+pub mod risk_measure;
+pub mod segment;
+pub mod sensitivity;
+pub mod shapley;
+pub mod stress;
+pub mod uncertainty;
 mod vec_to_mat;
+pub mod waterfall;
 
 /// Struct representing loan attributes
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Loan {
     /// Balance for the loan (in dollars)
     pub balance: f64,
@@ -58,7 +84,7 @@ fn default_zero() -> f64 {
 ///
 /// * `loan` - An instance of the [Loan](struct.Loan.html) struct
 /// * `w` - A single element from the weight
-/// vector in the Loan struct.
+///   vector in the Loan struct.
 fn get_el_from_loan(loan: &Loan, w: f64) -> f64 {
     -loan.lgd * loan.balance * w * loan.pd * loan.num
 }
@@ -71,7 +97,7 @@ fn get_el_from_loan(loan: &Loan, w: f64) -> f64 {
 ///
 /// * `loan` - An instance of the [Loan](struct.Loan.html) struct
 /// * `w` - A single element from the weight
-/// vector in the Loan struct.
+///   vector in the Loan struct.
 fn get_var_from_loan(loan: &Loan, w: f64) -> f64 {
     (1.0 + loan.lgd_variance) * (loan.lgd * loan.balance).powi(2) * w * loan.pd * loan.num
 }
@@ -92,27 +118,28 @@ fn get_lambda_from_loan(loan: &Loan) -> f64 {
 ///
 /// * `loan` - An instance of the [Loan](struct.Loan.html) struct
 /// * `el_vec` - The portfolio vector of expected
-/// loss.  Has the same size as the weight vector
-/// in the Loan struct.
+///   loss.  Has the same size as the weight vector
+///   in the Loan struct.
 /// * `el_sys` - The vector of expected values for
-/// the systemic random variables.  This is
-/// typically a vector of ones.
+///   the systemic random variables.  This is
+///   typically a vector of ones.
 /// * `var_vec` - The portfolio vector of
-/// variance.  Has the same size as the weight
-/// vector in the Loan struct.
+///   variance.  Has the same size as the weight
+///   vector in the Loan struct.
 /// * `var_sys` - The vector of variances for the
-/// systemic random variables.
+///   systemic random variables.
 /// * `lambda0` - Base loss (in dollars) from a
-/// liquidity event.  A positive number.
+///   liquidity event.  A positive number.
 /// * `lambda` - Sum of r*balance over each loan
-/// in the portfolio.
+///   in the portfolio.
 /// * `q` - Probability of liquidity event (scaled
-/// by the total portfolio loss).
+///   by the total portfolio loss).
 /// * `c` - Scalar for multiplying the covariance
-/// for the risk contribution when using the
-/// variance risk measure.  Typically
-/// (rho(X)-E\[X\])/sqrt(Var(X)) where rho is the
-/// value of the portfolio risk measure (eg, VaR).
+///   for the risk contribution when using the
+///   variance risk measure.  Typically
+///   (rho(X)-E\[X\])/sqrt(Var(X)) where rho is the
+///   value of the portfolio risk measure (eg, VaR).
+#[allow(clippy::too_many_arguments)]
 pub fn risk_contribution(
     loan: &Loan,
     el_vec: &[f64],
@@ -124,59 +151,164 @@ pub fn risk_contribution(
     q: f64,
     c: f64,
 ) -> f64 {
-    let el_scalar_incremental = 1.0 + q * lambda0;
-    let el_scalar_total = q * get_lambda_from_loan(loan);
+    let el_increment: Vec<f64> = loan
+        .weight
+        .iter()
+        .map(|&w| get_el_from_loan(loan, w))
+        .collect();
+    let var_increment: Vec<f64> = loan
+        .weight
+        .iter()
+        .map(|&w| get_var_from_loan(loan, w))
+        .collect();
+    risk_contribution_from_increment(
+        &el_increment,
+        &var_increment,
+        get_lambda_from_loan(loan),
+        el_vec,
+        el_sys,
+        var_vec,
+        var_sys,
+        lambda0,
+        lambda,
+        q,
+        c,
+    )
+}
+/// Returns risk contribution for an increment to the
+/// portfolio.  The increment is described by its
+/// contribution to el_vec, var_vec and lambda, so
+/// that exposures other than single loans (eg,
+/// obligors with several facilities) can be
+/// attributed.
+///
+/// # Arguments
+///
+/// * `el_increment` - The increment's contribution
+///   to the portfolio vector of expected loss.
+/// * `var_increment` - The increment's contribution
+///   to the portfolio vector of variance.
+/// * `lambda_increment` - The increment's contribution
+///   to the total liquidity risk (r*balance).
+/// * `el_vec` - The portfolio vector of expected
+///   loss.
+/// * `el_sys` - The vector of expected values for
+///   the systemic random variables.
+/// * `var_vec` - The portfolio vector of
+///   variance.
+/// * `var_sys` - The vector of variances for the
+///   systemic random variables.
+/// * `lambda0` - Base loss (in dollars) from a
+///   liquidity event.
+/// * `lambda` - Sum of r*balance over each loan
+///   in the portfolio.
+/// * `q` - Probability of liquidity event (scaled
+///   by the total portfolio loss).
+/// * `c` - Scalar for multiplying the covariance
+///   for the risk contribution.
+#[allow(clippy::too_many_arguments)]
+fn risk_contribution_from_increment(
+    el_increment: &[f64],
+    var_increment: &[f64],
+    lambda_increment: f64,
+    el_vec: &[f64],
+    el_sys: &[f64],
+    var_vec: &[f64],
+    var_sys: &[f64],
+    lambda0: f64,
+    lambda: f64,
+    q: f64,
+    c: f64,
+) -> f64 {
+    let (expectation_contribution, covariance_contribution) = risk_contribution_parts(
+        el_increment,
+        var_increment,
+        lambda_increment,
+        el_vec,
+        el_sys,
+        var_vec,
+        var_sys,
+        lambda0,
+        lambda,
+        q,
+    );
     let expectation_total = portfolio_expectation(el_vec, el_sys);
     let variance_total = portfolio_variance(el_vec, el_sys, var_vec, var_sys);
 
     let standard_deviation =
         variance_liquidity(lambda + lambda0, q, expectation_total, variance_total).sqrt();
 
+    expectation_contribution + c * covariance_contribution / standard_deviation
+}
+/// Returns the contribution of an increment to the
+/// portfolio expectation and its covariance with
+/// the portfolio (both with liquidity risk).  The
+/// arguments are the same as those for
+/// [risk_contribution_from_increment](fn.risk_contribution_from_increment.html)
+/// without `c`.
+#[allow(clippy::too_many_arguments)]
+fn risk_contribution_parts(
+    el_increment: &[f64],
+    var_increment: &[f64],
+    lambda_increment: f64,
+    el_vec: &[f64],
+    el_sys: &[f64],
+    var_vec: &[f64],
+    var_sys: &[f64],
+    lambda0: f64,
+    lambda: f64,
+    q: f64,
+) -> (f64, f64) {
+    let el_scalar_incremental = 1.0 + q * lambda0;
+    let el_scalar_total = q * lambda_increment;
+    let expectation_total = portfolio_expectation(el_vec, el_sys);
+    let variance_total = portfolio_variance(el_vec, el_sys, var_vec, var_sys);
+
     let var_scalar_incremental = el_scalar_incremental.powi(2);
 
     let var_scalar_total = el_scalar_total * (2.0 * el_scalar_incremental + q * lambda);
     let var_el_total = el_scalar_total * (2.0 * lambda0 + lambda);
     let expectation_incremental = el_sys
         .iter()
-        .zip(&loan.weight)
-        .map(|(e_s, &w)| get_el_from_loan(loan, w) * e_s)
+        .zip(el_increment)
+        .map(|(e_s, el_i)| el_i * e_s)
         .sum::<f64>();
 
     let variance_incremental = el_sys
         .iter()
-        .zip(&loan.weight)
-        .map(|(el_s, &w)| get_var_from_loan(loan, w) * el_s)
+        .zip(var_increment)
+        .map(|(el_s, var_i)| var_i * el_s)
         .sum::<f64>()
         + var_sys
             .iter()
-            .zip(&loan.weight)
+            .zip(el_increment)
             .zip(el_vec)
-            .map(|((v_s, &w), e_v)| e_v * v_s * get_el_from_loan(loan, w))
+            .map(|((v_s, el_i), e_v)| e_v * v_s * el_i)
             .sum::<f64>();
 
-    el_scalar_incremental * expectation_incremental
-        + el_scalar_total * expectation_total
-        + c * (var_scalar_incremental * variance_incremental + var_scalar_total * variance_total
+    (
+        el_scalar_incremental * expectation_incremental + el_scalar_total * expectation_total,
+        var_scalar_incremental * variance_incremental + var_scalar_total * variance_total
             - expectation_incremental * q * lambda0.powi(2)
-            - expectation_total * var_el_total)
-            / standard_deviation
+            - expectation_total * var_el_total,
+    )
 }
 /// Returns the variance of a portfolio with liquidity risk.
 ///
 /// # Arguments
 ///
 /// * `lambda` - Sum of lambda0 (base loss in liquidity event)
-/// and the lambda element from the [Loan](struct.Loan.html) struct.
+///   and the lambda element from the [Loan](struct.Loan.html) struct.
 /// * `q` - Probability of liquidity event scaled
-/// by the total portfolio loss.
+///   by the total portfolio loss.
 /// * `expectation` - Base expectation for the portfolio
-/// without liquidity risk.  Can be computed using the
-/// [get_portfolio_expectation](struct.EconomicCapitalAttributes.html#method.get_portfolio_expectation)
-/// method.
+///   without liquidity risk.  Can be computed using the
+///   [get_portfolio_expectation](struct.EconomicCapitalAttributes.html#method.get_portfolio_expectation)
+///   method.
 /// * `variance` - Base variance for the portfolio
-/// without liquidity risk.  Can be computed using the
-/// [get_portfolio_variance](struct.EconomicCapitalAttributes.html#method.get_portfolio_variance)
-/// method.
+///   without liquidity risk.  Can be computed using the
+///   [get_portfolio_variance](struct.EconomicCapitalAttributes.html#method.get_portfolio_variance)
+///   method.
 ///
 /// # Examples
 /// ```
@@ -197,13 +329,13 @@ pub fn variance_liquidity(lambda: f64, q: f64, expectation: f64, variance: f64)
 /// # Arguments
 ///
 /// * `lambda` - Sum of lambda0 (base loss in liquidity event)
-/// and the lambda element from the [Loan](struct.Loan.html) struct.
+///   and the lambda element from the [Loan](struct.Loan.html) struct.
 /// * `q` - Probability of liquidity event (scaled
-/// by the total portfolio loss).
+///   by the total portfolio loss).
 /// * `expectation` - Base expectation for the portfolio
-/// without liquidity risk.  Can be computed using the
-/// [get_portfolio_expectation](struct.EconomicCapitalAttributes.html#method.get_portfolio_expectation)
-/// method.
+///   without liquidity risk.  Can be computed using the
+///   [get_portfolio_expectation](struct.EconomicCapitalAttributes.html#method.get_portfolio_expectation)
+///   method.
 ///
 /// # Examples
 /// ```
@@ -226,9 +358,9 @@ pub fn expectation_liquidity(lambda: f64, q: f64, expectation: f64) -> f64 {
 /// # Arguments
 ///
 /// * `lambda` - Sum of lambda0 (base loss in liquidity event)
-/// and the lambda element from the [Loan](struct.Loan.html) struct.
+///   and the lambda element from the [Loan](struct.Loan.html) struct.
 /// * `q` - Probability of liquidity event (scaled
-/// by the total portfolio loss).
+///   by the total portfolio loss).
 pub fn get_liquidity_risk_fn(lambda: f64, q: f64) -> impl Fn(&Complex<f64>) -> Complex<f64> {
     move |u: &Complex<f64>| u - ((-u * lambda).exp() - 1.0) * q
 }
@@ -241,9 +373,9 @@ pub fn get_liquidity_risk_fn(lambda: f64, q: f64) -> impl Fn(&Complex<f64>) -> C
 /// # Arguments
 ///
 /// * `lgd_cf` - The characteristic function for a given
-/// loan's loss given default.
+///   loan's loss given default.
 /// * `liquidity_cf` - The liquidity function typically
-/// instantiated from [get_liquidity_risk_fn](fn.get_liquidity_risk_fn.html).
+///   instantiated from [get_liquidity_risk_fn](fn.get_liquidity_risk_fn.html).
 pub fn get_log_lpm_cf<T, U>(
     lgd_cf: T,
     liquidity_cf: U,
@@ -257,8 +389,50 @@ where
     }
 }
 
+/// Returns the moment generating function of independent
+/// Gamma distributed systemic variables with expectation
+/// one, evaluated at the exponents of a single element of
+/// the u domain.  A variable with zero variance is the
+/// constant one, which holds idiosyncratic risk.  This is typically used as the
+/// `mgf_systemic` argument in
+/// [get_full_cf](struct.EconomicCapitalAttributes.html#method.get_full_cf)
+/// by closing over the variances.
+///
+/// # Arguments
+///
+/// * `u_weights` - The characteristic exponents for each
+///   systemic variable.
+/// * `variance` - The vector of variances for the
+///   systemic random variables.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// extern crate num_complex;
+/// use num_complex::Complex;
+/// # fn main(){
+/// let v = vec![0.4, 0.3];
+/// let systemic_mgf=|u_weights: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u_weights, &v);
+/// # }
+/// ```
+pub fn systemic_gamma_mgf(u_weights: &[Complex<f64>], variance: &[f64]) -> Complex<f64> {
+    u_weights
+        .iter()
+        .zip(variance)
+        .map(|(u, v)| {
+            if *v == 0.0 {
+                *u
+            } else {
+                -(1.0 - v * u).ln() / v
+            }
+        })
+        .sum::<Complex<f64>>()
+        .exp()
+}
+
 /// Holds the attributes for the entire
 /// portfolio.
+#[derive(Debug, Clone)]
 pub struct EconomicCapitalAttributes {
     /// Holds the characteristic function for the portfolio
     /// (without multiplying by the systemic variables).
@@ -286,13 +460,13 @@ pub struct EconomicCapitalAttributes {
 /// # Arguments
 ///
 /// * `el_vec` - The portfolio vector of expected
-/// loss.  Has the same size as the weight vector
-/// in the [Loan](struct.Loan.html) struct.
+///   loss.  Has the same size as the weight vector
+///   in the [Loan](struct.Loan.html) struct.
 /// * `el_sys` - The vector of expected values for
-/// the systemic random variables.  This is
-/// typically a vector of ones.  Has the same size
-/// as the weight vector in the
-/// [Loan](struct.Loan.html) struct.
+///   the systemic random variables.  This is
+///   typically a vector of ones.  Has the same size
+///   as the weight vector in the
+///   [Loan](struct.Loan.html) struct.
 fn portfolio_expectation(el_vec: &[f64], el_sys: &[f64]) -> f64 {
     el_vec
         .iter()
@@ -310,20 +484,20 @@ fn portfolio_expectation(el_vec: &[f64], el_sys: &[f64]) -> f64 {
 /// # Arguments
 ///
 /// * `el_vec` - The portfolio vector of expected
-/// loss.  Has the same size as the weight vector
-/// in the [Loan](struct.Loan.html) struct.
+///   loss.  Has the same size as the weight vector
+///   in the [Loan](struct.Loan.html) struct.
 /// * `el_sys` - The vector of expected values for
-/// the systemic random variables.  This is
-/// typically a vector of ones.  Has the same size
-/// as the weight vector in the
-/// [Loan](struct.Loan.html) struct.
+///   the systemic random variables.  This is
+///   typically a vector of ones.  Has the same size
+///   as the weight vector in the
+///   [Loan](struct.Loan.html) struct.
 /// * `var_vec` - The portfolio vector of
-/// variance.  Has the same size as the weight
-/// vector in the [Loan](struct.Loan.html) struct.
+///   variance.  Has the same size as the weight
+///   vector in the [Loan](struct.Loan.html) struct.
 /// * `var_sys` - The vector of variances for the
-/// systemic random variables.  Has the same size
-/// as the weight vector in the
-/// [Loan](struct.Loan.html) struct.
+///   systemic random variables.  Has the same size
+///   as the weight vector in the
+///   [Loan](struct.Loan.html) struct.
 fn portfolio_variance(el_vec: &[f64], el_sys: &[f64], var_vec: &[f64], var_sys: &[f64]) -> f64 {
     let v_p: f64 = var_vec
         .iter()
@@ -365,12 +539,12 @@ impl EconomicCapitalAttributes {
     ///
     /// * `loan` - An instance of the [Loan](struct.Loan.html) struct.
     /// * `u_domain` - The vector of complex values
-    /// provided to the characteristic function.  Is
-    /// typically generated by the
-    /// [fang_oost](https://github.com/phillyfan1138/fang_oost_rust)
-    /// repository.  See the example.
+    ///   provided to the characteristic function.  Is
+    ///   typically generated by the
+    ///   [fang_oost](https://github.com/phillyfan1138/fang_oost_rust)
+    ///   repository.  See the example.
     /// * `log_lpm_cf` - The result from calling
-    /// [get_log_lpm_cf](fn.get_log_lpm_cf.html).
+    ///   [get_log_lpm_cf](fn.get_log_lpm_cf.html).
     ///
     /// # Examples
     /// ```
@@ -410,29 +584,68 @@ impl EconomicCapitalAttributes {
         U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
     {
         let vec_of_cf_u: Vec<Complex<f64>> =
-            u_domain.par_iter().map(|u| log_lpm_cf(&u, loan)).collect();
-        let num_w = self.num_w;
-        self.cf
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(index, elem)| {
-                let row_num = vec_to_mat::get_row_from_index(index, num_w);
-                let col_num = vec_to_mat::get_col_from_index(index, num_w);
-                *elem += vec_of_cf_u[col_num] * loan.weight[row_num] * loan.num;
-            });
+            u_domain.par_iter().map(|u| log_lpm_cf(u, loan)).collect();
+        self.add_cf_increment(&vec_of_cf_u, &loan.weight, loan.num);
         self.el_vec
             .iter_mut()
             .zip(&loan.weight)
             .for_each(|(el, &w)| {
-                *el += get_el_from_loan(&loan, w);
+                *el += get_el_from_loan(loan, w);
             });
         self.var_vec
             .iter_mut()
             .zip(&loan.weight)
             .for_each(|(var, &w)| {
-                *var += get_var_from_loan(&loan, w);
+                *var += get_var_from_loan(loan, w);
+            });
+        self.lambda += get_lambda_from_loan(loan);
+    }
+    /// Removes a loan which was previously processed
+    /// (eg, a loan which matured).  Since every
+    /// attribute is linear in num, this processes the
+    /// loan with a negative num.
+    ///
+    /// # Arguments
+    ///
+    /// * `loan` - An instance of the [Loan](struct.Loan.html) struct
+    /// * `u_domain` - The vector of complex values
+    ///   provided to the characteristic function.
+    /// * `log_lpm_cf` - The result from calling
+    ///   [get_log_lpm_cf](fn.get_log_lpm_cf.html).
+    pub fn remove_loan<U>(&mut self, loan: &Loan, u_domain: &[Complex<f64>], log_lpm_cf: U)
+    where
+        U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        self.process_loan(
+            &Loan {
+                num: -loan.num,
+                ..loan.clone()
+            },
+            u_domain,
+            log_lpm_cf,
+        );
+    }
+    /// Adds the characteristic exponent of an exposure
+    /// to each systemic variable's chunk of cf.
+    ///
+    /// # Arguments
+    ///
+    /// * `vec_of_cf_u` - The characteristic exponent
+    ///   evaluated at each element of the u domain.
+    /// * `weight` - Vector of weights describing the
+    ///   exposure to each systemic variable.
+    /// * `num` - The number of exposures with these
+    ///   features.
+    fn add_cf_increment(&mut self, vec_of_cf_u: &[Complex<f64>], weight: &[f64], num: f64) {
+        let num_w = self.num_w;
+        self.cf
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(index, elem)| {
+                let row_num = vec_to_mat::get_row_from_index(index, num_w);
+                let col_num = vec_to_mat::get_col_from_index(index, num_w);
+                *elem += vec_of_cf_u[col_num] * weight[row_num] * num;
             });
-        self.lambda += get_lambda_from_loan(&loan);
     }
     /// Performs marginal analytics for a potential loan
     /// to the portfolio.  The typical use case is for
@@ -445,14 +658,14 @@ impl EconomicCapitalAttributes {
     /// # Arguments
     ///
     /// * `loan` - An instance of the [Loan](struct.Loan.html)
-    /// struct.  This is the loan to experiment on.  
+    ///   struct.  This is the loan to experiment on.  
     /// * `u_domain` - The vector of complex values
-    /// provided to the characteristic function.  Is
-    /// typically generated by the
-    /// [fang_oost](https://github.com/phillyfan1138/fang_oost_rust)
-    /// repository.  See the example.
+    ///   provided to the characteristic function.  Is
+    ///   typically generated by the
+    ///   [fang_oost](https://github.com/phillyfan1138/fang_oost_rust)
+    ///   repository.  See the example.
     /// * `log_lpm_cf` - The result from calling
-    /// [get_log_lpm_cf](fn.get_log_lpm_cf.html).
+    ///   [get_log_lpm_cf](fn.get_log_lpm_cf.html).
     ///
     /// # Examples
     /// ```
@@ -502,7 +715,7 @@ impl EconomicCapitalAttributes {
         U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
     {
         let vec_of_cf_u: Vec<Complex<f64>> =
-            u_domain.par_iter().map(|u| log_lpm_cf(&u, loan)).collect();
+            u_domain.par_iter().map(|u| log_lpm_cf(u, loan)).collect();
         let num_w = self.num_w;
         EconomicCapitalAttributes {
             cf: self
@@ -519,15 +732,15 @@ impl EconomicCapitalAttributes {
                 .el_vec
                 .iter()
                 .zip(&loan.weight)
-                .map(|(el, &w)| el + get_el_from_loan(&loan, w))
+                .map(|(el, &w)| el + get_el_from_loan(loan, w))
                 .collect::<Vec<_>>(),
             var_vec: self
                 .var_vec
                 .iter()
                 .zip(&loan.weight)
-                .map(|(var, &w)| var + get_var_from_loan(&loan, w))
+                .map(|(var, &w)| var + get_var_from_loan(loan, w))
                 .collect::<Vec<_>>(),
-            lambda: self.lambda + get_lambda_from_loan(&loan),
+            lambda: self.lambda + get_lambda_from_loan(loan),
             num_w,
         }
     }
@@ -542,28 +755,28 @@ impl EconomicCapitalAttributes {
     /// # Arguments
     ///
     /// * `loan` - An instance of the [Loan](struct.Loan.html) struct.  
-    /// This is the loan to experiment on.
+    ///   This is the loan to experiment on.
     /// * `u_domain` - The vector of complex values
-    /// provided to the characteristic function.  Is
-    /// typically generated by the
-    /// [fang_oost](https://github.com/phillyfan1138/fang_oost_rust)
-    /// repository.  See the example.
+    ///   provided to the characteristic function.  Is
+    ///   typically generated by the
+    ///   [fang_oost](https://github.com/phillyfan1138/fang_oost_rust)
+    ///   repository.  See the example.
     /// * `log_lpm_cf` - The result from calling
-    /// [get_log_lpm_cf](fn.get_log_lpm_cf.html).
+    ///   [get_log_lpm_cf](fn.get_log_lpm_cf.html).
     /// * `lambda0` - Base loss (in dollars) from a
-    /// liquidity event.  A positive number.
+    ///   liquidity event.  A positive number.
     /// * `q` - Probability of liquidity event (scaled
-    /// by the total portfolio loss).
+    ///   by the total portfolio loss).
     /// * `mgf_systemic` - Moment generating function
-    /// for the systemic random variables.  Typically
-    /// a function of el_sys and var_sys.
+    ///   for the systemic random variables.  Typically
+    ///   a function of el_sys and var_sys.
     /// * `el_sys` - The vector of expected values for
-    /// the systemic random variables.  This is
-    /// typically a vector of ones.
+    ///   the systemic random variables.  This is
+    ///   typically a vector of ones.
     /// * `var_sys` - The vector of variances for the
-    /// systemic random variables.
+    ///   systemic random variables.
     /// * `risk_measure_fn` - Function which computes
-    /// the risk measure for the portfolio, e.g. VaR.
+    ///   the risk measure for the portfolio, e.g. VaR.
     ///
     /// # Examples
     /// ```
@@ -632,6 +845,7 @@ impl EconomicCapitalAttributes {
     ///     &risk_measure_fn,
     /// );
     /// # }
+    #[allow(clippy::too_many_arguments)]
     pub fn experiment_risk_contribution<U, V, T>(
         &self,
         loan: &Loan,
@@ -684,8 +898,8 @@ impl EconomicCapitalAttributes {
     /// # Arguments
     ///
     /// * `el_sys` - The vector of expected values for
-    /// the systemic random variables.  This is
-    /// typically a vector of ones.
+    ///   the systemic random variables.  This is
+    ///   typically a vector of ones.
     ///
     /// # Examples
     /// ```
@@ -736,10 +950,10 @@ impl EconomicCapitalAttributes {
     /// # Arguments
     ///
     /// * `el_sys` - The vector of expected values for
-    /// the systemic random variables.  This is
-    /// typically a vector of ones.
+    ///   the systemic random variables.  This is
+    ///   typically a vector of ones.
     /// * `var_sys` - The vector of variances for the
-    /// systemic random variables.
+    ///   systemic random variables.
     ///
     /// # Examples
     /// ```
@@ -804,8 +1018,8 @@ impl EconomicCapitalAttributes {
     /// # Arguments
     ///
     /// * `mgf_systemic` - Moment generating function
-    /// for the systemic random variables.  Typically
-    /// a function of el_sys and var_sys.
+    ///   for the systemic random variables.  Typically
+    ///   a function of el_sys and var_sys.
     ///
     /// # Examples
     /// ```
@@ -857,6 +1071,114 @@ impl EconomicCapitalAttributes {
     {
         self.get_experiment_full_cf(&self.cf, mgf_systemic)
     }
+    /// Gets the discrete characteristic function
+    /// for the portfolio conditional on a realization
+    /// of the systemic random variables.  Since the
+    /// exponent is linear in the systemic variables,
+    /// this is exp(sum_k cf_k * s_k).  This should be
+    /// called after processing all the loans in the
+    /// portfolio.
+    ///
+    /// # Arguments
+    ///
+    /// * `factor_values` - The realization of each
+    ///   systemic random variable.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// extern crate cf_dist_utils;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=256;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.0,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 10000.0
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let factor_values = vec![2.0];
+    /// let cf = ec_attributes.get_conditional_cf(&factor_values);
+    /// let expectation = cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &cf);
+    /// assert!((expectation - ec_attributes.get_conditional_expectation(&factor_values)).abs() < 0.01);
+    /// # }
+    /// ```
+    pub fn get_conditional_cf(&self, factor_values: &[f64]) -> Vec<Complex<f64>> {
+        self.cf
+            .par_chunks(self.num_w)
+            .map(|u_weights| {
+                u_weights
+                    .iter()
+                    .zip(factor_values)
+                    .map(|(u, s)| u * s)
+                    .sum::<Complex<f64>>()
+                    .exp()
+            })
+            .collect()
+    }
+    /// Returns the expectation of the portfolio
+    /// conditional on a realization of the systemic
+    /// random variables (without liquidity risk).
+    ///
+    /// # Arguments
+    ///
+    /// * `factor_values` - The realization of each
+    ///   systemic random variable.
+    pub fn get_conditional_expectation(&self, factor_values: &[f64]) -> f64 {
+        portfolio_expectation(&self.el_vec, factor_values)
+    }
+    /// Returns the variance of the portfolio
+    /// conditional on a realization of the systemic
+    /// random variables (without liquidity risk).
+    ///
+    /// # Arguments
+    ///
+    /// * `factor_values` - The realization of each
+    ///   systemic random variable.
+    pub fn get_conditional_variance(&self, factor_values: &[f64]) -> f64 {
+        portfolio_expectation(&self.var_vec, factor_values)
+    }
+    /// Adds the loans of another portfolio to this
+    /// portfolio.  Since cf, el_vec, var_vec and lambda
+    /// are sums over the loans, portfolios processed
+    /// separately (eg, by segment or by period) can be
+    /// combined without reprocessing the loans.  Both
+    /// portfolios must use the same u domain and
+    /// number of systemic variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The portfolio to add.
+    pub fn merge(&mut self, other: &EconomicCapitalAttributes) {
+        self.cf
+            .par_iter_mut()
+            .zip(&other.cf)
+            .for_each(|(elem, other_elem)| *elem += other_elem);
+        self.el_vec
+            .iter_mut()
+            .zip(&other.el_vec)
+            .for_each(|(el, other_el)| *el += other_el);
+        self.var_vec
+            .iter_mut()
+            .zip(&other.var_vec)
+            .for_each(|(var, other_var)| *var += other_var);
+        self.lambda += other.lambda;
+    }
 }
 
 #[cfg(test)]
@@ -865,7 +1187,13 @@ fn gamma_mgf<'a, 'b: 'a>(variance: &'b [f64]) -> impl Fn(&[Complex<f64>]) -> Com
         u_weights
             .iter()
             .zip(variance)
-            .map(|(u, v)| -(1.0 - v * u).ln() / v)
+            .map(|(u, v)| {
+                if *v == 0.0 {
+                    *u
+                } else {
+                    -(1.0 - v * u).ln() / v
+                }
+            })
             .sum::<Complex<f64>>()
             .exp()
     }
@@ -876,6 +1204,85 @@ fn test_mgf(u_weights: &[Complex<f64>]) -> Complex<f64> {
     u_weights.iter().sum::<Complex<f64>>().exp()
 }
 
+#[cfg(test)]
+fn exponential_lgd_cf(u: &Complex<f64>, l: f64, _lgd_v: f64) -> Complex<f64> {
+    (-u * l).exp()
+}
+
+#[cfg(test)]
+fn gamma_lgd_cf(u: &Complex<f64>, l: f64, lgd_v: f64) -> Complex<f64> {
+    cf_functions::gamma::gamma_cf(&(-u * l), 1.0 / lgd_v, lgd_v)
+}
+
+#[cfg(test)]
+fn get_test_u_domain(num_u: usize, x_min: f64, x_max: f64) -> Vec<Complex<f64>> {
+    fang_oost::get_u_domain(num_u, x_min, x_max).collect()
+}
+
+#[cfg(test)]
+fn get_test_log_lpm_cf(
+    lambda: f64,
+    q: f64,
+) -> impl Fn(&Complex<f64>, &Loan) -> Complex<f64> + Sync + Send {
+    get_log_lpm_cf(exponential_lgd_cf, get_liquidity_risk_fn(lambda, q))
+}
+
+#[cfg(test)]
+fn get_test_loan(weight: Vec<f64>, num: f64) -> Loan {
+    Loan {
+        pd: 0.05,
+        lgd: 0.5,
+        balance: 1.0,
+        r: 0.0,
+        lgd_variance: 0.0,
+        weight,
+        num,
+    }
+}
+
+#[cfg(test)]
+fn get_test_loans() -> Vec<Loan> {
+    vec![
+        Loan {
+            pd: 0.05,
+            lgd: 0.5,
+            balance: 1.0,
+            r: 0.0,
+            lgd_variance: 0.2,
+            weight: vec![0.4, 0.6],
+            num: 6000.0,
+        },
+        Loan {
+            pd: 0.03,
+            lgd: 0.4,
+            balance: 2.0,
+            r: 0.0,
+            lgd_variance: 0.2,
+            weight: vec![0.3, 0.7],
+            num: 4000.0,
+        },
+    ]
+}
+
+#[cfg(test)]
+fn process_test_loans<T>(
+    lgd_cf: T,
+    lambda: f64,
+    q: f64,
+    u_domain: &[Complex<f64>],
+    loans: &[Loan],
+) -> EconomicCapitalAttributes
+where
+    T: Fn(&Complex<f64>, f64, f64) -> Complex<f64> + Sync + Send,
+{
+    let log_lpm_cf = get_log_lpm_cf(lgd_cf, get_liquidity_risk_fn(lambda, q));
+    let mut ec = EconomicCapitalAttributes::new(u_domain.len(), loans[0].weight.len());
+    loans
+        .iter()
+        .for_each(|loan| ec.process_loan(loan, u_domain, &log_lpm_cf));
+    ec
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -903,14 +1310,95 @@ mod tests {
         };
         let log_lpm_cf = |_u: &Complex<f64>, _loan: &Loan| Complex::new(1.0, 0.0);
         let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(256, 0.0, 1.0).collect();
-        discrete_cf.process_loan(&loan, &u_domain, &log_lpm_cf);
+        discrete_cf.process_loan(&loan, &u_domain, log_lpm_cf);
         let cf = discrete_cf.cf;
         assert_eq!(cf.len(), 256 * 3);
         cf.iter().for_each(|cf_el| {
-            assert_eq!(cf_el, &Complex::new(0.5 as f64, 0.0 as f64));
+            assert_eq!(cf_el, &Complex::new(0.5_f64, 0.0_f64));
         });
     }
     #[test]
+    fn test_merge_matches_processing_both_loans() {
+        let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(256, -5000.0, 0.0).collect();
+        let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+        let liquid_fn = get_liquidity_risk_fn(100.0, 0.0001);
+        let log_lpm_cf = get_log_lpm_cf(&lgd_fn, &liquid_fn);
+        let loan1 = Loan {
+            pd: 0.05,
+            lgd: 0.5,
+            balance: 1.0,
+            lgd_variance: 0.0,
+            r: 0.1,
+            weight: vec![0.4, 0.6],
+            num: 1000.0,
+        };
+        let loan2 = Loan {
+            pd: 0.03,
+            lgd: 0.4,
+            balance: 2.0,
+            lgd_variance: 0.0,
+            r: 0.2,
+            weight: vec![0.3, 0.7],
+            num: 2000.0,
+        };
+        let mut discrete_cf = EconomicCapitalAttributes::new(256, 2);
+        discrete_cf.process_loan(&loan1, &u_domain, &log_lpm_cf);
+        discrete_cf.process_loan(&loan2, &u_domain, &log_lpm_cf);
+        let mut merged_cf = EconomicCapitalAttributes::new(256, 2);
+        merged_cf.process_loan(&loan1, &u_domain, &log_lpm_cf);
+        let mut other_cf = EconomicCapitalAttributes::new(256, 2);
+        other_cf.process_loan(&loan2, &u_domain, &log_lpm_cf);
+        merged_cf.merge(&other_cf);
+        discrete_cf
+            .cf
+            .iter()
+            .zip(&merged_cf.cf)
+            .for_each(|(cf_d, cf_m)| {
+                assert_abs_diff_eq!(cf_d.re, cf_m.re, epsilon = 0.0000001);
+                assert_abs_diff_eq!(cf_d.im, cf_m.im, epsilon = 0.0000001);
+            });
+        assert_eq!(discrete_cf.el_vec, merged_cf.el_vec);
+        assert_eq!(discrete_cf.var_vec, merged_cf.var_vec);
+        assert_abs_diff_eq!(discrete_cf.lambda, merged_cf.lambda, epsilon = 0.0000001);
+    }
+    #[test]
+    fn test_conditional_cf_moments() {
+        let num_u: usize = 1024;
+        let x_min = -10000.0;
+        let x_max = 0.0;
+        let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+        let lgd_fn = |u: &Complex<f64>, l: f64, lgd_v: f64| {
+            cf_functions::gamma::gamma_cf(&(-u * l), 1.0 / lgd_v, lgd_v)
+        };
+        let liquid_fn = get_liquidity_risk_fn(0.0, 0.0);
+        let log_lpm_cf = get_log_lpm_cf(&lgd_fn, &liquid_fn);
+        let loan = Loan {
+            pd: 0.05,
+            lgd: 0.5,
+            balance: 1.0,
+            lgd_variance: 0.2,
+            r: 0.0,
+            weight: vec![0.4, 0.6],
+            num: 10000.0,
+        };
+        let mut discrete_cf = EconomicCapitalAttributes::new(num_u, 2);
+        discrete_cf.process_loan(&loan, &u_domain, &log_lpm_cf);
+        let factor_values = vec![3.0, 0.5];
+        let cf = discrete_cf.get_conditional_cf(&factor_values);
+        let expectation = cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &cf);
+        let variance = cf_dist_utils::get_variance_discrete_cf(x_min, x_max, &cf);
+        assert_abs_diff_eq!(
+            expectation,
+            discrete_cf.get_conditional_expectation(&factor_values),
+            epsilon = 0.001
+        );
+        assert_abs_diff_eq!(
+            variance,
+            discrete_cf.get_conditional_variance(&factor_values),
+            epsilon = 0.1
+        );
+    }
+    #[test]
     fn test_process_loans_with_final() {
         let mut discrete_cf = EconomicCapitalAttributes::new(256, 3);
         let loan = Loan {
@@ -924,12 +1412,12 @@ mod tests {
         };
         let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(256, 0.0, 1.0).collect();
         let log_lpm_cf = |_u: &Complex<f64>, _loan: &Loan| Complex::new(1.0, 0.0);
-        discrete_cf.process_loan(&loan, &u_domain, &log_lpm_cf);
+        discrete_cf.process_loan(&loan, &u_domain, log_lpm_cf);
         let final_cf: Vec<Complex<f64>> = discrete_cf.get_full_cf(&test_mgf);
 
         assert_eq!(final_cf.len(), 256);
         final_cf.iter().for_each(|cf_el| {
-            assert_eq!(cf_el, &Complex::new(1.5 as f64, 0.0 as f64).exp());
+            assert_eq!(cf_el, &Complex::new(1.5_f64, 0.0_f64).exp());
         });
     }
     #[test]
@@ -1017,7 +1505,7 @@ mod tests {
 
         assert_abs_diff_eq!(expectation_approx, expectation, epsilon = 0.00001);
         assert_abs_diff_eq!(
-            expectation_liquidity(lambda, q, discrete_cf.get_portfolio_expectation(&vec![1.0])),
+            expectation_liquidity(lambda, q, discrete_cf.get_portfolio_expectation(&[1.0])),
             expectation,
             epsilon = 0.00001
         );
@@ -1775,7 +2263,7 @@ mod tests {
             &systemic_mgf,
             &systemic_expectation,
             &v,
-            &risk_measure_fn,
+            risk_measure_fn,
         );
 
         let EconomicCapitalAttributes {
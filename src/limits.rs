@@ -0,0 +1,486 @@
+//! Limit setting under an economic capital budget.
+//! Candidate exposures are evaluated with
+//! [experiment_loan](../struct.EconomicCapitalAttributes.html#method.experiment_loan)
+//! so that the portfolio is never rebuilt for a trial.
+
+use crate::error::EconomicCapitalError;
+use crate::pricing::{PricingParameters, PricingResult};
+use crate::{EconomicCapitalAttributes, Loan};
+use num_complex::Complex;
+
+const BUDGET_ERROR: &str = "Current portfolio already exceeds the budget.";
+const LIMIT_ERROR: &str = "Limit search did not converge.";
+
+/// Maximum exposure that can be added to a segment.
+#[derive(Debug, Clone)]
+pub struct ExposureLimit {
+    /// Number of loans like the template that can
+    /// be added.
+    pub num: f64,
+    /// Exposure (balance times num) that can be added.
+    pub exposure: f64,
+    /// Value of the risk measure with the added
+    /// exposure.
+    pub risk_measure: f64,
+}
+
+/// A segment competing for capital.
+#[derive(Debug, Clone)]
+pub struct CandidateSegment {
+    /// Loan describing an increment to the segment.
+    /// The `num` of the template is the size of the
+    /// increment.
+    pub loan: Loan,
+    /// Spread charged on the segment.
+    pub spread: f64,
+    /// Maximum number of increments that can be
+    /// originated.
+    pub max_increments: usize,
+}
+
+/// Result of allocating a capital budget across
+/// segments.
+#[derive(Debug, Clone)]
+pub struct CapitalAllocation {
+    /// Number of increments allocated to each segment.
+    pub increments: Vec<usize>,
+    /// Pricing of the last increment allocated to
+    /// each segment, if any.
+    pub marginal_pricing: Vec<Option<PricingResult>>,
+    /// Value of the risk measure after the allocation.
+    pub risk_measure: f64,
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the risk measure after adding `num`
+    /// loans like the template.
+    fn get_experiment_risk_measure<U, V, T>(
+        &self,
+        template: &Loan,
+        num: f64,
+        u_domain: &[Complex<f64>],
+        log_lpm_cf: &U,
+        mgf_systemic: &V,
+        risk_measure_fn: &T,
+    ) -> f64
+    where
+        U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        V: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64,
+    {
+        let loan = Loan {
+            num,
+            ..template.clone()
+        };
+        let EconomicCapitalAttributes { cf, .. } =
+            self.experiment_loan(&loan, u_domain, log_lpm_cf);
+        risk_measure_fn(&self.get_experiment_full_cf(&cf, mgf_systemic))
+    }
+    /// Returns the maximum number of loans like the
+    /// template which can be added to the portfolio
+    /// while keeping the risk measure (eg, expected
+    /// shortfall) under the budget.  The number is
+    /// found by bisection after bracketing.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - Loan with the balance, pd, lgd
+    ///   and weight profile of the segment.  Its `num`
+    ///   is ignored.
+    /// * `u_domain` - Domain of the characteristic function.
+    /// * `log_lpm_cf` - Log of the characteristic
+    ///   function of the loan.
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables.
+    /// * `risk_measure_fn` - Function which computes
+    ///   the risk measure for the portfolio.
+    /// * `budget` - Maximum value of the risk measure.
+    /// * `tolerance` - Tolerance on the number of loans.
+    /// * `max_iterations` - Maximum number of iterations
+    ///   for bracketing and for bisection.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// extern crate cf_dist_utils;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=128;
+    /// let x_min=-10000.0;
+    /// let x_max=0.0;
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.0,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 10000.0
+    /// };
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let v = vec![0.4];
+    /// let systemic_mgf = |u_weights: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u_weights, &v);
+    /// let risk_measure_fn = |final_cf: &[Complex<f64>]| {
+    ///     let cf_dist_utils::RiskMetric{expected_shortfall, ..} = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+    ///         0.01, x_min, x_max, 100, 0.0001, final_cf,
+    ///     ).unwrap();
+    ///     expected_shortfall
+    /// };
+    /// let budget = risk_measure_fn(&ec_attributes.get_full_cf(&systemic_mgf)) + 100.0;
+    /// let template=loan_ec::Loan{
+    ///     num: 1.0,
+    ///     ..loan
+    /// };
+    /// let limit = ec_attributes.get_exposure_limit(
+    ///     &template, &u_domain, &log_lpm_cf, &systemic_mgf,
+    ///     &risk_measure_fn, budget, 0.1, 100
+    /// ).unwrap();
+    /// assert!(limit.risk_measure <= budget);
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_exposure_limit<U, V, T>(
+        &self,
+        template: &Loan,
+        u_domain: &[Complex<f64>],
+        log_lpm_cf: &U,
+        mgf_systemic: &V,
+        risk_measure_fn: &T,
+        budget: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<ExposureLimit, EconomicCapitalError>
+    where
+        U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        V: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64,
+    {
+        let risk_measure_at = |num: f64| {
+            self.get_experiment_risk_measure(
+                template,
+                num,
+                u_domain,
+                log_lpm_cf,
+                mgf_systemic,
+                risk_measure_fn,
+            )
+        };
+        if risk_measure_fn(&self.get_full_cf(mgf_systemic)) > budget {
+            return Err(EconomicCapitalError::new(BUDGET_ERROR));
+        }
+        let mut low = 0.0;
+        let mut high = 1.0;
+        let mut iteration = 0;
+        while risk_measure_at(high) <= budget {
+            low = high;
+            high *= 2.0;
+            iteration += 1;
+            if iteration > max_iterations {
+                return Err(EconomicCapitalError::new(LIMIT_ERROR));
+            }
+        }
+        for _ in 0..max_iterations {
+            if high - low < tolerance {
+                return Ok(ExposureLimit {
+                    num: low,
+                    exposure: low * template.balance,
+                    risk_measure: risk_measure_at(low),
+                });
+            }
+            let mid = 0.5 * (low + high);
+            if risk_measure_at(mid) <= budget {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Err(EconomicCapitalError::new(LIMIT_ERROR))
+    }
+    /// Allocates a budget for the risk measure across
+    /// segments greedily.  At each step the increment
+    /// with the highest RAROC (computed from its
+    /// marginal risk contribution with
+    /// [price_loan](../struct.EconomicCapitalAttributes.html#method.price_loan))
+    /// is added, provided the RAROC is at least the
+    /// hurdle rate and the risk measure stays under
+    /// the budget.  Increments without a RAROC (no
+    /// positive economic capital) are skipped.  The
+    /// portfolio is not modified; the allocated
+    /// increments are processed into a copy.
+    ///
+    /// # Arguments
+    ///
+    /// * `candidates` - The segments competing for capital.
+    /// * `u_domain` - Domain of the characteristic function.
+    /// * `log_lpm_cf` - Log of the characteristic
+    ///   function of the loan.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables.
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    /// * `var_sys` - The vector of variances for the
+    ///   systemic random variables.
+    /// * `risk_measure_fn` - Function which computes
+    ///   the risk measure for the portfolio.
+    /// * `parameters` - Costs and hurdle rate.
+    /// * `budget` - Maximum value of the risk measure.
+    #[allow(clippy::too_many_arguments)]
+    pub fn allocate_capital<U, V, T>(
+        &self,
+        candidates: &[CandidateSegment],
+        u_domain: &[Complex<f64>],
+        log_lpm_cf: &U,
+        lambda0: f64,
+        q: f64,
+        mgf_systemic: &V,
+        el_sys: &[f64],
+        var_sys: &[f64],
+        risk_measure_fn: &T,
+        parameters: &PricingParameters,
+        budget: f64,
+    ) -> CapitalAllocation
+    where
+        U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        V: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64 + std::marker::Sync + std::marker::Send,
+    {
+        let mut portfolio = self.clone();
+        let mut increments = vec![0; candidates.len()];
+        let mut marginal_pricing = vec![None; candidates.len()];
+        let mut available: Vec<bool> = candidates
+            .iter()
+            .map(|candidate| candidate.max_increments > 0)
+            .collect();
+        loop {
+            let best = candidates
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| available[*index])
+                .map(|(index, candidate)| {
+                    let pricing = portfolio.price_loan(
+                        &candidate.loan,
+                        u_domain,
+                        log_lpm_cf,
+                        lambda0,
+                        q,
+                        mgf_systemic,
+                        el_sys,
+                        var_sys,
+                        risk_measure_fn,
+                        parameters,
+                        candidate.spread,
+                    );
+                    (index, pricing)
+                })
+                .filter_map(|(index, pricing)| match pricing.raroc {
+                    Some(raroc) if raroc >= parameters.hurdle_rate => Some((index, raroc, pricing)),
+                    _ => None,
+                })
+                .max_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+                .map(|(index, _, pricing)| (index, pricing));
+            let (index, pricing) = match best {
+                Some(best) => best,
+                None => break,
+            };
+            let candidate = &candidates[index];
+            let risk_measure = portfolio.get_experiment_risk_measure(
+                &candidate.loan,
+                candidate.loan.num,
+                u_domain,
+                log_lpm_cf,
+                mgf_systemic,
+                risk_measure_fn,
+            );
+            if risk_measure > budget {
+                available[index] = false;
+                continue;
+            }
+            portfolio.process_loan(&candidate.loan, u_domain, log_lpm_cf);
+            increments[index] += 1;
+            marginal_pricing[index] = Some(pricing);
+            if increments[index] >= candidate.max_increments {
+                available[index] = false;
+            }
+        }
+        CapitalAllocation {
+            increments,
+            marginal_pricing,
+            risk_measure: risk_measure_fn(&portfolio.get_full_cf(mgf_systemic)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    const X_MIN: f64 = -10000.0;
+    const X_MAX: f64 = 0.0;
+    fn risk_measure_fn(final_cf: &[Complex<f64>]) -> f64 {
+        let cf_dist_utils::RiskMetric {
+            expected_shortfall, ..
+        } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+            0.01, X_MIN, X_MAX, 100, 0.0001, final_cf,
+        )
+        .unwrap();
+        expected_shortfall
+    }
+    fn log_lpm_cf(u: &Complex<f64>, loan: &Loan) -> Complex<f64> {
+        get_test_log_lpm_cf(0.0, 0.0)(u, loan)
+    }
+    fn get_portfolio(num_u: usize) -> (EconomicCapitalAttributes, Vec<Complex<f64>>) {
+        let u_domain = get_test_u_domain(num_u, X_MIN, X_MAX);
+        let loan = get_test_loan(vec![0.5, 0.5], 5000.0);
+        let ec = process_test_loans(exponential_lgd_cf, 0.0, 0.0, &u_domain, &[loan]);
+        (ec, u_domain)
+    }
+    #[test]
+    fn exposure_limit_meets_budget() {
+        let (ec, u_domain) = get_portfolio(256);
+        let v = vec![0.4, 0.3];
+        let v_mgf = gamma_mgf(&v);
+        let base = risk_measure_fn(&ec.get_full_cf(&v_mgf));
+        let budget = base + 200.0;
+        let template = Loan {
+            pd: 0.02,
+            lgd: 0.4,
+            balance: 1.0,
+            r: 0.0,
+            lgd_variance: 0.0,
+            weight: vec![1.0, 0.0],
+            num: 1.0,
+        };
+        let limit = ec
+            .get_exposure_limit(
+                &template,
+                &u_domain,
+                &log_lpm_cf,
+                &v_mgf,
+                &risk_measure_fn,
+                budget,
+                0.01,
+                100,
+            )
+            .unwrap();
+        assert!(limit.num > 0.0);
+        assert!(limit.risk_measure <= budget);
+        assert_abs_diff_eq!(limit.risk_measure, budget, epsilon = 2.0);
+        assert!(ec
+            .get_exposure_limit(
+                &template,
+                &u_domain,
+                &log_lpm_cf,
+                &v_mgf,
+                &risk_measure_fn,
+                base - 1.0,
+                0.01,
+                100,
+            )
+            .is_err());
+    }
+    #[test]
+    fn allocation_prefers_higher_raroc_and_respects_budget() {
+        let (ec, u_domain) = get_portfolio(256);
+        let v = vec![0.4, 0.3];
+        let v_mgf = gamma_mgf(&v);
+        let base = risk_measure_fn(&ec.get_full_cf(&v_mgf));
+        let budget = base + 100.0;
+        let increment = |spread: f64| CandidateSegment {
+            loan: Loan {
+                pd: 0.02,
+                lgd: 0.4,
+                balance: 1.0,
+                r: 0.0,
+                lgd_variance: 0.0,
+                weight: vec![0.0, 1.0],
+                num: 100.0,
+            },
+            spread,
+            max_increments: 50,
+        };
+        let candidates = vec![increment(0.03), increment(0.06)];
+        let parameters = pricing::PricingParameters {
+            funding_cost: 0.01,
+            operating_cost: 0.005,
+            hurdle_rate: 0.1,
+            tax_rate: 0.25,
+            capital_return: 0.0,
+        };
+        let allocation = ec.allocate_capital(
+            &candidates,
+            &u_domain,
+            &log_lpm_cf,
+            0.0,
+            0.0,
+            &v_mgf,
+            &[1.0, 1.0],
+            &v,
+            &risk_measure_fn,
+            &parameters,
+            budget,
+        );
+        assert!(allocation.increments[1] > 0);
+        assert!(allocation.increments[1] >= allocation.increments[0]);
+        assert!(allocation.risk_measure <= budget);
+        let pricing = allocation.marginal_pricing[1].as_ref().unwrap();
+        assert!(pricing.raroc.unwrap() >= parameters.hurdle_rate);
+    }
+    #[test]
+    fn allocation_skips_candidates_without_capital() {
+        let (ec, u_domain) = get_portfolio(256);
+        let v = vec![0.4, 0.3];
+        let v_mgf = gamma_mgf(&v);
+        let budget = risk_measure_fn(&ec.get_full_cf(&v_mgf)) + 100.0;
+        let increment = |pd: f64| CandidateSegment {
+            loan: Loan {
+                pd,
+                lgd: 0.4,
+                balance: 1.0,
+                r: 0.0,
+                lgd_variance: 0.0,
+                weight: vec![0.0, 1.0],
+                num: 100.0,
+            },
+            spread: 0.06,
+            max_increments: 5,
+        };
+        let candidates = vec![increment(0.0), increment(0.02)];
+        let parameters = pricing::PricingParameters {
+            funding_cost: 0.01,
+            operating_cost: 0.005,
+            hurdle_rate: 0.1,
+            tax_rate: 0.25,
+            capital_return: 0.0,
+        };
+        let allocation = ec.allocate_capital(
+            &candidates,
+            &u_domain,
+            &log_lpm_cf,
+            0.0,
+            0.0,
+            &v_mgf,
+            &[1.0, 1.0],
+            &v,
+            &risk_measure_fn,
+            &parameters,
+            budget,
+        );
+        assert_eq!(allocation.increments[0], 0);
+        assert!(allocation.marginal_pricing[0].is_none());
+        assert!(allocation.increments[1] > 0);
+    }
+}
@@ -0,0 +1,427 @@
+//! Losses from rating migration as well as default.
+//! Each loan has a rating; moving to another rating
+//! revalues the loan by its spread duration times the
+//! change in credit spread, and moving to default loses
+//! the loss given default.  As for default, every
+//! transition is a Poisson event.  Downgrades and
+//! default have intensities scaling with the systemic
+//! variables through the loan's `weight`, while upgrades
+//! scale through a separate `upgrade_weight`, so that
+//! a bad systemic outcome does not also make gains more
+//! likely.  The characteristic function of the combined
+//! loss is still conditioned on the systemic variables:
+//! sum_{j down} p_ij (exp(-u dV_ij)-1) + p_iD (lgd_cf(u)-1)
+//! for the `weight` and sum_{j up} p_ij (exp(-u dV_ij)-1)
+//! for the `upgrade_weight`.  Putting all of the
+//! `upgrade_weight` on a systemic variable with zero
+//! variance keeps upgrades idiosyncratic.
+
+use crate::error::EconomicCapitalError;
+use crate::{default_one, default_zero, get_lambda_from_loan, EconomicCapitalAttributes, Loan};
+use num_complex::Complex;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+
+/// Rating transition matrix and credit spreads.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MigrationModel {
+    /// Names of the (non-default) ratings.
+    pub ratings: Vec<String>,
+    /// Probability of moving from each rating (row) to
+    /// each rating (column) over the horizon.  Each row
+    /// has one more column than there are ratings; the
+    /// last column is the probability of default.
+    pub transition: Vec<Vec<f64>>,
+    /// Credit spread of each rating.
+    pub spreads: Vec<f64>,
+}
+
+/// Struct for a loan with a rating.  The
+/// probability of default comes from the rating.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MigrationLoan {
+    /// Expected value of the loss given
+    /// default for the loan.  A positive
+    /// number.
+    pub lgd: f64,
+    /// Balance (in dollars) of the loan.
+    pub balance: f64,
+    /// Vector of weights describing the
+    /// exposure of the downgrade and default
+    /// intensities to each systemic variable.
+    pub weight: Vec<f64>,
+    /// Vector of weights describing the
+    /// exposure of the upgrade intensities to
+    /// each systemic variable.
+    pub upgrade_weight: Vec<f64>,
+    #[serde(default = "default_zero")]
+    /// Amount of liquidity exposure as a
+    /// percent of the balance.  A positive
+    /// number.
+    pub r: f64,
+    #[serde(default = "default_zero")]
+    /// Variance of the loss given
+    /// default for the loan.
+    pub lgd_variance: f64,
+    #[serde(default = "default_one")]
+    /// The number of loans that have these
+    /// features.
+    pub num: f64,
+    /// Current rating of the loan.
+    pub rating: String,
+    /// Sensitivity of the loan's value (per unit of
+    /// balance) to its credit spread.
+    pub spread_duration: f64,
+}
+
+/// A rating transition and its loss for a single
+/// loan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    /// Probability of the transition.
+    pub probability: f64,
+    /// Loss (in dollars) from revaluation.  Negative
+    /// for upgrades.
+    pub loss: f64,
+}
+
+impl MigrationLoan {
+    /// Returns the loan with a given probability of
+    /// default.
+    ///
+    /// # Arguments
+    ///
+    /// * `pd` - Probability of default of the rating.
+    pub fn get_default_loan(&self, pd: f64) -> Loan {
+        Loan {
+            pd,
+            lgd: self.lgd,
+            balance: self.balance,
+            weight: self.weight.clone(),
+            r: self.r,
+            lgd_variance: self.lgd_variance,
+            num: self.num,
+        }
+    }
+}
+
+impl MigrationModel {
+    /// Returns the non-default transitions of a loan
+    /// and its probability of default.
+    ///
+    /// # Arguments
+    ///
+    /// * `migration_loan` - The loan and its rating.
+    pub fn get_transitions(
+        &self,
+        migration_loan: &MigrationLoan,
+    ) -> Result<(Vec<Transition>, f64), EconomicCapitalError> {
+        let num_ratings = self.ratings.len();
+        if self.spreads.len() != num_ratings
+            || self.transition.len() != num_ratings
+            || self
+                .transition
+                .iter()
+                .any(|row| row.len() != num_ratings + 1)
+        {
+            return Err(EconomicCapitalError::new(
+                "Transition matrix must have a row per rating and a column per rating plus default.",
+            ));
+        }
+        let index = self
+            .ratings
+            .iter()
+            .position(|rating| *rating == migration_loan.rating)
+            .ok_or_else(|| EconomicCapitalError::new("Rating is not in the migration model."))?;
+        let row = &self.transition[index];
+        let value_per_spread = migration_loan.balance * migration_loan.spread_duration;
+        let transitions = row
+            .iter()
+            .zip(&self.spreads)
+            .enumerate()
+            .filter(|(to, (probability, _))| *to != index && **probability > 0.0)
+            .map(|(_, (probability, spread))| Transition {
+                probability: *probability,
+                loss: value_per_spread * (spread - self.spreads[index]),
+            })
+            .collect();
+        Ok((transitions, row[num_ratings]))
+    }
+}
+
+impl EconomicCapitalAttributes {
+    /// Adds a loan with migration and default risk to
+    /// the portfolio.  The loss given default is
+    /// described by `lgd_cf` as in
+    /// [get_log_lpm_cf](../fn.get_log_lpm_cf.html).
+    ///
+    /// # Arguments
+    ///
+    /// * `migration_loan` - The loan and its rating.
+    /// * `model` - The transition matrix and spreads.
+    /// * `u_domain` - The vector of complex values
+    ///   provided to the characteristic function.
+    /// * `lgd_cf` - The characteristic function for a
+    ///   given loan's loss given default.
+    /// * `liquidity_cf` - The liquidity function
+    ///   typically instantiated from
+    ///   [get_liquidity_risk_fn](../fn.get_liquidity_risk_fn.html).
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let model: loan_ec::migration::MigrationModel = serde_json::from_str(r#"{
+    ///     "ratings": ["A", "B"],
+    ///     "transition": [[0.9, 0.09, 0.01], [0.05, 0.85, 0.1]],
+    ///     "spreads": [0.01, 0.03]
+    /// }"#).unwrap();
+    /// let migration_loan: loan_ec::migration::MigrationLoan = serde_json::from_str(r#"{
+    ///     "lgd": 0.5, "balance": 1.0, "weight": [1.0], "upgrade_weight": [1.0],
+    ///     "num": 1000.0, "rating": "A", "spread_duration": 4.0
+    /// }"#).unwrap();
+    /// let num_u = 128;
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, -500.0, 50.0).collect();
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let mut ec_attributes = loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// ec_attributes.process_migration_loan(
+    ///     &migration_loan, &model, &u_domain, &lgd_fn, &liquid_fn
+    /// ).unwrap();
+    /// //default: 1000*0.01*0.5, downgrade: 1000*0.09*4*0.02
+    /// assert!((ec_attributes.get_portfolio_expectation(&[1.0]) + 12.2).abs() < 0.0000001);
+    /// # }
+    /// ```
+    pub fn process_migration_loan<T, U>(
+        &mut self,
+        migration_loan: &MigrationLoan,
+        model: &MigrationModel,
+        u_domain: &[Complex<f64>],
+        lgd_cf: T,
+        liquidity_cf: U,
+    ) -> Result<(), EconomicCapitalError>
+    where
+        T: Fn(&Complex<f64>, f64, f64) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        U: Fn(&Complex<f64>) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        if migration_loan.upgrade_weight.len() != migration_loan.weight.len() {
+            return Err(EconomicCapitalError::new(
+                "Upgrade weight must have an element per systemic variable.",
+            ));
+        }
+        let (transitions, pd) = model.get_transitions(migration_loan)?;
+        let (upgrades, downgrades): (Vec<Transition>, Vec<Transition>) = transitions
+            .into_iter()
+            .partition(|transition| transition.loss < 0.0);
+        let loan = migration_loan.get_default_loan(pd);
+        let loss_given_default = loan.lgd * loan.balance;
+        let get_cf_u = |transitions: &[Transition], u_liquid: &Complex<f64>| {
+            transitions
+                .iter()
+                .map(|Transition { probability, loss }| {
+                    ((-u_liquid * loss).exp() - 1.0) * probability
+                })
+                .sum::<Complex<f64>>()
+        };
+        let (downgrade_cf_u, upgrade_cf_u): (Vec<Complex<f64>>, Vec<Complex<f64>>) = u_domain
+            .par_iter()
+            .map(|u| {
+                let u_liquid = liquidity_cf(u);
+                (
+                    get_cf_u(&downgrades, &u_liquid)
+                        + (lgd_cf(&u_liquid, loss_given_default, loan.lgd_variance) - 1.0) * pd,
+                    get_cf_u(&upgrades, &u_liquid),
+                )
+            })
+            .unzip();
+        self.add_cf_increment(&downgrade_cf_u, &loan.weight, loan.num);
+        self.add_cf_increment(&upgrade_cf_u, &migration_loan.upgrade_weight, loan.num);
+        let get_moments = |transitions: &[Transition], init: (f64, f64)| {
+            transitions
+                .iter()
+                .fold(init, |(first, second), Transition { probability, loss }| {
+                    (
+                        first + probability * loss,
+                        second + probability * loss.powi(2),
+                    )
+                })
+        };
+        let (downgrade_first, downgrade_second) = get_moments(
+            &downgrades,
+            (
+                pd * loss_given_default,
+                pd * (1.0 + loan.lgd_variance) * loss_given_default.powi(2),
+            ),
+        );
+        let (upgrade_first, upgrade_second) = get_moments(&upgrades, (0.0, 0.0));
+        self.el_vec
+            .iter_mut()
+            .zip(loan.weight.iter().zip(&migration_loan.upgrade_weight))
+            .for_each(|(el, (&w, &upgrade_w))| {
+                *el -= (downgrade_first * w + upgrade_first * upgrade_w) * loan.num
+            });
+        self.var_vec
+            .iter_mut()
+            .zip(loan.weight.iter().zip(&migration_loan.upgrade_weight))
+            .for_each(|(var, (&w, &upgrade_w))| {
+                *var += (downgrade_second * w + upgrade_second * upgrade_w) * loan.num
+            });
+        self.lambda += get_lambda_from_loan(&loan);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_model(migration: f64) -> MigrationModel {
+        MigrationModel {
+            ratings: vec!["A".to_string(), "B".to_string(), "C".to_string()],
+            transition: vec![
+                vec![0.99 - 2.0 * migration, migration, migration, 0.01],
+                vec![migration, 0.97 - 2.0 * migration, migration, 0.03],
+                vec![migration, migration, 0.9 - 2.0 * migration, 0.1],
+            ],
+            spreads: vec![0.01, 0.02, 0.05],
+        }
+    }
+    fn get_migration_loan(rating: &str) -> MigrationLoan {
+        MigrationLoan {
+            lgd: 0.5,
+            balance: 1.0,
+            weight: vec![0.4, 0.6, 0.0],
+            upgrade_weight: vec![0.0, 0.0, 1.0],
+            r: 0.0,
+            lgd_variance: 0.0,
+            num: 1000.0,
+            rating: rating.to_string(),
+            spread_duration: 5.0,
+        }
+    }
+    #[test]
+    fn transitions_revalue_by_spread() {
+        let model = get_model(0.02);
+        let (transitions, pd) = model.get_transitions(&get_migration_loan("B")).unwrap();
+        assert_eq!(pd, 0.03);
+        assert_eq!(transitions.len(), 2);
+        assert_abs_diff_eq!(transitions[0].loss, -0.05, epsilon = 0.0000001);
+        assert_abs_diff_eq!(transitions[1].loss, 0.15, epsilon = 0.0000001);
+        assert!(model.get_transitions(&get_migration_loan("D")).is_err());
+    }
+    #[test]
+    fn no_migration_matches_default_only() {
+        let num_u = 128;
+        let u_domain = get_test_u_domain(num_u, -500.0, 0.0);
+        let liquid_fn = get_liquidity_risk_fn(0.0, 0.0);
+        let mut migration = EconomicCapitalAttributes::new(num_u, 3);
+        let migration_loan = get_migration_loan("B");
+        migration
+            .process_migration_loan(
+                &migration_loan,
+                &get_model(0.0),
+                &u_domain,
+                exponential_lgd_cf,
+                &liquid_fn,
+            )
+            .unwrap();
+        let mut default_only = EconomicCapitalAttributes::new(num_u, 3);
+        default_only.process_loan(
+            &migration_loan.get_default_loan(0.03),
+            &u_domain,
+            get_log_lpm_cf(exponential_lgd_cf, &liquid_fn),
+        );
+        migration
+            .cf
+            .iter()
+            .zip(&default_only.cf)
+            .for_each(|(m, d)| {
+                assert_abs_diff_eq!(m.re, d.re, epsilon = 0.0000001);
+                assert_abs_diff_eq!(m.im, d.im, epsilon = 0.0000001);
+            });
+        assert_eq!(migration.el_vec, default_only.el_vec);
+        assert_eq!(migration.var_vec, default_only.var_vec);
+    }
+    #[test]
+    fn migration_adds_to_risk() {
+        let num_u = 256;
+        let x_min = -500.0;
+        let x_max = 100.0;
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let liquid_fn = get_liquidity_risk_fn(0.0, 0.0);
+        let v = vec![0.4, 0.3, 0.0];
+        let get_risk = |migration: f64| {
+            let mut ec = EconomicCapitalAttributes::new(num_u, 3);
+            ["A", "B", "C"].iter().for_each(|rating| {
+                ec.process_migration_loan(
+                    &get_migration_loan(rating),
+                    &get_model(migration),
+                    &u_domain,
+                    exponential_lgd_cf,
+                    &liquid_fn,
+                )
+                .unwrap()
+            });
+            let cf = ec.get_full_cf(&gamma_mgf(&v));
+            assert_abs_diff_eq!(
+                cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &cf),
+                ec.get_portfolio_expectation(&[1.0, 1.0, 1.0]),
+                epsilon = 0.001
+            );
+            ec.get_portfolio_variance(&[1.0, 1.0, 1.0], &v)
+        };
+        assert!(get_risk(0.05) > get_risk(0.0));
+    }
+    #[test]
+    fn upgrades_are_idiosyncratic() {
+        let num_u = 256;
+        let x_min = -600.0;
+        let x_max = 100.0;
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let liquid_fn = get_liquidity_risk_fn(0.0, 0.0);
+        let v = vec![0.4, 0.3, 0.0];
+        let get_variance = |upgrade_weight: Vec<f64>| {
+            let mut ec = EconomicCapitalAttributes::new(num_u, 3);
+            ec.process_migration_loan(
+                &MigrationLoan {
+                    upgrade_weight,
+                    ..get_migration_loan("C")
+                },
+                &get_model(0.05),
+                &u_domain,
+                exponential_lgd_cf,
+                &liquid_fn,
+            )
+            .unwrap();
+            let cf = ec.get_full_cf(&gamma_mgf(&v));
+            let variance = ec.get_portfolio_variance(&[1.0, 1.0, 1.0], &v);
+            assert_relative_eq!(
+                cf_dist_utils::get_variance_discrete_cf(x_min, x_max, &cf),
+                variance,
+                max_relative = 0.001
+            );
+            variance
+        };
+        //upgrades scaled with the defaults offset them
+        assert!(get_variance(vec![0.0, 0.0, 1.0]) > get_variance(vec![0.4, 0.6, 0.0]));
+        let mut ec = EconomicCapitalAttributes::new(num_u, 3);
+        assert!(ec
+            .process_migration_loan(
+                &MigrationLoan {
+                    upgrade_weight: vec![1.0],
+                    ..get_migration_loan("C")
+                },
+                &get_model(0.05),
+                &u_domain,
+                exponential_lgd_cf,
+                &liquid_fn,
+            )
+            .is_err());
+    }
+}
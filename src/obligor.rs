@@ -0,0 +1,506 @@
+//! Obligor level aggregation of facilities.  All facilities
+//! of an obligor default together: a single default event
+//! drives the sum of the facility losses.
+
+use crate::{
+    default_one, default_zero, risk_contribution_from_increment, EconomicCapitalAttributes,
+};
+use num_complex::Complex;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+
+/// Struct representing a single facility
+/// (eg, a term loan, a line or a card) of
+/// an obligor.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Facility {
+    /// Balance for the facility (in dollars)
+    pub balance: f64,
+    /// Expected value of the loss given
+    /// default for the facility.  A positive
+    /// number.
+    pub lgd: f64,
+    #[serde(default = "default_zero")]
+    /// Amount of liquidity exposure as a
+    /// percent of the balance.  A positive
+    /// number.
+    pub r: f64,
+    #[serde(default = "default_zero")]
+    /// Variance of the loss given
+    /// default for the facility.
+    pub lgd_variance: f64,
+}
+
+/// Struct representing an obligor with
+/// one or more facilities.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Obligor {
+    /// Annualized probability of default
+    /// of the obligor.
+    pub pd: f64,
+    /// Vector of weights describing the
+    /// exposure of this obligor to each
+    /// systemic variable.
+    pub weight: Vec<f64>,
+    #[serde(default = "default_one")]
+    /// The number of obligors that have these
+    /// features.
+    pub num: f64,
+    /// The facilities of the obligor.
+    pub facilities: Vec<Facility>,
+}
+
+/// Returns the expected loss given default
+/// (in dollars) for a facility.
+fn get_facility_expected_loss(facility: &Facility) -> f64 {
+    facility.lgd * facility.balance
+}
+
+impl Obligor {
+    /// Returns the expected loss given default
+    /// (in dollars) summed over the facilities.
+    fn get_expected_loss(&self) -> f64 {
+        self.facilities
+            .iter()
+            .map(get_facility_expected_loss)
+            .sum::<f64>()
+    }
+    /// Returns the second moment of the loss
+    /// given default (in dollars) summed over
+    /// the facilities.  The facility losses are
+    /// independent given default.
+    fn get_second_moment(&self) -> f64 {
+        self.get_expected_loss().powi(2)
+            + self
+                .facilities
+                .iter()
+                .map(|facility| {
+                    facility.lgd_variance * get_facility_expected_loss(facility).powi(2)
+                })
+                .sum::<f64>()
+    }
+    /// Returns the incremental "lambda" for the
+    /// obligor.
+    fn get_lambda(&self) -> f64 {
+        self.facilities
+            .iter()
+            .map(|facility| facility.balance * facility.r)
+            .sum::<f64>()
+            * self.num
+    }
+    /// Returns the obligor's increments to the
+    /// portfolio expected loss and variance
+    /// vectors.
+    fn get_increments(&self) -> (Vec<f64>, Vec<f64>) {
+        let expected_loss = self.get_expected_loss();
+        let second_moment = self.get_second_moment();
+        self.weight
+            .iter()
+            .map(|&w| {
+                let scale = w * self.pd * self.num;
+                (-expected_loss * scale, second_moment * scale)
+            })
+            .unzip()
+    }
+    /// Returns the increments to the portfolio
+    /// expected loss and variance vectors for the
+    /// facility at `index`.  The variance increment
+    /// includes the facility's covariance with the
+    /// other facilities of the obligor, so that the
+    /// facility increments add up to the obligor
+    /// increments.
+    fn get_facility_increments(&self, index: usize) -> (Vec<f64>, Vec<f64>) {
+        let facility = &self.facilities[index];
+        let expected_loss = get_facility_expected_loss(facility);
+        let cross_moment = (1.0 + facility.lgd_variance) * expected_loss.powi(2)
+            + expected_loss * (self.get_expected_loss() - expected_loss);
+        self.weight
+            .iter()
+            .map(|&w| {
+                let scale = w * self.pd * self.num;
+                (-expected_loss * scale, cross_moment * scale)
+            })
+            .unzip()
+    }
+}
+
+/// Returns a function which is the characteristic exponent
+/// for a given obligor.  The result of this function is used
+/// as the third argument in
+/// [process_obligor](../struct.EconomicCapitalAttributes.html#method.process_obligor).
+///
+/// # Arguments
+///
+/// * `lgd_cf` - The characteristic function for a given
+///   facility's loss given default.
+/// * `liquidity_cf` - The liquidity function typically
+///   instantiated from [get_liquidity_risk_fn](../fn.get_liquidity_risk_fn.html).
+pub fn get_obligor_log_lpm_cf<T, U>(
+    lgd_cf: T,
+    liquidity_cf: U,
+) -> impl Fn(&Complex<f64>, &Obligor) -> Complex<f64>
+where
+    T: Fn(&Complex<f64>, f64, f64) -> Complex<f64>,
+    U: Fn(&Complex<f64>) -> Complex<f64>,
+{
+    move |u: &Complex<f64>, obligor: &Obligor| {
+        let u_liquid = liquidity_cf(u);
+        let lgd_cf_product = obligor
+            .facilities
+            .iter()
+            .map(|facility| {
+                lgd_cf(
+                    &u_liquid,
+                    get_facility_expected_loss(facility),
+                    facility.lgd_variance,
+                )
+            })
+            .fold(Complex::new(1.0, 0.0), |acc, cf| acc * cf);
+        (lgd_cf_product - 1.0) * obligor.pd
+    }
+}
+
+/// Returns risk contribution for a given obligor.
+/// The arguments other than `obligor` are the same
+/// as those for
+/// [risk_contribution](../fn.risk_contribution.html).
+///
+/// # Arguments
+///
+/// * `obligor` - An instance of the [Obligor](struct.Obligor.html) struct.
+/// * `el_vec` - The portfolio vector of expected
+///   loss.
+/// * `el_sys` - The vector of expected values for
+///   the systemic random variables.
+/// * `var_vec` - The portfolio vector of
+///   variance.
+/// * `var_sys` - The vector of variances for the
+///   systemic random variables.
+/// * `lambda0` - Base loss (in dollars) from a
+///   liquidity event.  A positive number.
+/// * `lambda` - Sum of r*balance over each loan
+///   in the portfolio.
+/// * `q` - Probability of liquidity event (scaled
+///   by the total portfolio loss).
+/// * `c` - Scalar for multiplying the covariance
+///   for the risk contribution.
+#[allow(clippy::too_many_arguments)]
+pub fn obligor_risk_contribution(
+    obligor: &Obligor,
+    el_vec: &[f64],
+    el_sys: &[f64],
+    var_vec: &[f64],
+    var_sys: &[f64],
+    lambda0: f64,
+    lambda: f64,
+    q: f64,
+    c: f64,
+) -> f64 {
+    let (el_increment, var_increment) = obligor.get_increments();
+    risk_contribution_from_increment(
+        &el_increment,
+        &var_increment,
+        obligor.get_lambda(),
+        el_vec,
+        el_sys,
+        var_vec,
+        var_sys,
+        lambda0,
+        lambda,
+        q,
+        c,
+    )
+}
+
+/// Returns the risk contribution of each facility of
+/// a given obligor.  The facility contributions add up
+/// to the
+/// [obligor_risk_contribution](fn.obligor_risk_contribution.html).
+/// The arguments are the same as those for
+/// [obligor_risk_contribution](fn.obligor_risk_contribution.html).
+#[allow(clippy::too_many_arguments)]
+pub fn facility_risk_contributions(
+    obligor: &Obligor,
+    el_vec: &[f64],
+    el_sys: &[f64],
+    var_vec: &[f64],
+    var_sys: &[f64],
+    lambda0: f64,
+    lambda: f64,
+    q: f64,
+    c: f64,
+) -> Vec<f64> {
+    obligor
+        .facilities
+        .iter()
+        .enumerate()
+        .map(|(index, facility)| {
+            let (el_increment, var_increment) = obligor.get_facility_increments(index);
+            risk_contribution_from_increment(
+                &el_increment,
+                &var_increment,
+                facility.balance * facility.r * obligor.num,
+                el_vec,
+                el_sys,
+                var_vec,
+                var_sys,
+                lambda0,
+                lambda,
+                q,
+                c,
+            )
+        })
+        .collect()
+}
+
+impl EconomicCapitalAttributes {
+    /// Adds a new obligor to the portfolio.
+    /// Mutates el_vec, var_vec, cf, and lambda.
+    ///
+    /// # Arguments
+    ///
+    /// * `obligor` - An instance of the [Obligor](struct.Obligor.html) struct.
+    /// * `u_domain` - The vector of complex values
+    ///   provided to the characteristic function.
+    /// * `log_lpm_cf` - The result from calling
+    ///   [get_obligor_log_lpm_cf](fn.get_obligor_log_lpm_cf.html).
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=100;
+    /// let num_w:usize=1;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, num_w);
+    /// let obligor=loan_ec::obligor::Obligor{
+    ///     pd: 0.05,
+    ///     weight: vec![1.0],
+    ///     num: 1000.0,
+    ///     facilities: vec![
+    ///         loan_ec::obligor::Facility{balance: 1.0, lgd: 0.5, r: 0.0, lgd_variance: 0.0},
+    ///         loan_ec::obligor::Facility{balance: 0.5, lgd: 0.8, r: 0.0, lgd_variance: 0.0}
+    ///     ]
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::obligor::get_obligor_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// ec_attributes.process_obligor(&obligor, &u_domain, &log_lpm_cf);
+    /// # }
+    /// ```
+    pub fn process_obligor<U>(
+        &mut self,
+        obligor: &Obligor,
+        u_domain: &[Complex<f64>],
+        log_lpm_cf: U,
+    ) where
+        U: Fn(&Complex<f64>, &Obligor) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        let vec_of_cf_u: Vec<Complex<f64>> = u_domain
+            .par_iter()
+            .map(|u| log_lpm_cf(u, obligor))
+            .collect();
+        self.add_cf_increment(&vec_of_cf_u, &obligor.weight, obligor.num);
+        let (el_increment, var_increment) = obligor.get_increments();
+        self.el_vec
+            .iter_mut()
+            .zip(&el_increment)
+            .for_each(|(el, el_i)| *el += el_i);
+        self.var_vec
+            .iter_mut()
+            .zip(&var_increment)
+            .for_each(|(var, var_i)| *var += var_i);
+        self.lambda += obligor.get_lambda();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_test_obligor() -> Obligor {
+        Obligor {
+            pd: 0.05,
+            weight: vec![0.4, 0.6],
+            num: 5000.0,
+            facilities: vec![
+                Facility {
+                    balance: 1.0,
+                    lgd: 0.5,
+                    r: 0.1,
+                    lgd_variance: 0.2,
+                },
+                Facility {
+                    balance: 0.5,
+                    lgd: 0.8,
+                    r: 0.2,
+                    lgd_variance: 0.3,
+                },
+            ],
+        }
+    }
+    #[test]
+    fn single_facility_obligor_matches_loan() {
+        let num_u: usize = 256;
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let liquid_fn = get_liquidity_risk_fn(100.0, 0.0001);
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let loan = Loan {
+            pd: 0.05,
+            lgd: 0.5,
+            balance: 1.0,
+            r: 0.1,
+            lgd_variance: 0.2,
+            weight: vec![0.4, 0.6],
+            num: 5000.0,
+        };
+        let obligor = Obligor {
+            pd: 0.05,
+            weight: vec![0.4, 0.6],
+            num: 5000.0,
+            facilities: vec![Facility {
+                balance: 1.0,
+                lgd: 0.5,
+                r: 0.1,
+                lgd_variance: 0.2,
+            }],
+        };
+        let mut ec_loan = EconomicCapitalAttributes::new(num_u, 2);
+        ec_loan.process_loan(&loan, &u_domain, get_log_lpm_cf(gamma_lgd_cf, &liquid_fn));
+        let mut ec_obligor = EconomicCapitalAttributes::new(num_u, 2);
+        ec_obligor.process_obligor(
+            &obligor,
+            &u_domain,
+            get_obligor_log_lpm_cf(gamma_lgd_cf, &liquid_fn),
+        );
+        ec_loan
+            .cf
+            .iter()
+            .zip(&ec_obligor.cf)
+            .for_each(|(cf_l, cf_o)| {
+                assert_abs_diff_eq!(cf_l.re, cf_o.re, epsilon = 0.0000001);
+                assert_abs_diff_eq!(cf_l.im, cf_o.im, epsilon = 0.0000001);
+            });
+        ec_loan
+            .el_vec
+            .iter()
+            .zip(&ec_obligor.el_vec)
+            .for_each(|(el_l, el_o)| assert_abs_diff_eq!(el_l, el_o, epsilon = 0.0000001));
+        ec_loan
+            .var_vec
+            .iter()
+            .zip(&ec_obligor.var_vec)
+            .for_each(|(var_l, var_o)| assert_abs_diff_eq!(var_l, var_o, epsilon = 0.0000001));
+        assert_abs_diff_eq!(ec_loan.lambda, ec_obligor.lambda, epsilon = 0.0000001);
+    }
+    #[test]
+    fn obligor_cf_matches_moments() {
+        let num_u: usize = 1024;
+        let obligor = get_test_obligor();
+        let lambda0 = 100.0;
+        let q = 0.01 / (obligor.num * obligor.pd * obligor.get_expected_loss());
+        let lambda = obligor.get_lambda();
+        let x_min =
+            (-obligor.num * obligor.pd * obligor.get_expected_loss() - lambda0 - lambda) * 3.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.3];
+        let systemic_expectation = vec![1.0, 1.0];
+        let liquid_fn = get_liquidity_risk_fn(lambda0 + lambda, q);
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let mut ec = EconomicCapitalAttributes::new(num_u, v.len());
+        ec.process_obligor(
+            &obligor,
+            &u_domain,
+            get_obligor_log_lpm_cf(gamma_lgd_cf, &liquid_fn),
+        );
+        let expectation = ec.get_portfolio_expectation(&systemic_expectation);
+        let variance = ec.get_portfolio_variance(&systemic_expectation, &v);
+        let expectation_liquid = expectation_liquidity(lambda0 + lambda, q, expectation);
+        let variance_liquid = variance_liquidity(lambda0 + lambda, q, expectation, variance);
+        let final_cf = ec.get_full_cf(&gamma_mgf(&v));
+        let expectation_approx =
+            cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &final_cf);
+        let variance_approx = cf_dist_utils::get_variance_discrete_cf(x_min, x_max, &final_cf);
+        assert_abs_diff_eq!(expectation_approx, expectation_liquid, epsilon = 0.00001);
+        assert_abs_diff_eq!(variance_approx, variance_liquid, epsilon = 0.1);
+    }
+    #[test]
+    fn facility_contributions_add_to_obligor_contribution() {
+        let obligor = get_test_obligor();
+        let el_vec = vec![-400.0, -600.0];
+        let var_vec = vec![300.0, 500.0];
+        let el_sys = vec![1.0, 1.0];
+        let var_sys = vec![0.4, 0.3];
+        let lambda = 2000.0;
+        let lambda0 = 100.0;
+        let q = 0.00001;
+        let c = 3.0;
+        let obligor_rc = obligor_risk_contribution(
+            &obligor, &el_vec, &el_sys, &var_vec, &var_sys, lambda0, lambda, q, c,
+        );
+        let facility_rc = facility_risk_contributions(
+            &obligor, &el_vec, &el_sys, &var_vec, &var_sys, lambda0, lambda, q, c,
+        );
+        assert_eq!(facility_rc.len(), 2);
+        assert_abs_diff_eq!(
+            facility_rc.iter().sum::<f64>(),
+            obligor_rc,
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn obligor_contributions_add_to_risk_measure() {
+        let num_u: usize = 1024;
+        let obligor1 = get_test_obligor();
+        let obligor2 = Obligor {
+            pd: 0.03,
+            weight: vec![0.3, 0.7],
+            num: 4000.0,
+            ..get_test_obligor()
+        };
+        let lambda0 = 100.0;
+        let lambda = obligor1.get_lambda() + obligor2.get_lambda();
+        let q = 0.00001;
+        let x_min = -20000.0;
+        let x_max = 0.0;
+        let v = vec![0.4, 0.3];
+        let systemic_expectation = vec![1.0, 1.0];
+        let liquid_fn = get_liquidity_risk_fn(lambda0 + lambda, q);
+        let log_lpm_cf = get_obligor_log_lpm_cf(gamma_lgd_cf, &liquid_fn);
+        let u_domain = get_test_u_domain(num_u, x_min, x_max);
+        let mut ec = EconomicCapitalAttributes::new(num_u, v.len());
+        ec.process_obligor(&obligor1, &u_domain, &log_lpm_cf);
+        ec.process_obligor(&obligor2, &u_domain, &log_lpm_cf);
+        let expectation = ec.get_portfolio_expectation(&systemic_expectation);
+        let variance = ec.get_portfolio_variance(&systemic_expectation, &v);
+        let liquid_exp = expectation_liquidity(lambda0 + lambda, q, expectation);
+        let liquid_var = variance_liquidity(lambda0 + lambda, q, expectation, variance);
+        let c = 3.0;
+        let rc: f64 = [&obligor1, &obligor2]
+            .iter()
+            .map(|obligor| {
+                obligor_risk_contribution(
+                    obligor,
+                    &ec.el_vec,
+                    &systemic_expectation,
+                    &ec.var_vec,
+                    &v,
+                    lambda0,
+                    lambda,
+                    q,
+                    c,
+                )
+            })
+            .sum();
+        assert_abs_diff_eq!(rc, liquid_exp + c * liquid_var.sqrt(), epsilon = 0.1);
+    }
+}
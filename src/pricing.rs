@@ -0,0 +1,467 @@
+//! Risk-adjusted pricing of a candidate loan against
+//! the current portfolio.  The economic capital of the
+//! loan is its share of the portfolio unexpected loss
+//! (the risk measure net of the portfolio expected
+//! loss), in proportion to its covariance with the
+//! portfolio.  Rates and
+//! costs are per unit of exposure over the same
+//! horizon as the loss distribution.
+//!
+//! RAROC = (1-tax)*((spread-funding-operating)*exposure
+//! -expected_loss+capital_return*capital)/capital.
+
+use crate::{
+    expectation_liquidity, get_el_from_loan, get_lambda_from_loan, get_var_from_loan,
+    portfolio_expectation, portfolio_variance, risk_contribution_parts, variance_liquidity,
+    EconomicCapitalAttributes, Loan,
+};
+use num_complex::Complex;
+use serde_derive::Deserialize;
+
+/// Costs and required returns used in pricing.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PricingParameters {
+    /// Cost of funding the loan, as a spread over
+    /// the same base rate as the quoted spread.
+    pub funding_cost: f64,
+    /// Operating cost per unit of exposure.
+    pub operating_cost: f64,
+    /// Required (after tax) return on economic capital.
+    pub hurdle_rate: f64,
+    /// Tax rate applied to the risk-adjusted income.
+    pub tax_rate: f64,
+    #[serde(default)]
+    /// Return earned on the capital held against the
+    /// loan.
+    pub capital_return: f64,
+}
+
+/// Pricing of a candidate loan.
+#[derive(Debug, Clone)]
+pub struct PricingResult {
+    /// Exposure of the loan (balance times num).
+    pub exposure: f64,
+    /// Expected loss of the loan (positive, in dollars).
+    pub expected_loss: f64,
+    /// Marginal risk contribution of the loan.
+    pub risk_contribution: f64,
+    /// Economic capital of the loan (covariance
+    /// share of the portfolio unexpected loss).
+    pub economic_capital: f64,
+    /// Spread at which the RAROC equals the hurdle
+    /// rate.  `None` if the economic capital is not
+    /// positive.
+    pub break_even_spread: Option<f64>,
+    /// RAROC at the quoted spread.  `None` if the
+    /// economic capital is not positive.
+    pub raroc: Option<f64>,
+}
+
+/// Returns the risk adjusted return on capital, or
+/// `None` if the economic capital is not positive
+/// (eg, for a loan with no default risk).
+///
+/// # Arguments
+///
+/// * `spread` - The spread charged on the loan.
+/// * `exposure` - Exposure of the loan.
+/// * `expected_loss` - Expected loss (positive, in
+///   dollars) of the loan.
+/// * `economic_capital` - Economic capital of the loan.
+/// * `parameters` - An instance of the
+///   [PricingParameters](struct.PricingParameters.html) struct.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let parameters = loan_ec::pricing::PricingParameters{
+///     funding_cost: 0.01,
+///     operating_cost: 0.005,
+///     hurdle_rate: 0.12,
+///     tax_rate: 0.25,
+///     capital_return: 0.0,
+/// };
+/// let raroc = loan_ec::pricing::get_raroc(0.04, 1000.0, 10.0, 80.0, &parameters).unwrap();
+/// assert!(raroc > parameters.hurdle_rate);
+/// assert!(loan_ec::pricing::get_raroc(0.04, 1000.0, 0.0, 0.0, &parameters).is_none());
+/// # }
+/// ```
+pub fn get_raroc(
+    spread: f64,
+    exposure: f64,
+    expected_loss: f64,
+    economic_capital: f64,
+    parameters: &PricingParameters,
+) -> Option<f64> {
+    if economic_capital <= 0.0 {
+        return None;
+    }
+    let income = (spread - parameters.funding_cost - parameters.operating_cost) * exposure
+        - expected_loss
+        + parameters.capital_return * economic_capital;
+    Some((1.0 - parameters.tax_rate) * income / economic_capital)
+}
+
+/// Returns the spread at which the RAROC equals the
+/// hurdle rate, or `None` if the economic capital is
+/// not positive.
+///
+/// # Arguments
+///
+/// * `exposure` - Exposure of the loan.
+/// * `expected_loss` - Expected loss (positive, in
+///   dollars) of the loan.
+/// * `economic_capital` - Economic capital of the loan.
+/// * `parameters` - An instance of the
+///   [PricingParameters](struct.PricingParameters.html) struct.
+pub fn get_break_even_spread(
+    exposure: f64,
+    expected_loss: f64,
+    economic_capital: f64,
+    parameters: &PricingParameters,
+) -> Option<f64> {
+    if economic_capital <= 0.0 {
+        return None;
+    }
+    let required_income = (parameters.hurdle_rate / (1.0 - parameters.tax_rate)
+        - parameters.capital_return)
+        * economic_capital;
+    Some(
+        parameters.funding_cost
+            + parameters.operating_cost
+            + (expected_loss + required_income) / exposure,
+    )
+}
+
+impl EconomicCapitalAttributes {
+    /// Prices a candidate loan against the current
+    /// portfolio.  The portfolio is not modified.
+    /// The risk contribution is the same as from
+    /// [experiment_risk_contribution](../struct.EconomicCapitalAttributes.html#method.experiment_risk_contribution)
+    /// and the remaining arguments are the same.  The
+    /// risk contribution includes the loan's expected
+    /// loss, so the economic capital comes from the
+    /// covariance term alone, scaled by the portfolio
+    /// unexpected loss.
+    ///
+    /// # Arguments
+    ///
+    /// * `parameters` - An instance of the
+    ///   [PricingParameters](struct.PricingParameters.html) struct.
+    /// * `quoted_spread` - The spread at which to
+    ///   compute the RAROC.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// extern crate cf_dist_utils;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=128;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let lambda0=0.0;
+    /// let q=0.0;
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.2,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 10000.0
+    /// };
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(lambda0, q);
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let v = vec![0.4];
+    /// let systemic_mgf = |u_weights: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u_weights, &v);
+    /// let risk_measure_fn = |final_cf: &[Complex<f64>]| {
+    ///     let cf_dist_utils::RiskMetric{value_at_risk, ..} = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+    ///         0.01, x_min, x_max, 100, 0.0001, final_cf,
+    ///     ).unwrap();
+    ///     value_at_risk
+    /// };
+    /// let candidate=loan_ec::Loan{
+    ///     pd: 0.02,
+    ///     lgd: 0.4,
+    ///     lgd_variance: 0.2,
+    ///     balance: 10.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 1.0
+    /// };
+    /// let parameters = loan_ec::pricing::PricingParameters{
+    ///     funding_cost: 0.01,
+    ///     operating_cost: 0.005,
+    ///     hurdle_rate: 0.12,
+    ///     tax_rate: 0.25,
+    ///     capital_return: 0.0,
+    /// };
+    /// let pricing = ec_attributes.price_loan(
+    ///     &candidate, &u_domain, &log_lpm_cf, lambda0, q,
+    ///     &systemic_mgf, &[1.0], &v, &risk_measure_fn,
+    ///     &parameters, 0.05
+    /// );
+    /// assert!(pricing.break_even_spread.unwrap() > parameters.funding_cost);
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn price_loan<U, V, T>(
+        &self,
+        loan: &Loan,
+        u_domain: &[Complex<f64>],
+        log_lpm_cf: U,
+        lambda0: f64,
+        q: f64,
+        mgf_systemic: V,
+        el_sys: &[f64],
+        var_sys: &[f64],
+        risk_measure_fn: T,
+        parameters: &PricingParameters,
+        quoted_spread: f64,
+    ) -> PricingResult
+    where
+        U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        V: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64 + std::marker::Sync + std::marker::Send,
+    {
+        let EconomicCapitalAttributes {
+            cf,
+            el_vec,
+            var_vec,
+            lambda,
+            ..
+        } = self.experiment_loan(loan, u_domain, log_lpm_cf);
+        let full_cf = self.get_experiment_full_cf(&cf, &mgf_systemic);
+        let risk_measure = risk_measure_fn(&full_cf);
+        let port_expectation = portfolio_expectation(&el_vec, el_sys);
+        let port_variance = portfolio_variance(&el_vec, el_sys, &var_vec, var_sys);
+        let liq_expectation = expectation_liquidity(lambda + lambda0, q, port_expectation);
+        let liq_variance = variance_liquidity(lambda + lambda0, q, port_expectation, port_variance);
+        let el_increment: Vec<f64> = loan
+            .weight
+            .iter()
+            .map(|&w| get_el_from_loan(loan, w))
+            .collect();
+        let var_increment: Vec<f64> = loan
+            .weight
+            .iter()
+            .map(|&w| get_var_from_loan(loan, w))
+            .collect();
+        let (expectation_contribution, covariance_contribution) = risk_contribution_parts(
+            &el_increment,
+            &var_increment,
+            get_lambda_from_loan(loan),
+            &el_vec,
+            el_sys,
+            &var_vec,
+            var_sys,
+            lambda0,
+            lambda,
+            q,
+        );
+        let variance_share = covariance_contribution / liq_variance;
+        //the risk measure is positive while the expectation is negative
+        let risk_contribution =
+            expectation_contribution + (risk_measure - liq_expectation) * variance_share;
+        let economic_capital = (risk_measure + liq_expectation) * variance_share;
+        let expected_loss = -loan
+            .weight
+            .iter()
+            .zip(el_sys)
+            .map(|(&w, e)| get_el_from_loan(loan, w) * e)
+            .sum::<f64>();
+        let exposure = loan.balance * loan.num;
+        PricingResult {
+            exposure,
+            expected_loss,
+            risk_contribution,
+            economic_capital,
+            break_even_spread: get_break_even_spread(
+                exposure,
+                expected_loss,
+                economic_capital,
+                parameters,
+            ),
+            raroc: get_raroc(
+                quoted_spread,
+                exposure,
+                expected_loss,
+                economic_capital,
+                parameters,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_parameters() -> PricingParameters {
+        PricingParameters {
+            funding_cost: 0.01,
+            operating_cost: 0.005,
+            hurdle_rate: 0.12,
+            tax_rate: 0.25,
+            capital_return: 0.02,
+        }
+    }
+    #[test]
+    fn raroc_at_break_even_is_hurdle() {
+        let parameters = get_parameters();
+        let spread = get_break_even_spread(1000.0, 10.0, 80.0, &parameters).unwrap();
+        assert_abs_diff_eq!(
+            get_raroc(spread, 1000.0, 10.0, 80.0, &parameters).unwrap(),
+            parameters.hurdle_rate,
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn raroc_increases_with_spread() {
+        let parameters = get_parameters();
+        assert!(
+            get_raroc(0.05, 1000.0, 10.0, 80.0, &parameters).unwrap()
+                > get_raroc(0.04, 1000.0, 10.0, 80.0, &parameters).unwrap()
+        );
+    }
+    const X_MIN: f64 = -5000.0;
+    const X_MAX: f64 = 0.0;
+    fn get_test_portfolio() -> (EconomicCapitalAttributes, Vec<Complex<f64>>) {
+        let u_domain = get_test_u_domain(256, X_MIN, X_MAX);
+        let loan = get_test_loan(vec![1.0], 10000.0);
+        let ec = process_test_loans(exponential_lgd_cf, 0.0, 0.0, &u_domain, &[loan]);
+        (ec, u_domain)
+    }
+    fn get_candidate(pd: f64, balance: f64) -> Loan {
+        Loan {
+            pd,
+            lgd: 0.5,
+            balance,
+            r: 0.0,
+            lgd_variance: 0.0,
+            weight: vec![1.0],
+            num: 1.0,
+        }
+    }
+    fn risk_measure_fn(final_cf: &[Complex<f64>]) -> f64 {
+        let cf_dist_utils::RiskMetric { value_at_risk, .. } =
+            cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+                0.01, X_MIN, X_MAX, 100, 0.0001, final_cf,
+            )
+            .unwrap();
+        value_at_risk
+    }
+    #[test]
+    fn riskier_loan_requires_higher_spread() {
+        let (ec, u_domain) = get_test_portfolio();
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let v = vec![0.4];
+        let v_mgf = gamma_mgf(&v);
+        let parameters = get_parameters();
+        let price = |pd: f64| {
+            ec.price_loan(
+                &get_candidate(pd, 10.0),
+                &u_domain,
+                &log_lpm_cf,
+                0.0,
+                0.0,
+                &v_mgf,
+                &[1.0],
+                &v,
+                risk_measure_fn,
+                &parameters,
+                0.05,
+            )
+        };
+        let safe = price(0.01);
+        let risky = price(0.05);
+        assert_abs_diff_eq!(safe.expected_loss, 0.05, epsilon = 0.0000001);
+        assert!(safe.economic_capital > 0.0);
+        assert!(risky.economic_capital > safe.economic_capital);
+        assert!(risky.break_even_spread.unwrap() > safe.break_even_spread.unwrap());
+        assert!(risky.raroc.unwrap() < safe.raroc.unwrap());
+        let riskless = price(0.0);
+        assert!(riskless.raroc.is_none());
+        assert!(riskless.break_even_spread.is_none());
+    }
+    #[test]
+    fn economic_capital_follows_variance_share() {
+        let (ec, u_domain) = get_test_portfolio();
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let v = vec![0.4];
+        let v_mgf = gamma_mgf(&v);
+        let get_variance_share = |candidate: &Loan| {
+            let EconomicCapitalAttributes {
+                el_vec, var_vec, ..
+            } = ec.experiment_loan(candidate, &u_domain, &log_lpm_cf);
+            let el_increment = vec![get_el_from_loan(candidate, 1.0)];
+            let var_increment = vec![get_var_from_loan(candidate, 1.0)];
+            let (_, covariance) = risk_contribution_parts(
+                &el_increment,
+                &var_increment,
+                0.0,
+                &el_vec,
+                &[1.0],
+                &var_vec,
+                &v,
+                0.0,
+                0.0,
+                0.0,
+            );
+            covariance / portfolio_variance(&el_vec, &[1.0], &var_vec, &v)
+        };
+        let safe = get_candidate(0.01, 400.0);
+        let safe_share = get_variance_share(&safe);
+        //balance of a riskier loan with the same variance share
+        let (mut low, mut high) = (1.0, 400.0);
+        (0..60).for_each(|_| {
+            let mid = 0.5 * (low + high);
+            if get_variance_share(&get_candidate(0.05, mid)) < safe_share {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        });
+        let risky = get_candidate(0.05, 0.5 * (low + high));
+        assert_relative_eq!(
+            get_variance_share(&risky),
+            safe_share,
+            max_relative = 0.0000001
+        );
+        let parameters = get_parameters();
+        let price = |candidate: &Loan| {
+            ec.price_loan(
+                candidate,
+                &u_domain,
+                &log_lpm_cf,
+                0.0,
+                0.0,
+                &v_mgf,
+                &[1.0],
+                &v,
+                risk_measure_fn,
+                &parameters,
+                0.05,
+            )
+        };
+        let safe_price = price(&safe);
+        let risky_price = price(&risky);
+        assert!(risky_price.expected_loss > 1.5 * safe_price.expected_loss);
+        assert_relative_eq!(
+            risky_price.economic_capital,
+            safe_price.economic_capital,
+            max_relative = 0.001
+        );
+    }
+}
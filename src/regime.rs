@@ -0,0 +1,395 @@
+//! Regime switching systemic variables.  The portfolio
+//! characteristic function is the probability weighted
+//! mixture of the characteristic functions in each regime
+//! (eg, expansion and recession), all built from the same
+//! accumulated cf.  Each regime has its own systemic
+//! variances and optionally scales every probability of
+//! default.
+
+use crate::{
+    default_one, expectation_liquidity, get_el_from_loan, get_lambda_from_loan, get_var_from_loan,
+    portfolio_expectation, portfolio_variance, risk_contribution_parts, variance_liquidity,
+    EconomicCapitalAttributes, Loan,
+};
+use num_complex::Complex;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+
+/// Struct representing a single regime of
+/// the systemic variables.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Regime {
+    /// Probability of the regime.  The
+    /// probabilities over all regimes should
+    /// add to one.
+    pub probability: f64,
+    /// The vector of variances for the systemic
+    /// random variables in this regime.
+    pub variance: Vec<f64>,
+    #[serde(default = "default_one")]
+    /// Multiplier applied to every probability
+    /// of default in this regime.
+    pub pd_multiplier: f64,
+}
+
+/// Returns a copy of `vec` multiplied by a scalar.
+fn scale(vec: &[f64], multiplier: f64) -> Vec<f64> {
+    vec.iter().map(|v| v * multiplier).collect()
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the expectation and the variance (both
+    /// with liquidity risk) of the portfolio in a
+    /// given regime.
+    fn get_regime_moments(
+        &self,
+        regime: &Regime,
+        el_sys: &[f64],
+        lambda0: f64,
+        q: f64,
+    ) -> (f64, f64) {
+        let el_vec = scale(&self.el_vec, regime.pd_multiplier);
+        let var_vec = scale(&self.var_vec, regime.pd_multiplier);
+        let expectation = portfolio_expectation(&el_vec, el_sys);
+        let variance = portfolio_variance(&el_vec, el_sys, &var_vec, &regime.variance);
+        (
+            expectation_liquidity(self.lambda + lambda0, q, expectation),
+            variance_liquidity(self.lambda + lambda0, q, expectation, variance),
+        )
+    }
+    /// Gets the discrete characteristic function for
+    /// the portfolio as the mixture over regimes.  This
+    /// should be called after processing all the loans
+    /// in the portfolio.
+    ///
+    /// # Arguments
+    ///
+    /// * `regimes` - Vector of [Regime](struct.Regime.html)
+    ///   structs.
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables as a
+    ///   function of the characteristic exponents and
+    ///   the systemic variances, eg
+    ///   [systemic_gamma_mgf](../fn.systemic_gamma_mgf.html).
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=100;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.2,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 10000.0
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let regimes=vec![
+    ///     loan_ec::regime::Regime{probability: 0.8, variance: vec![0.2], pd_multiplier: 0.8},
+    ///     loan_ec::regime::Regime{probability: 0.2, variance: vec![0.6], pd_multiplier: 1.8}
+    /// ];
+    /// let cf=ec_attributes.get_regime_full_cf(&regimes, &loan_ec::systemic_gamma_mgf);
+    /// # }
+    /// ```
+    pub fn get_regime_full_cf<U>(&self, regimes: &[Regime], mgf_systemic: &U) -> Vec<Complex<f64>>
+    where
+        U: Fn(&[Complex<f64>], &[f64]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        self.cf
+            .par_chunks(self.num_w)
+            .map(|u_weights| {
+                regimes
+                    .iter()
+                    .map(|regime| {
+                        let scaled_weights: Vec<Complex<f64>> =
+                            u_weights.iter().map(|u| u * regime.pd_multiplier).collect();
+                        mgf_systemic(&scaled_weights, &regime.variance) * regime.probability
+                    })
+                    .sum::<Complex<f64>>()
+            })
+            .collect()
+    }
+    /// Gets the expected value of the portfolio with
+    /// liquidity risk under the mixture of regimes.
+    ///
+    /// # Arguments
+    ///
+    /// * `regimes` - Vector of [Regime](struct.Regime.html)
+    ///   structs.
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    pub fn get_regime_expectation(
+        &self,
+        regimes: &[Regime],
+        el_sys: &[f64],
+        lambda0: f64,
+        q: f64,
+    ) -> f64 {
+        regimes
+            .iter()
+            .map(|regime| {
+                regime.probability * self.get_regime_moments(regime, el_sys, lambda0, q).0
+            })
+            .sum()
+    }
+    /// Gets the variance of the portfolio with
+    /// liquidity risk under the mixture of regimes.
+    /// The arguments are the same as those for
+    /// [get_regime_expectation](struct.EconomicCapitalAttributes.html#method.get_regime_expectation).
+    pub fn get_regime_variance(
+        &self,
+        regimes: &[Regime],
+        el_sys: &[f64],
+        lambda0: f64,
+        q: f64,
+    ) -> f64 {
+        let (expectation, second_moment) =
+            regimes
+                .iter()
+                .fold((0.0, 0.0), |(expectation, second_moment), regime| {
+                    let (regime_expectation, regime_variance) =
+                        self.get_regime_moments(regime, el_sys, lambda0, q);
+                    (
+                        expectation + regime.probability * regime_expectation,
+                        second_moment
+                            + regime.probability * (regime_variance + regime_expectation.powi(2)),
+                    )
+                });
+        second_moment - expectation.powi(2)
+    }
+    /// Returns the risk contribution of a loan in the
+    /// portfolio under the mixture of regimes.  The
+    /// contributions add up to the mixture expectation
+    /// plus `c` times the mixture standard deviation.
+    ///
+    /// # Arguments
+    ///
+    /// * `loan` - An instance of the [Loan](../struct.Loan.html) struct.
+    /// * `regimes` - Vector of [Regime](struct.Regime.html)
+    ///   structs.
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `c` - Scalar for multiplying the covariance
+    ///   for the risk contribution.  Typically
+    ///   (rho(X)-E\[X\])/sqrt(Var(X)) where the
+    ///   expectation and variance are from
+    ///   [get_regime_expectation](struct.EconomicCapitalAttributes.html#method.get_regime_expectation)
+    ///   and
+    ///   [get_regime_variance](struct.EconomicCapitalAttributes.html#method.get_regime_variance).
+    pub fn regime_risk_contribution(
+        &self,
+        loan: &Loan,
+        regimes: &[Regime],
+        el_sys: &[f64],
+        lambda0: f64,
+        q: f64,
+        c: f64,
+    ) -> f64 {
+        let (expectation_contribution, cross_moment_contribution) = regimes.iter().fold(
+            (0.0, 0.0),
+            |(expectation_contribution, cross_moment_contribution), regime| {
+                let m = regime.pd_multiplier;
+                let el_increment: Vec<f64> = loan
+                    .weight
+                    .iter()
+                    .map(|&w| get_el_from_loan(loan, w) * m)
+                    .collect();
+                let var_increment: Vec<f64> = loan
+                    .weight
+                    .iter()
+                    .map(|&w| get_var_from_loan(loan, w) * m)
+                    .collect();
+                let (regime_expectation_contribution, regime_covariance_contribution) =
+                    risk_contribution_parts(
+                        &el_increment,
+                        &var_increment,
+                        get_lambda_from_loan(loan),
+                        &scale(&self.el_vec, m),
+                        el_sys,
+                        &scale(&self.var_vec, m),
+                        &regime.variance,
+                        lambda0,
+                        self.lambda,
+                        q,
+                    );
+                let (regime_expectation, _) = self.get_regime_moments(regime, el_sys, lambda0, q);
+                (
+                    expectation_contribution + regime.probability * regime_expectation_contribution,
+                    cross_moment_contribution
+                        + regime.probability
+                            * (regime_covariance_contribution
+                                + regime_expectation_contribution * regime_expectation),
+                )
+            },
+        );
+        let expectation = self.get_regime_expectation(regimes, el_sys, lambda0, q);
+        let standard_deviation = self.get_regime_variance(regimes, el_sys, lambda0, q).sqrt();
+        expectation_contribution
+            + c * (cross_moment_contribution - expectation_contribution * expectation)
+                / standard_deviation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_test_loans() -> Vec<Loan> {
+        vec![
+            Loan {
+                pd: 0.05,
+                lgd: 0.5,
+                balance: 1.0,
+                r: 0.1,
+                lgd_variance: 0.2,
+                weight: vec![0.4, 0.6],
+                num: 6000.0,
+            },
+            Loan {
+                pd: 0.03,
+                lgd: 0.4,
+                balance: 2.0,
+                r: 0.2,
+                lgd_variance: 0.2,
+                weight: vec![0.3, 0.7],
+                num: 4000.0,
+            },
+        ]
+    }
+    fn get_test_regimes() -> Vec<Regime> {
+        vec![
+            Regime {
+                probability: 0.7,
+                variance: vec![0.2, 0.3],
+                pd_multiplier: 0.8,
+            },
+            Regime {
+                probability: 0.3,
+                variance: vec![0.6, 0.5],
+                pd_multiplier: 1.5,
+            },
+        ]
+    }
+    fn get_test_attributes(
+        num_u: usize,
+        x_min: f64,
+        x_max: f64,
+        lambda0: f64,
+        q: f64,
+    ) -> EconomicCapitalAttributes {
+        let loans = get_test_loans();
+        let lambda: f64 = loans.iter().map(get_lambda_from_loan).sum();
+        process_test_loans(
+            gamma_lgd_cf,
+            lambda0 + lambda,
+            q,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &loans,
+        )
+    }
+    #[test]
+    fn single_regime_matches_full_cf() {
+        let num_u: usize = 256;
+        let ec = get_test_attributes(num_u, -10000.0, 0.0, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let regimes = vec![Regime {
+            probability: 1.0,
+            variance: v.clone(),
+            pd_multiplier: 1.0,
+        }];
+        let regime_cf = ec.get_regime_full_cf(&regimes, &systemic_gamma_mgf);
+        let full_cf = ec.get_full_cf(&gamma_mgf(&v));
+        regime_cf.iter().zip(&full_cf).for_each(|(r, f)| {
+            assert_abs_diff_eq!(r.re, f.re, epsilon = 0.0000001);
+            assert_abs_diff_eq!(r.im, f.im, epsilon = 0.0000001);
+        });
+    }
+    #[test]
+    fn regime_moments_match_cf() {
+        let num_u: usize = 1024;
+        let lambda0 = 100.0;
+        let q = 0.000001;
+        let x_min = -30000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(num_u, x_min, x_max, lambda0, q);
+        let el_sys = vec![1.0, 1.0];
+        let regimes = get_test_regimes();
+        let regime_cf = ec.get_regime_full_cf(&regimes, &systemic_gamma_mgf);
+        let expectation = ec.get_regime_expectation(&regimes, &el_sys, lambda0, q);
+        let variance = ec.get_regime_variance(&regimes, &el_sys, lambda0, q);
+        let expectation_approx =
+            cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, &regime_cf);
+        let variance_approx = cf_dist_utils::get_variance_discrete_cf(x_min, x_max, &regime_cf);
+        assert_abs_diff_eq!(expectation_approx, expectation, epsilon = 0.001);
+        assert_abs_diff_eq!(variance_approx, variance, epsilon = 1.0);
+    }
+    #[test]
+    fn single_regime_risk_contribution_matches_risk_contribution() {
+        let lambda0 = 100.0;
+        let q = 0.000001;
+        let ec = get_test_attributes(64, -30000.0, 0.0, lambda0, q);
+        let el_sys = vec![1.0, 1.0];
+        let v = vec![0.4, 0.3];
+        let regimes = vec![Regime {
+            probability: 1.0,
+            variance: v.clone(),
+            pd_multiplier: 1.0,
+        }];
+        let c = 3.0;
+        get_test_loans().iter().for_each(|loan| {
+            let rc = risk_contribution(
+                loan,
+                &ec.el_vec,
+                &el_sys,
+                &ec.var_vec,
+                &v,
+                lambda0,
+                ec.lambda,
+                q,
+                c,
+            );
+            let regime_rc = ec.regime_risk_contribution(loan, &regimes, &el_sys, lambda0, q, c);
+            assert_abs_diff_eq!(rc, regime_rc, epsilon = 0.0000001);
+        });
+    }
+    #[test]
+    fn regime_risk_contributions_add_to_risk_measure() {
+        let lambda0 = 100.0;
+        let q = 0.000001;
+        let ec = get_test_attributes(64, -30000.0, 0.0, lambda0, q);
+        let el_sys = vec![1.0, 1.0];
+        let regimes = get_test_regimes();
+        let c = 3.0;
+        let rc: f64 = get_test_loans()
+            .iter()
+            .map(|loan| ec.regime_risk_contribution(loan, &regimes, &el_sys, lambda0, q, c))
+            .sum();
+        let expectation = ec.get_regime_expectation(&regimes, &el_sys, lambda0, q);
+        let variance = ec.get_regime_variance(&regimes, &el_sys, lambda0, q);
+        assert_abs_diff_eq!(rc, expectation + c * variance.sqrt(), epsilon = 0.0001);
+    }
+}
@@ -0,0 +1,291 @@
+//! Reverse stress testing.  Finds the most likely
+//! realization of the (independent, Gamma distributed)
+//! systemic variables for which the expected portfolio
+//! loss equals a target loss, such as the value at risk.
+//!
+//! With shape 1/v and scale v for each systemic
+//! variable, maximizing the log density subject to
+//! sum_k el_k s_k = L gives
+//! s_k = (1/v_k - 1)/(1/v_k + mu el_k), where the
+//! multiplier mu is found by bisection.
+
+use crate::distributions::ln_gamma;
+use crate::error::EconomicCapitalError;
+use crate::{expectation_liquidity, EconomicCapitalAttributes};
+
+const VARIANCE_ERROR: &str = "Systemic variances must be between 0 and 1.";
+const TARGET_ERROR: &str = "Target loss must be positive.";
+const CONVERGENCE_ERROR: &str = "Reverse stress test did not converge.";
+const NO_RISK_ERROR: &str = "Portfolio has no expected loss.";
+
+/// The most likely scenario producing a target loss.
+#[derive(Debug, Clone)]
+pub struct ReverseStressScenario {
+    /// Value of each systemic random variable.
+    pub factor_values: Vec<f64>,
+    /// Expected value of the portfolio with liquidity
+    /// risk conditional on the factor values.  Equals
+    /// the negative of the target loss.
+    pub expectation: f64,
+    /// Log density of the factor values.
+    pub log_density: f64,
+}
+
+/// Returns the log density of independent Gamma random
+/// variables with mean one and variance v.
+fn gamma_log_density(factor_values: &[f64], variance: &[f64]) -> f64 {
+    factor_values
+        .iter()
+        .zip(variance)
+        .map(|(s, v)| {
+            let alpha = 1.0 / v;
+            (alpha - 1.0) * s.ln() - s / v - ln_gamma(alpha) - alpha * v.ln()
+        })
+        .sum()
+}
+
+/// Returns the factor values maximizing the density
+/// for a given Lagrange multiplier.
+fn get_factor_values(el_vec: &[f64], variance: &[f64], mu: f64) -> Vec<f64> {
+    el_vec
+        .iter()
+        .zip(variance)
+        .map(|(el, v)| (1.0 / v - 1.0) / (1.0 / v + mu * el))
+        .collect()
+}
+
+fn get_conditional_expectation(el_vec: &[f64], factor_values: &[f64]) -> f64 {
+    el_vec.iter().zip(factor_values).map(|(el, s)| el * s).sum()
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the most likely realization of the
+    /// systemic random variables for which the
+    /// expected portfolio loss (with liquidity risk)
+    /// equals `target_loss`.  The systemic variables
+    /// are Gamma distributed with mean one, as in
+    /// [systemic_gamma_mgf](../fn.systemic_gamma_mgf.html),
+    /// and variances less than one so that the
+    /// density has a mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `target_loss` - The (positive) loss to
+    ///   explain, for example the value at risk.
+    /// * `variance` - The variance of each systemic
+    ///   random variable.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `tolerance` - Tolerance on the conditional
+    ///   expectation.
+    /// * `max_iterations` - Maximum number of bisection
+    ///   steps.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=128;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 2);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.0,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![0.4, 0.6],
+    ///     num: 10000.0
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let scenario = ec_attributes.get_reverse_stress_scenario(
+    ///     500.0, &[0.4, 0.3], 0.0, 0.0, 0.0000001, 200
+    /// ).unwrap();
+    /// assert!((scenario.expectation + 500.0).abs() < 0.0001);
+    /// # }
+    /// ```
+    pub fn get_reverse_stress_scenario(
+        &self,
+        target_loss: f64,
+        variance: &[f64],
+        lambda0: f64,
+        q: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<ReverseStressScenario, EconomicCapitalError> {
+        if variance.iter().any(|v| *v <= 0.0 || *v >= 1.0) {
+            return Err(EconomicCapitalError::new(VARIANCE_ERROR));
+        }
+        if target_loss <= 0.0 {
+            return Err(EconomicCapitalError::new(TARGET_ERROR));
+        }
+        //liquidity risk scales the expectation by (1+q*lambda)
+        let lambda = self.lambda + lambda0;
+        let target = -target_loss / expectation_liquidity(lambda, q, 1.0);
+        let el_vec = &self.el_vec;
+        let objective = |mu: f64| {
+            get_conditional_expectation(el_vec, &get_factor_values(el_vec, variance, mu)) - target
+        };
+        //the denominators 1/v_k+mu*el_k must stay positive
+        let mu_max = el_vec
+            .iter()
+            .zip(variance)
+            .filter(|(el, _)| **el < 0.0)
+            .map(|(el, v)| -1.0 / (v * el))
+            .fold(f64::INFINITY, f64::min);
+        if mu_max.is_infinite() {
+            return Err(EconomicCapitalError::new(NO_RISK_ERROR));
+        }
+        //objective is decreasing in mu
+        let (mut mu_low, mut mu_high) = if objective(0.0) > 0.0 {
+            (0.0, mu_max)
+        } else {
+            let mut mu_low = -mu_max;
+            let mut iteration = 0;
+            while objective(mu_low) < 0.0 {
+                mu_low *= 2.0;
+                iteration += 1;
+                if iteration > max_iterations {
+                    return Err(EconomicCapitalError::new(CONVERGENCE_ERROR));
+                }
+            }
+            (mu_low, 0.0)
+        };
+        for _ in 0..max_iterations {
+            let mu = 0.5 * (mu_low + mu_high);
+            let value = objective(mu);
+            if value.abs() < tolerance {
+                let factor_values = get_factor_values(el_vec, variance, mu);
+                return Ok(ReverseStressScenario {
+                    expectation: expectation_liquidity(
+                        lambda,
+                        q,
+                        get_conditional_expectation(el_vec, &factor_values),
+                    ),
+                    log_density: gamma_log_density(&factor_values, variance),
+                    factor_values,
+                });
+            }
+            if value > 0.0 {
+                mu_low = mu;
+            } else {
+                mu_high = mu;
+            }
+        }
+        Err(EconomicCapitalError::new(CONVERGENCE_ERROR))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_test_attributes() -> EconomicCapitalAttributes {
+        let loan1 = Loan {
+            pd: 0.05,
+            lgd: 0.5,
+            balance: 1.0,
+            r: 0.0,
+            lgd_variance: 0.0,
+            weight: vec![0.8, 0.2],
+            num: 6000.0,
+        };
+        let loan2 = Loan {
+            pd: 0.03,
+            lgd: 0.4,
+            balance: 2.0,
+            r: 0.0,
+            lgd_variance: 0.0,
+            weight: vec![0.1, 0.9],
+            num: 4000.0,
+        };
+        process_test_loans(
+            exponential_lgd_cf,
+            0.0,
+            0.0,
+            &get_test_u_domain(64, -5000.0, 0.0),
+            &[loan1, loan2],
+        )
+    }
+    #[test]
+    fn scenario_hits_target_loss() {
+        let ec = get_test_attributes();
+        let variance = vec![0.4, 0.3];
+        let scenario = ec
+            .get_reverse_stress_scenario(400.0, &variance, 0.0, 0.0, 0.0000001, 500)
+            .unwrap();
+        assert_abs_diff_eq!(scenario.expectation, -400.0, epsilon = 0.00001);
+        assert_abs_diff_eq!(
+            ec.get_conditional_expectation(&scenario.factor_values),
+            -400.0,
+            epsilon = 0.00001
+        );
+        assert!(scenario.factor_values.iter().all(|s| *s > 1.0));
+    }
+    #[test]
+    fn scenario_is_most_likely() {
+        let ec = get_test_attributes();
+        let variance = vec![0.4, 0.3];
+        let scenario = ec
+            .get_reverse_stress_scenario(400.0, &variance, 0.0, 0.0, 0.0000001, 500)
+            .unwrap();
+        //perturb along the constraint el_1 ds_1 + el_2 ds_2 = 0
+        let ds = 0.01;
+        let perturbed = vec![
+            scenario.factor_values[0] + ds * ec.el_vec[1],
+            scenario.factor_values[1] - ds * ec.el_vec[0],
+        ];
+        assert!(gamma_log_density(&perturbed, &variance) < scenario.log_density);
+        let perturbed = vec![
+            scenario.factor_values[0] - ds * ec.el_vec[1],
+            scenario.factor_values[1] + ds * ec.el_vec[0],
+        ];
+        assert!(gamma_log_density(&perturbed, &variance) < scenario.log_density);
+    }
+    #[test]
+    fn small_target_gives_benign_scenario() {
+        let ec = get_test_attributes();
+        let variance = vec![0.4, 0.3];
+        let scenario = ec
+            .get_reverse_stress_scenario(50.0, &variance, 0.0, 0.0, 0.0000001, 500)
+            .unwrap();
+        assert_abs_diff_eq!(scenario.expectation, -50.0, epsilon = 0.00001);
+        assert!(scenario.factor_values.iter().all(|s| *s < 1.0));
+    }
+    #[test]
+    fn liquidity_reduces_required_stress() {
+        let ec = get_test_attributes();
+        let variance = vec![0.4, 0.3];
+        let scenario = ec
+            .get_reverse_stress_scenario(400.0, &variance, 0.0, 0.0, 0.0000001, 500)
+            .unwrap();
+        let scenario_liquidity = ec
+            .get_reverse_stress_scenario(400.0, &variance, 1000.0, 0.0001, 0.0000001, 500)
+            .unwrap();
+        assert_abs_diff_eq!(scenario_liquidity.expectation, -400.0, epsilon = 0.00001);
+        assert!(scenario_liquidity.factor_values[0] < scenario.factor_values[0]);
+    }
+    #[test]
+    fn invalid_inputs_error() {
+        let ec = get_test_attributes();
+        assert!(ec
+            .get_reverse_stress_scenario(400.0, &[1.5, 0.3], 0.0, 0.0, 0.0000001, 500)
+            .is_err());
+        assert!(ec
+            .get_reverse_stress_scenario(-400.0, &[0.4, 0.3], 0.0, 0.0, 0.0000001, 500)
+            .is_err());
+    }
+}
@@ -0,0 +1,420 @@
+//! Built-in risk measures evaluated from the discrete
+//! characteristic function of the portfolio: spectral
+//! (distortion) risk measures and expectiles.
+//!
+//! A distortion risk measure of the loss L is
+//! int_0^inf g(P(L>l))dl for a concave distortion g with
+//! g(0)=0 and g(1)=1.  Since the portfolio X=-L is
+//! described on \[x_min, x_max\], this is computed as the
+//! Stieltjes sum of -x against g(F(x)) over a grid,
+//! where F is the distribution function of X.
+
+use crate::cf_transform::{get_cdf, get_density_coefficients, get_partial_expectation};
+use crate::distributions::{normal_cdf, normal_inv};
+use crate::error::EconomicCapitalError;
+use crate::{
+    expectation_liquidity, portfolio_expectation, portfolio_variance, risk_contribution,
+    variance_liquidity, EconomicCapitalAttributes, Loan,
+};
+use num_complex::Complex;
+use serde_derive::Deserialize;
+
+/// Number of bisection steps for the expectile.
+const EXPECTILE_ITERATIONS: usize = 200;
+
+/// Risk measures which can be selected by
+/// configuration, eg `{"type": "wang_transform",
+/// "lambda": 0.5}`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RiskMeasure {
+    /// Expected shortfall at tail probability `alpha`.
+    ExpectedShortfall {
+        /// Tail probability in (0, 1\].
+        alpha: f64,
+    },
+    /// Spectral measure with exponential weight
+    /// gamma*exp(-gamma*s)/(1-exp(-gamma)) on the tail
+    /// probability s.
+    ExponentialSpectral {
+        /// Risk aversion, greater than zero.
+        gamma: f64,
+    },
+    /// Wang transform g(s)=N(N^{-1}(s)+lambda).
+    WangTransform {
+        /// Market price of risk.
+        lambda: f64,
+    },
+    /// Power (proportional hazard) distortion
+    /// g(s)=s^gamma.
+    PowerDistortion {
+        /// Exponent in (0, 1\].
+        gamma: f64,
+    },
+    /// Expectile e of the loss, solving
+    /// tau E\[(L-e)+\]=(1-tau) E\[(e-L)+\].
+    Expectile {
+        /// Level in (0, 1).
+        tau: f64,
+    },
+}
+
+impl RiskMeasure {
+    /// Checks that the parameters are in range.
+    fn validate(&self) -> Result<(), EconomicCapitalError> {
+        let valid = match *self {
+            RiskMeasure::ExpectedShortfall { alpha } => alpha > 0.0 && alpha <= 1.0,
+            RiskMeasure::ExponentialSpectral { gamma } => gamma > 0.0,
+            RiskMeasure::WangTransform { lambda } => lambda.is_finite(),
+            RiskMeasure::PowerDistortion { gamma } => gamma > 0.0 && gamma <= 1.0,
+            RiskMeasure::Expectile { tau } => tau > 0.0 && tau < 1.0,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(EconomicCapitalError::new(
+                "Risk measure parameter is out of range.",
+            ))
+        }
+    }
+    /// Returns the distortion g(s) of a tail
+    /// probability s.  The expectile is not a
+    /// distortion risk measure and returns s.
+    fn distortion(&self, s: f64) -> f64 {
+        if s <= 0.0 {
+            return 0.0;
+        }
+        if s >= 1.0 {
+            return 1.0;
+        }
+        match *self {
+            RiskMeasure::ExpectedShortfall { alpha } => (s / alpha).min(1.0),
+            RiskMeasure::ExponentialSpectral { gamma } => {
+                (1.0 - (-gamma * s).exp()) / (1.0 - (-gamma).exp())
+            }
+            RiskMeasure::WangTransform { lambda } => normal_cdf(normal_inv(s) + lambda),
+            RiskMeasure::PowerDistortion { gamma } => s.powf(gamma),
+            RiskMeasure::Expectile { .. } => s,
+        }
+    }
+}
+
+/// Returns the risk measure (as a positive number)
+/// of the portfolio described by a discrete
+/// characteristic function.
+///
+/// # Arguments
+///
+/// * `risk_measure` - The risk measure to evaluate.
+/// * `discrete_cf` - The discrete characteristic
+///   function of the portfolio.
+/// * `x_min` - Lower truncation of the loss
+///   distribution.
+/// * `x_max` - Upper truncation of the loss
+///   distribution.
+/// * `num_x` - Number of grid intervals for
+///   distortion risk measures.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// extern crate fang_oost;
+/// extern crate num_complex;
+/// use num_complex::Complex;
+/// # fn main(){
+/// let x_min = -25.0;
+/// let x_max = 15.0;
+/// let norm_cf = |u: &Complex<f64>| (-u * 5.0 + 2.0 * u * u).exp();
+/// let discrete_cf = fang_oost::get_discrete_cf(128, x_min, x_max, norm_cf);
+/// let measure: loan_ec::risk_measure::RiskMeasure =
+///     serde_json::from_str(r#"{"type": "expected_shortfall", "alpha": 0.05}"#).unwrap();
+/// let es = loan_ec::risk_measure::get_risk_measure(
+///     &measure, &discrete_cf, x_min, x_max, 1024
+/// ).unwrap();
+/// assert!(es > 5.0);
+/// # }
+/// ```
+pub fn get_risk_measure(
+    risk_measure: &RiskMeasure,
+    discrete_cf: &[Complex<f64>],
+    x_min: f64,
+    x_max: f64,
+    num_x: usize,
+) -> Result<f64, EconomicCapitalError> {
+    risk_measure.validate()?;
+    let coefficients = get_density_coefficients(discrete_cf, x_min, x_max);
+    let cdf = |x: f64| get_cdf(&coefficients, x_min, x_max, x).clamp(0.0, 1.0);
+    if let RiskMeasure::Expectile { tau } = *risk_measure {
+        //in terms of x=-e: tau(xF(x)-PE(x))=(1-tau)(E[X]-PE(x)-x(1-F(x)))
+        let expectation = get_partial_expectation(&coefficients, x_min, x_max, x_max);
+        let excess = |x: f64| {
+            let cdf_x = cdf(x);
+            let partial = get_partial_expectation(&coefficients, x_min, x_max, x);
+            tau * (x * cdf_x - partial) - (1.0 - tau) * (expectation - partial - x * (1.0 - cdf_x))
+        };
+        let (mut low, mut high) = (x_min, x_max);
+        for _ in 0..EXPECTILE_ITERATIONS {
+            let x = 0.5 * (low + high);
+            if excess(x) < 0.0 {
+                low = x;
+            } else {
+                high = x;
+            }
+        }
+        return Ok(-0.5 * (low + high));
+    }
+    let dx = (x_max - x_min) / num_x as f64;
+    let distorted: Vec<f64> = (0..=num_x)
+        .map(|index| match index {
+            0 => 0.0,
+            _ if index == num_x => 1.0,
+            _ => risk_measure.distortion(cdf(x_min + dx * index as f64)),
+        })
+        .collect();
+    Ok(distorted
+        .windows(2)
+        .enumerate()
+        .map(|(index, g)| -(x_min + dx * (index as f64 + 0.5)) * (g[1] - g[0]))
+        .sum())
+}
+
+/// A risk measure of the portfolio and its
+/// allocation to each loan.
+#[derive(Debug, Clone)]
+pub struct RiskMeasureResult {
+    /// The value of the risk measure.
+    pub risk_measure: f64,
+    /// The risk contribution of each loan.  The
+    /// contributions add to the risk measure.
+    pub contributions: Vec<f64>,
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns a built-in risk measure of a processed
+    /// portfolio and the contribution of each loan,
+    /// using
+    /// [risk_contribution](../fn.risk_contribution.html).
+    ///
+    /// # Arguments
+    ///
+    /// * `risk_measure` - The risk measure to evaluate.
+    /// * `loans` - The loans in the portfolio.
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables.
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    /// * `var_sys` - The vector of variances for the
+    ///   systemic random variables.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `x_min` - Lower truncation of the loss
+    ///   distribution.
+    /// * `x_max` - Upper truncation of the loss
+    ///   distribution.
+    /// * `num_x` - Number of grid intervals for
+    ///   distortion risk measures.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_risk_measure_contributions<U>(
+        &self,
+        risk_measure: &RiskMeasure,
+        loans: &[Loan],
+        mgf_systemic: &U,
+        el_sys: &[f64],
+        var_sys: &[f64],
+        lambda0: f64,
+        q: f64,
+        x_min: f64,
+        x_max: f64,
+        num_x: usize,
+    ) -> Result<RiskMeasureResult, EconomicCapitalError>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        let value = get_risk_measure(
+            risk_measure,
+            &self.get_full_cf(mgf_systemic),
+            x_min,
+            x_max,
+            num_x,
+        )?;
+        let expectation = portfolio_expectation(&self.el_vec, el_sys);
+        let variance = portfolio_variance(&self.el_vec, el_sys, &self.var_vec, var_sys);
+        let lambda = self.lambda + lambda0;
+        let liq_expectation = expectation_liquidity(lambda, q, expectation);
+        let liq_variance = variance_liquidity(lambda, q, expectation, variance);
+        let c = (value - liq_expectation) / liq_variance.sqrt();
+        Ok(RiskMeasureResult {
+            risk_measure: value,
+            contributions: loans
+                .iter()
+                .map(|loan| {
+                    risk_contribution(
+                        loan,
+                        &self.el_vec,
+                        el_sys,
+                        &self.var_vec,
+                        var_sys,
+                        lambda0,
+                        self.lambda,
+                        q,
+                        c,
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    const MU: f64 = -5.0;
+    const SIGMA: f64 = 2.0;
+    const X_MIN: f64 = -25.0;
+    const X_MAX: f64 = 15.0;
+    fn get_normal_cf() -> Vec<Complex<f64>> {
+        let norm_cf = |u: &Complex<f64>| (u * MU + 0.5 * SIGMA * SIGMA * u * u).exp();
+        fang_oost::get_discrete_cf(128, X_MIN, X_MAX, norm_cf)
+    }
+    fn measure(risk_measure: RiskMeasure) -> f64 {
+        get_risk_measure(&risk_measure, &get_normal_cf(), X_MIN, X_MAX, 4096).unwrap()
+    }
+    #[test]
+    fn expected_shortfall_matches_cf_dist_utils() {
+        let cf_dist_utils::RiskMetric {
+            expected_shortfall, ..
+        } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+            0.05,
+            X_MIN,
+            X_MAX,
+            100,
+            0.0000001,
+            &get_normal_cf(),
+        )
+        .unwrap();
+        assert_abs_diff_eq!(
+            measure(RiskMeasure::ExpectedShortfall { alpha: 0.05 }),
+            expected_shortfall,
+            epsilon = 0.001
+        );
+    }
+    #[test]
+    fn neutral_distortions_give_expected_loss() {
+        assert_abs_diff_eq!(
+            measure(RiskMeasure::WangTransform { lambda: 0.0 }),
+            -MU,
+            epsilon = 0.0001
+        );
+        assert_abs_diff_eq!(
+            measure(RiskMeasure::PowerDistortion { gamma: 1.0 }),
+            -MU,
+            epsilon = 0.0001
+        );
+        assert_abs_diff_eq!(
+            measure(RiskMeasure::ExpectedShortfall { alpha: 1.0 }),
+            -MU,
+            epsilon = 0.0001
+        );
+        assert_abs_diff_eq!(
+            measure(RiskMeasure::Expectile { tau: 0.5 }),
+            -MU,
+            epsilon = 0.0001
+        );
+    }
+    #[test]
+    fn measures_increase_with_risk_aversion() {
+        //the Wang transform of a normal shifts the mean by lambda*sigma
+        assert_abs_diff_eq!(
+            measure(RiskMeasure::WangTransform { lambda: 1.0 }),
+            -MU + SIGMA,
+            epsilon = 0.0001
+        );
+        assert!(
+            measure(RiskMeasure::ExponentialSpectral { gamma: 10.0 })
+                > measure(RiskMeasure::ExponentialSpectral { gamma: 1.0 })
+        );
+        assert!(
+            measure(RiskMeasure::PowerDistortion { gamma: 0.5 })
+                > measure(RiskMeasure::PowerDistortion { gamma: 0.9 })
+        );
+        assert!(
+            measure(RiskMeasure::Expectile { tau: 0.99 })
+                > measure(RiskMeasure::Expectile { tau: 0.9 })
+        );
+    }
+    #[test]
+    fn invalid_parameters_are_errors() {
+        let cf = get_normal_cf();
+        assert!(
+            get_risk_measure(&RiskMeasure::Expectile { tau: 1.0 }, &cf, X_MIN, X_MAX, 100).is_err()
+        );
+        assert!(get_risk_measure(
+            &RiskMeasure::PowerDistortion { gamma: 1.5 },
+            &cf,
+            X_MIN,
+            X_MAX,
+            100
+        )
+        .is_err());
+    }
+    #[test]
+    fn contributions_add_to_risk_measure() {
+        let num_u: usize = 256;
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let lambda0 = 100.0;
+        let q = 0.0001;
+        let loans = vec![
+            Loan {
+                pd: 0.05,
+                lgd: 0.5,
+                balance: 1.0,
+                r: 0.1,
+                lgd_variance: 0.0,
+                weight: vec![0.4, 0.6],
+                num: 5000.0,
+            },
+            Loan {
+                pd: 0.02,
+                lgd: 0.4,
+                balance: 2.0,
+                r: 0.0,
+                lgd_variance: 0.0,
+                weight: vec![1.0, 0.0],
+                num: 2000.0,
+            },
+        ];
+        let ec = process_test_loans(
+            exponential_lgd_cf,
+            lambda0,
+            q,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &loans,
+        );
+        let v = vec![0.4, 0.3];
+        let result = ec
+            .get_risk_measure_contributions(
+                &RiskMeasure::ExponentialSpectral { gamma: 20.0 },
+                &loans,
+                &gamma_mgf(&v),
+                &[1.0, 1.0],
+                &v,
+                lambda0,
+                q,
+                x_min,
+                x_max,
+                1024,
+            )
+            .unwrap();
+        assert!(result.risk_measure > 0.0);
+        assert_abs_diff_eq!(
+            result.contributions.iter().sum::<f64>(),
+            result.risk_measure,
+            epsilon = 0.0001
+        );
+    }
+}
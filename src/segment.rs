@@ -0,0 +1,308 @@
+//! Loans with an identity and attributes, and
+//! aggregation of their risk contributions over a
+//! user-defined hierarchy of attributes (eg, business
+//! line, then region, then product).
+
+use crate::error::EconomicCapitalError;
+use crate::{
+    expectation_liquidity, get_el_from_loan, portfolio_expectation, portfolio_variance,
+    risk_contribution, variance_liquidity, EconomicCapitalAttributes, Loan,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Name of the segment for loans without the tag
+/// for a level of the hierarchy.
+pub const UNASSIGNED: &str = "unassigned";
+/// Name of the root of the hierarchy.
+pub const PORTFOLIO: &str = "portfolio";
+
+/// Struct for a loan with an identifier and tags.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoanRecord {
+    /// Identifier of the loan.
+    pub id: String,
+    #[serde(default)]
+    /// Attributes of the loan, eg "region": "west".
+    pub tags: HashMap<String, String>,
+    #[serde(flatten)]
+    /// Risk parameters of the loan.
+    pub loan: Loan,
+}
+
+/// Aggregated metrics for a segment and its
+/// sub-segments.
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentReport {
+    /// Tag value of the segment (or "portfolio" for
+    /// the root).
+    pub name: String,
+    /// Number of loan records in the segment.
+    pub num_records: usize,
+    /// Exposure (balance times num) of the segment.
+    pub exposure: f64,
+    /// Expected loss (positive, in dollars) of the
+    /// segment.
+    pub expected_loss: f64,
+    /// Sum of the risk contributions of the segment.
+    pub risk_contribution: f64,
+    /// Risk contribution as a fraction of the
+    /// portfolio risk measure.
+    pub share: f64,
+    /// Sub-segments at the next level of the
+    /// hierarchy, ordered by name.
+    pub children: Vec<SegmentReport>,
+}
+
+fn get_expected_loss(loan: &Loan) -> f64 {
+    -loan
+        .weight
+        .iter()
+        .map(|&w| get_el_from_loan(loan, w))
+        .sum::<f64>()
+}
+
+fn build_segment(
+    name: &str,
+    indices: &[usize],
+    records: &[LoanRecord],
+    contributions: &[f64],
+    hierarchy: &[&str],
+    risk_measure: f64,
+) -> SegmentReport {
+    let risk_contribution: f64 = indices.iter().map(|&index| contributions[index]).sum();
+    let children = match hierarchy.split_first() {
+        Some((level, rest)) => {
+            let mut groups: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+            indices.iter().for_each(|&index| {
+                let tag = records[index]
+                    .tags
+                    .get(*level)
+                    .map_or(UNASSIGNED, |tag| tag.as_str());
+                groups.entry(tag).or_default().push(index);
+            });
+            groups
+                .iter()
+                .map(|(tag, group)| {
+                    build_segment(tag, group, records, contributions, rest, risk_measure)
+                })
+                .collect()
+        }
+        None => vec![],
+    };
+    SegmentReport {
+        name: name.to_string(),
+        num_records: indices.len(),
+        exposure: indices
+            .iter()
+            .map(|&index| records[index].loan.balance * records[index].loan.num)
+            .sum(),
+        expected_loss: indices
+            .iter()
+            .map(|&index| get_expected_loss(&records[index].loan))
+            .sum(),
+        risk_contribution,
+        share: risk_contribution / risk_measure,
+        children,
+    }
+}
+
+/// Returns the hierarchical report of risk
+/// contributions.  The root is the whole portfolio
+/// and each level of the hierarchy groups the loans
+/// by the value of a tag.  When the contributions are
+/// computed with
+/// [get_risk_contributions](../struct.EconomicCapitalAttributes.html#method.get_risk_contributions)
+/// the root total equals the portfolio risk measure.
+///
+/// # Arguments
+///
+/// * `records` - The loans in the portfolio.
+/// * `contributions` - The risk contribution of each loan.
+///   Must have an element per loan.
+/// * `hierarchy` - The tag names defining each level.
+/// * `risk_measure` - The portfolio risk measure (eg, VaR).
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// # fn main(){
+/// let records: Vec<loan_ec::segment::LoanRecord> = serde_json::from_str(r#"[
+///     {"id": "a", "tags": {"region": "west"}, "pd": 0.05, "lgd": 0.5,
+///      "balance": 1.0, "r": 0.0, "lgd_variance": 0.0, "weight": [1.0], "num": 1.0},
+///     {"id": "b", "tags": {"region": "east"}, "pd": 0.05, "lgd": 0.5,
+///      "balance": 1.0, "r": 0.0, "lgd_variance": 0.0, "weight": [1.0], "num": 1.0}
+/// ]"#).unwrap();
+/// let report = loan_ec::segment::get_segment_report(
+///     &records, &[3.0, 1.0], &["region"], 4.0
+/// ).unwrap();
+/// assert_eq!(report.children[0].name, "east");
+/// assert_eq!(report.children[1].share, 0.75);
+/// # }
+/// ```
+pub fn get_segment_report(
+    records: &[LoanRecord],
+    contributions: &[f64],
+    hierarchy: &[&str],
+    risk_measure: f64,
+) -> Result<SegmentReport, EconomicCapitalError> {
+    if records.len() != contributions.len() {
+        return Err(EconomicCapitalError::new(
+            "Contributions must have an element per loan.",
+        ));
+    }
+    let indices: Vec<usize> = (0..records.len()).collect();
+    Ok(build_segment(
+        PORTFOLIO,
+        &indices,
+        records,
+        contributions,
+        hierarchy,
+        risk_measure,
+    ))
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the risk contribution of each loan
+    /// record in a processed portfolio.  The
+    /// contributions add to `risk_measure`.
+    ///
+    /// # Arguments
+    ///
+    /// * `records` - The loans in the portfolio.
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    /// * `var_sys` - The vector of variances for the
+    ///   systemic random variables.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `risk_measure` - The portfolio risk measure (eg, VaR).
+    pub fn get_risk_contributions(
+        &self,
+        records: &[LoanRecord],
+        el_sys: &[f64],
+        var_sys: &[f64],
+        lambda0: f64,
+        q: f64,
+        risk_measure: f64,
+    ) -> Vec<f64> {
+        let expectation = portfolio_expectation(&self.el_vec, el_sys);
+        let variance = portfolio_variance(&self.el_vec, el_sys, &self.var_vec, var_sys);
+        let lambda = self.lambda + lambda0;
+        let liq_expectation = expectation_liquidity(lambda, q, expectation);
+        let liq_variance = variance_liquidity(lambda, q, expectation, variance);
+        let c = (risk_measure - liq_expectation) / liq_variance.sqrt();
+        records
+            .iter()
+            .map(|record| {
+                risk_contribution(
+                    &record.loan,
+                    &self.el_vec,
+                    el_sys,
+                    &self.var_vec,
+                    var_sys,
+                    lambda0,
+                    self.lambda,
+                    q,
+                    c,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_record(id: &str, line: &str, region: Option<&str>, num: f64) -> LoanRecord {
+        let mut tags = HashMap::new();
+        tags.insert("line".to_string(), line.to_string());
+        if let Some(region) = region {
+            tags.insert("region".to_string(), region.to_string());
+        }
+        LoanRecord {
+            id: id.to_string(),
+            tags,
+            loan: Loan {
+                pd: 0.05,
+                lgd: 0.5,
+                balance: 1.0,
+                r: 0.1,
+                lgd_variance: 0.2,
+                weight: vec![0.4, 0.6],
+                num,
+            },
+        }
+    }
+    #[test]
+    fn report_ties_to_risk_measure() {
+        let num_u = 128;
+        let lambda0 = 100.0;
+        let q = 0.0001;
+        let u_domain = get_test_u_domain(num_u, -5000.0, 0.0);
+        let log_lpm_cf = get_test_log_lpm_cf(lambda0, q);
+        let records = vec![
+            get_record("1", "retail", Some("west"), 1000.0),
+            get_record("2", "retail", Some("east"), 2000.0),
+            get_record("3", "retail", None, 500.0),
+            get_record("4", "commercial", Some("west"), 3000.0),
+        ];
+        let mut ec = EconomicCapitalAttributes::new(num_u, 2);
+        records
+            .iter()
+            .for_each(|record| ec.process_loan(&record.loan, &u_domain, &log_lpm_cf));
+        let risk_measure = 300.0;
+        let contributions =
+            ec.get_risk_contributions(&records, &[1.0, 1.0], &[0.4, 0.3], lambda0, q, risk_measure);
+        let report =
+            get_segment_report(&records, &contributions, &["line", "region"], risk_measure)
+                .unwrap();
+        assert_eq!(report.name, PORTFOLIO);
+        assert_eq!(report.num_records, 4);
+        assert_abs_diff_eq!(report.risk_contribution, risk_measure, epsilon = 0.0001);
+        assert_abs_diff_eq!(report.share, 1.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(report.exposure, 6500.0, epsilon = 0.0000001);
+        let names: Vec<&str> = report.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["commercial", "retail"]);
+        let retail = &report.children[1];
+        let regions: Vec<&str> = retail.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(regions, vec!["east", UNASSIGNED, "west"]);
+        assert_abs_diff_eq!(
+            retail.risk_contribution,
+            contributions[0] + contributions[1] + contributions[2],
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            retail
+                .children
+                .iter()
+                .map(|c| c.risk_contribution)
+                .sum::<f64>(),
+            retail.risk_contribution,
+            epsilon = 0.0000001
+        );
+        assert!(retail.children[0].children.is_empty());
+    }
+    #[test]
+    fn report_requires_contribution_per_loan() {
+        let records = vec![
+            get_record("1", "retail", Some("west"), 1.0),
+            get_record("2", "retail", Some("east"), 1.0),
+        ];
+        assert!(get_segment_report(&records, &[1.0], &["line"], 1.0).is_err());
+        assert!(get_segment_report(&records, &[1.0, 1.0, 1.0], &["line"], 1.0).is_err());
+    }
+    #[test]
+    fn records_deserialize_without_tags() {
+        let record: LoanRecord = serde_json::from_str(
+            r#"{"id": "a", "pd": 0.05, "lgd": 0.5, "balance": 1.0, "r": 0.0, "lgd_variance": 0.0, "weight": [1.0], "num": 1.0}"#,
+        )
+        .unwrap();
+        assert!(record.tags.is_empty());
+        assert_eq!(record.loan.pd, 0.05);
+    }
+}
@@ -0,0 +1,764 @@
+//! Sensitivities of the value at risk and expected
+//! shortfall to the model parameters, computed from
+//! a processed portfolio without reprocessing the
+//! loans.
+//!
+//! The value at risk x solves F(x)=alpha, so that
+//! dVaR = dF(x)/f(x) and
+//! dES = -(dP(x)-x dF(x))/alpha, where P is the
+//! partial expectation.  dF and dP are computed from
+//! the derivative of the characteristic function,
+//! which is analytic for Gamma systemic variables.
+//! Liquidity risk is applied to the characteristic
+//! function of the loss, so that the q and lambda0
+//! sensitivities include its effect on the whole
+//! distribution.
+
+use crate::cf_transform::{
+    get_cdf, get_density, get_density_coefficients, get_liquidity_cf, get_partial_expectation,
+};
+use crate::error::EconomicCapitalError;
+use crate::{systemic_gamma_mgf, EconomicCapitalAttributes};
+use num_complex::Complex;
+use rayon::prelude::*;
+
+/// Derivative of the risk measures with respect to a
+/// single parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sensitivity {
+    /// Derivative of the value at risk.
+    pub value_at_risk: f64,
+    /// Derivative of the expected shortfall.
+    pub expected_shortfall: f64,
+}
+
+/// Risk measures of the portfolio and their
+/// sensitivities.  The PD and LGD sensitivities are
+/// with respect to a multiplier applied to every
+/// loan, so they represent the change for a 100%
+/// relative shift.
+#[derive(Debug, Clone)]
+pub struct RiskSensitivities {
+    /// Value at risk of the portfolio (positive).
+    pub value_at_risk: f64,
+    /// Expected shortfall of the portfolio (positive).
+    pub expected_shortfall: f64,
+    /// Sensitivity to the variance of each systemic
+    /// random variable.
+    pub variance: Vec<Sensitivity>,
+    /// Sensitivity to a uniform PD multiplier.
+    pub pd: Sensitivity,
+    /// Sensitivity to a uniform LGD multiplier.
+    pub lgd: Sensitivity,
+    /// Sensitivity to the probability of a
+    /// liquidity event.
+    pub q: Sensitivity,
+    /// Sensitivity to the base liquidity loss.
+    pub lambda0: Sensitivity,
+}
+
+/// Value at risk and expected shortfall of a
+/// characteristic function, keeping the density
+/// coefficients for the derivatives.
+struct RiskMeasures {
+    coefficients: Vec<f64>,
+    value_at_risk: f64,
+    expected_shortfall: f64,
+}
+
+impl RiskMeasures {
+    fn new(
+        discrete_cf: &[Complex<f64>],
+        x_min: f64,
+        x_max: f64,
+        alpha: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<Self, EconomicCapitalError> {
+        let coefficients = get_density_coefficients(discrete_cf, x_min, x_max);
+        let cf_dist_utils::RiskMetric {
+            value_at_risk,
+            expected_shortfall,
+        } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+            alpha,
+            x_min,
+            x_max,
+            max_iterations,
+            tolerance,
+            discrete_cf,
+        )?;
+        Ok(RiskMeasures {
+            coefficients,
+            value_at_risk,
+            expected_shortfall,
+        })
+    }
+    /// Returns the sensitivity given the derivative
+    /// of the characteristic function.
+    fn get_sensitivity(
+        &self,
+        d_cf: &[Complex<f64>],
+        x_min: f64,
+        x_max: f64,
+        alpha: f64,
+    ) -> Sensitivity {
+        let d_coefficients = get_density_coefficients(d_cf, x_min, x_max);
+        let x = -self.value_at_risk;
+        let d_cdf = get_cdf(&d_coefficients, x_min, x_max, x);
+        let density = get_density(&self.coefficients, x_min, x_max, x);
+        Sensitivity {
+            value_at_risk: d_cdf / density,
+            expected_shortfall: -(get_partial_expectation(&d_coefficients, x_min, x_max, x)
+                - x * d_cdf)
+                / alpha,
+        }
+    }
+    /// Returns the finite difference sensitivity
+    /// between two risk measures `width` apart.
+    fn get_difference_sensitivity(up: &Self, down: &Self, width: f64) -> Sensitivity {
+        Sensitivity {
+            value_at_risk: (up.value_at_risk - down.value_at_risk) / width,
+            expected_shortfall: (up.expected_shortfall - down.expected_shortfall) / width,
+        }
+    }
+}
+
+/// Returns the step for the central difference of a
+/// liquidity parameter: relative to the parameter
+/// when it is positive and relative to `scale`
+/// otherwise.
+fn get_liquidity_step(value: f64, scale: f64, step: f64) -> f64 {
+    if value > 0.0 {
+        value * step
+    } else {
+        scale * step
+    }
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the full characteristic function with
+    /// the probabilities of default multiplied by
+    /// `pd_multiplier` and the given systemic variances.
+    fn get_parameterized_full_cf<U>(
+        &self,
+        mgf_systemic: &U,
+        variance: &[f64],
+        pd_multiplier: f64,
+    ) -> Vec<Complex<f64>>
+    where
+        U: Fn(&[Complex<f64>], &[f64]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        self.cf
+            .par_chunks(self.num_w)
+            .map(|u_weights| {
+                let scaled: Vec<Complex<f64>> =
+                    u_weights.iter().map(|u| u * pd_multiplier).collect();
+                mgf_systemic(&scaled, variance)
+            })
+            .collect()
+    }
+    /// Checks that there is a variance per systemic
+    /// variable.
+    fn check_variance(&self, variance: &[f64]) -> Result<(), EconomicCapitalError> {
+        if variance.len() != self.num_w {
+            return Err(EconomicCapitalError::new(
+                "Variance does not match the number of systemic variables.",
+            ));
+        }
+        Ok(())
+    }
+    /// Returns the LGD, q and lambda0 sensitivities,
+    /// which are shared by the analytic and finite
+    /// difference methods.  Each is a central finite
+    /// difference of the characteristic function with
+    /// the loss scaled or the liquidity risk bumped.
+    #[allow(clippy::too_many_arguments)]
+    fn get_common_sensitivities(
+        &self,
+        full_cf: &[Complex<f64>],
+        lambda0: f64,
+        q: f64,
+        alpha: f64,
+        x_min: f64,
+        x_max: f64,
+        step: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<(Sensitivity, Sensitivity, Sensitivity), EconomicCapitalError> {
+        let lambda = self.lambda + lambda0;
+        let risk_measures_at = |lambda: f64, q: f64, scale: f64| {
+            RiskMeasures::new(
+                &get_liquidity_cf(full_cf, x_min, x_max, lambda, q, scale),
+                x_min,
+                x_max,
+                alpha,
+                tolerance,
+                max_iterations,
+            )
+        };
+        let lgd = RiskMeasures::get_difference_sensitivity(
+            &risk_measures_at(lambda, q, 1.0 + step)?,
+            &risk_measures_at(lambda, q, 1.0 - step)?,
+            2.0 * step,
+        );
+        //with no liquidity event the step in q is relative to 1/lambda
+        let q_step = get_liquidity_step(q, if lambda > 0.0 { 1.0 / lambda } else { 1.0 }, step);
+        let q_sensitivity = RiskMeasures::get_difference_sensitivity(
+            &risk_measures_at(lambda, q + q_step, 1.0)?,
+            &risk_measures_at(lambda, q - q_step, 1.0)?,
+            2.0 * q_step,
+        );
+        let lambda_step = get_liquidity_step(lambda, 1.0, step);
+        let lambda0_sensitivity = RiskMeasures::get_difference_sensitivity(
+            &risk_measures_at(lambda + lambda_step, q, 1.0)?,
+            &risk_measures_at(lambda - lambda_step, q, 1.0)?,
+            2.0 * lambda_step,
+        );
+        Ok((lgd, q_sensitivity, lambda0_sensitivity))
+    }
+    /// Returns the value at risk and expected shortfall
+    /// of the portfolio and their sensitivities for
+    /// Gamma distributed systemic variables with mean
+    /// one.  The variance and PD sensitivities are
+    /// analytic; the LGD, q and lambda0 sensitivities
+    /// use central finite differences of the loss
+    /// distribution.  Liquidity risk is applied to the
+    /// characteristic function, so the loans must be
+    /// processed without it, ie with
+    /// [get_liquidity_risk_fn(0.0, 0.0)](../fn.get_liquidity_risk_fn.html).
+    ///
+    /// # Arguments
+    ///
+    /// * `variance` - The variance of each systemic
+    ///   random variable.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `alpha` - Tail probability of the risk measures.
+    /// * `x_min` - Lower truncation of the loss distribution.
+    /// * `x_max` - Upper truncation of the loss distribution.
+    /// * `step` - Relative step for the LGD, q and lambda0
+    ///   finite differences.
+    /// * `tolerance` - Tolerance for the value at risk.
+    /// * `max_iterations` - Maximum number of iterations
+    ///   for the value at risk.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=256;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.0,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 10000.0
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let sensitivities = ec_attributes.get_sensitivities(
+    ///     &[0.4], 0.0, 0.0, 0.01, x_min, x_max, 0.01, 0.000001, 100
+    /// ).unwrap();
+    /// assert!(sensitivities.variance[0].value_at_risk > 0.0);
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_sensitivities(
+        &self,
+        variance: &[f64],
+        lambda0: f64,
+        q: f64,
+        alpha: f64,
+        x_min: f64,
+        x_max: f64,
+        step: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<RiskSensitivities, EconomicCapitalError> {
+        self.check_variance(variance)?;
+        let lambda = self.lambda + lambda0;
+        let full_cf = self.get_parameterized_full_cf(&systemic_gamma_mgf, variance, 1.0);
+        let risk_measures = RiskMeasures::new(
+            &get_liquidity_cf(&full_cf, x_min, x_max, lambda, q, 1.0),
+            x_min,
+            x_max,
+            alpha,
+            tolerance,
+            max_iterations,
+        )?;
+        //the liquidity transform is linear in the characteristic function
+        let get_sensitivity = |d_cf: &[Complex<f64>]| {
+            risk_measures.get_sensitivity(
+                &get_liquidity_cf(d_cf, x_min, x_max, lambda, q, 1.0),
+                x_min,
+                x_max,
+                alpha,
+            )
+        };
+        let variance_sensitivity = (0..self.num_w)
+            .map(|index| {
+                let v = variance[index];
+                let d_cf: Vec<Complex<f64>> = self
+                    .cf
+                    .chunks(self.num_w)
+                    .zip(&full_cf)
+                    .map(|(u_weights, cf)| {
+                        let u = u_weights[index];
+                        if v == 0.0 {
+                            //limit as the variance goes to zero
+                            cf * u * u * 0.5
+                        } else {
+                            let one_minus = 1.0 - v * u;
+                            cf * (one_minus.ln() / v.powi(2) + u / (v * one_minus))
+                        }
+                    })
+                    .collect();
+                get_sensitivity(&d_cf)
+            })
+            .collect();
+        let d_pd_cf: Vec<Complex<f64>> = self
+            .cf
+            .chunks(self.num_w)
+            .zip(&full_cf)
+            .map(|(u_weights, cf)| {
+                cf * u_weights
+                    .iter()
+                    .zip(variance)
+                    .map(|(u, v)| u / (1.0 - v * u))
+                    .sum::<Complex<f64>>()
+            })
+            .collect();
+        let (lgd, q_sensitivity, lambda0_sensitivity) = self.get_common_sensitivities(
+            &full_cf,
+            lambda0,
+            q,
+            alpha,
+            x_min,
+            x_max,
+            step,
+            tolerance,
+            max_iterations,
+        )?;
+        Ok(RiskSensitivities {
+            value_at_risk: risk_measures.value_at_risk,
+            expected_shortfall: risk_measures.expected_shortfall,
+            variance: variance_sensitivity,
+            pd: get_sensitivity(&d_pd_cf),
+            lgd,
+            q: q_sensitivity,
+            lambda0: lambda0_sensitivity,
+        })
+    }
+    /// Returns the value at risk and expected shortfall
+    /// of the portfolio and their sensitivities for
+    /// any systemic distribution, using central finite
+    /// differences for every sensitivity.  As for
+    /// [get_sensitivities](#method.get_sensitivities),
+    /// the loans must be processed without liquidity
+    /// risk.
+    ///
+    /// # Arguments
+    ///
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the systemic random variables, as a
+    ///   function of the variances.
+    /// * `variance` - The variance of each systemic
+    ///   random variable.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `alpha` - Tail probability of the risk measures.
+    /// * `x_min` - Lower truncation of the loss distribution.
+    /// * `x_max` - Upper truncation of the loss distribution.
+    /// * `step` - Step for the finite differences.  It
+    ///   is absolute for the variances and relative
+    ///   for the other parameters.  Variances below
+    ///   the step use a forward difference.
+    /// * `tolerance` - Tolerance for the value at risk.
+    /// * `max_iterations` - Maximum number of iterations
+    ///   for the value at risk.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_sensitivities_finite_difference<U>(
+        &self,
+        mgf_systemic: &U,
+        variance: &[f64],
+        lambda0: f64,
+        q: f64,
+        alpha: f64,
+        x_min: f64,
+        x_max: f64,
+        step: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<RiskSensitivities, EconomicCapitalError>
+    where
+        U: Fn(&[Complex<f64>], &[f64]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    {
+        self.check_variance(variance)?;
+        let lambda = self.lambda + lambda0;
+        let risk_measures_at = |variance: &[f64], pd_multiplier: f64| {
+            RiskMeasures::new(
+                &get_liquidity_cf(
+                    &self.get_parameterized_full_cf(mgf_systemic, variance, pd_multiplier),
+                    x_min,
+                    x_max,
+                    lambda,
+                    q,
+                    1.0,
+                ),
+                x_min,
+                x_max,
+                alpha,
+                tolerance,
+                max_iterations,
+            )
+        };
+        let risk_measures = risk_measures_at(variance, 1.0)?;
+        let variance_sensitivity = (0..self.num_w)
+            .map(|index| {
+                let mut bumped = variance.to_vec();
+                bumped[index] = variance[index] + step;
+                let up = risk_measures_at(&bumped, 1.0)?;
+                //forward difference to keep the variance non-negative
+                if variance[index] < step {
+                    return Ok(RiskMeasures::get_difference_sensitivity(
+                        &up,
+                        &risk_measures,
+                        step,
+                    ));
+                }
+                bumped[index] = variance[index] - step;
+                let down = risk_measures_at(&bumped, 1.0)?;
+                Ok(RiskMeasures::get_difference_sensitivity(
+                    &up,
+                    &down,
+                    2.0 * step,
+                ))
+            })
+            .collect::<Result<Vec<Sensitivity>, EconomicCapitalError>>()?;
+        let pd = RiskMeasures::get_difference_sensitivity(
+            &risk_measures_at(variance, 1.0 + step)?,
+            &risk_measures_at(variance, 1.0 - step)?,
+            2.0 * step,
+        );
+        let (lgd, q_sensitivity, lambda0_sensitivity) = self.get_common_sensitivities(
+            &self.get_parameterized_full_cf(mgf_systemic, variance, 1.0),
+            lambda0,
+            q,
+            alpha,
+            x_min,
+            x_max,
+            step,
+            tolerance,
+            max_iterations,
+        )?;
+        Ok(RiskSensitivities {
+            value_at_risk: risk_measures.value_at_risk,
+            expected_shortfall: risk_measures.expected_shortfall,
+            variance: variance_sensitivity,
+            pd,
+            lgd,
+            q: q_sensitivity,
+            lambda0: lambda0_sensitivity,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_test_attributes(
+        num_u: usize,
+        x_min: f64,
+        x_max: f64,
+        lambda0: f64,
+        q: f64,
+    ) -> EconomicCapitalAttributes {
+        process_test_loans(
+            gamma_lgd_cf,
+            lambda0,
+            q,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &get_test_loans(),
+        )
+    }
+    fn get_reprocessed_risk_measures(lambda0: f64, q: f64) -> (f64, f64) {
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(256, x_min, x_max, lambda0, q);
+        let cf_dist_utils::RiskMetric {
+            value_at_risk,
+            expected_shortfall,
+        } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+            0.01,
+            x_min,
+            x_max,
+            100,
+            0.0000001,
+            &ec.get_full_cf(&gamma_mgf(&[0.4, 0.3])),
+        )
+        .unwrap();
+        (value_at_risk, expected_shortfall)
+    }
+    #[test]
+    fn analytic_matches_finite_difference() {
+        let x_min = -3000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(256, x_min, x_max, 0.0, 0.0);
+        let variance = vec![0.4, 0.3];
+        let analytic = ec
+            .get_sensitivities(
+                &variance, 0.0, 0.0, 0.01, x_min, x_max, 0.001, 0.0000001, 100,
+            )
+            .unwrap();
+        let finite_difference = ec
+            .get_sensitivities_finite_difference(
+                &systemic_gamma_mgf,
+                &variance,
+                0.0,
+                0.0,
+                0.01,
+                x_min,
+                x_max,
+                0.001,
+                0.0000001,
+                100,
+            )
+            .unwrap();
+        analytic
+            .variance
+            .iter()
+            .zip(&finite_difference.variance)
+            .for_each(|(a, f)| {
+                assert_relative_eq!(a.value_at_risk, f.value_at_risk, max_relative = 0.0002);
+                assert_relative_eq!(
+                    a.expected_shortfall,
+                    f.expected_shortfall,
+                    max_relative = 0.001
+                );
+            });
+        assert_relative_eq!(
+            analytic.pd.value_at_risk,
+            finite_difference.pd.value_at_risk,
+            max_relative = 0.0002
+        );
+        assert_relative_eq!(
+            analytic.pd.expected_shortfall,
+            finite_difference.pd.expected_shortfall,
+            max_relative = 0.001
+        );
+        assert!(analytic.variance[0].value_at_risk > 0.0);
+        assert!(analytic.pd.value_at_risk > 0.0);
+    }
+    #[test]
+    fn zero_variance_uses_limit() {
+        let x_min = -3000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(256, x_min, x_max, 0.0, 0.0);
+        let variance = vec![0.0, 0.3];
+        let analytic = ec
+            .get_sensitivities(
+                &variance, 0.0, 0.0, 0.01, x_min, x_max, 0.0001, 0.0000001, 100,
+            )
+            .unwrap();
+        let finite_difference = ec
+            .get_sensitivities_finite_difference(
+                &systemic_gamma_mgf,
+                &variance,
+                0.0,
+                0.0,
+                0.01,
+                x_min,
+                x_max,
+                0.0001,
+                0.0000001,
+                100,
+            )
+            .unwrap();
+        let (a, f) = (&analytic.variance[0], &finite_difference.variance[0]);
+        assert!(a.value_at_risk.is_finite() && a.value_at_risk > 0.0);
+        assert_relative_eq!(a.value_at_risk, f.value_at_risk, max_relative = 0.01);
+        assert_relative_eq!(
+            a.expected_shortfall,
+            f.expected_shortfall,
+            max_relative = 0.01
+        );
+        assert!(ec
+            .get_sensitivities(&[0.4], 0.0, 0.0, 0.01, x_min, x_max, 0.001, 0.0000001, 100)
+            .is_err());
+        assert!(ec
+            .get_sensitivities_finite_difference(
+                &systemic_gamma_mgf,
+                &[0.4],
+                0.0,
+                0.0,
+                0.01,
+                x_min,
+                x_max,
+                0.001,
+                0.0000001,
+                100,
+            )
+            .is_err());
+    }
+    #[test]
+    fn liquidity_matches_reprocessed_loans() {
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let lambda0 = 1000.0;
+        let q = 0.0001;
+        let step = 0.01;
+        let ec = get_test_attributes(256, x_min, x_max, 0.0, 0.0);
+        let sensitivities = ec
+            .get_sensitivities(
+                &[0.4, 0.3],
+                lambda0,
+                q,
+                0.01,
+                x_min,
+                x_max,
+                step,
+                0.0000001,
+                100,
+            )
+            .unwrap();
+        let (value_at_risk, expected_shortfall) = get_reprocessed_risk_measures(lambda0, q);
+        assert_abs_diff_eq!(sensitivities.value_at_risk, value_at_risk, epsilon = 0.001);
+        assert_abs_diff_eq!(
+            sensitivities.expected_shortfall,
+            expected_shortfall,
+            epsilon = 0.001
+        );
+        let (q_up, _) = get_reprocessed_risk_measures(lambda0, q * (1.0 + step));
+        let (q_down, _) = get_reprocessed_risk_measures(lambda0, q * (1.0 - step));
+        assert_relative_eq!(
+            sensitivities.q.value_at_risk,
+            (q_up - q_down) / (2.0 * q * step),
+            max_relative = 0.00001
+        );
+        let (lambda_up, _) = get_reprocessed_risk_measures(lambda0 * (1.0 + step), q);
+        let (lambda_down, _) = get_reprocessed_risk_measures(lambda0 * (1.0 - step), q);
+        assert_relative_eq!(
+            sensitivities.lambda0.value_at_risk,
+            (lambda_up - lambda_down) / (2.0 * lambda0 * step),
+            max_relative = 0.00001
+        );
+    }
+    #[test]
+    fn risk_measures_match_cf_dist_utils() {
+        let x_min = -3000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(256, x_min, x_max, 0.0, 0.0);
+        let variance = vec![0.4, 0.3];
+        let sensitivities = ec
+            .get_sensitivities(
+                &variance, 0.0, 0.0, 0.01, x_min, x_max, 0.001, 0.0000001, 100,
+            )
+            .unwrap();
+        let cf_dist_utils::RiskMetric {
+            value_at_risk,
+            expected_shortfall,
+        } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+            0.01,
+            x_min,
+            x_max,
+            100,
+            0.0000001,
+            &ec.get_full_cf(&gamma_mgf(&variance)),
+        )
+        .unwrap();
+        assert_abs_diff_eq!(sensitivities.value_at_risk, value_at_risk, epsilon = 0.001);
+        assert_abs_diff_eq!(
+            sensitivities.expected_shortfall,
+            expected_shortfall,
+            epsilon = 0.01
+        );
+    }
+    #[test]
+    fn lgd_sensitivity_is_homogeneous() {
+        let x_min = -3000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(256, x_min, x_max, 0.0, 0.0);
+        let sensitivities = ec
+            .get_sensitivities(
+                &[0.4, 0.3],
+                0.0,
+                0.0,
+                0.01,
+                x_min,
+                x_max,
+                0.01,
+                0.0000001,
+                100,
+            )
+            .unwrap();
+        assert_relative_eq!(
+            sensitivities.lgd.value_at_risk,
+            sensitivities.value_at_risk,
+            max_relative = 0.0001
+        );
+        assert_relative_eq!(
+            sensitivities.lgd.expected_shortfall,
+            sensitivities.expected_shortfall,
+            max_relative = 0.0001
+        );
+    }
+    #[test]
+    fn liquidity_sensitivities_are_positive() {
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let lambda0 = 1000.0;
+        let q = 0.0001;
+        let ec = get_test_attributes(256, x_min, x_max, 0.0, 0.0);
+        let sensitivities = ec
+            .get_sensitivities(
+                &[0.4, 0.3],
+                lambda0,
+                q,
+                0.01,
+                x_min,
+                x_max,
+                0.01,
+                0.0000001,
+                100,
+            )
+            .unwrap();
+        assert!(sensitivities.q.value_at_risk > 0.0);
+        assert!(sensitivities.q.expected_shortfall > sensitivities.q.value_at_risk);
+        assert!(sensitivities.lambda0.value_at_risk > 0.0);
+        //without a liquidity event only q moves the risk measures
+        let no_liquidity = ec
+            .get_sensitivities(
+                &[0.4, 0.3],
+                lambda0,
+                0.0,
+                0.01,
+                x_min,
+                x_max,
+                0.01,
+                0.0000001,
+                100,
+            )
+            .unwrap();
+        assert!(no_liquidity.q.value_at_risk > 0.0);
+        assert_abs_diff_eq!(no_liquidity.lambda0.value_at_risk, 0.0, epsilon = 0.000001);
+    }
+}
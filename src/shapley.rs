@@ -0,0 +1,227 @@
+//! Shapley value allocation of a risk measure across
+//! groups of loans (eg, business units).  Each group is
+//! processed into its own
+//! [EconomicCapitalAttributes](../struct.EconomicCapitalAttributes.html)
+//! and every coalition of groups is built by merging the
+//! group accumulators, so no loan is processed twice.
+//! The allocation of group i is
+//! sum_S |S|!(n-|S|-1)!/n! (v(S+i)-v(S)) over the
+//! coalitions S without i.
+
+use crate::error::EconomicCapitalError;
+use crate::EconomicCapitalAttributes;
+use serde_derive::Serialize;
+
+/// Largest number of groups, since the number of
+/// coalitions grows as 2^n.
+pub const MAX_GROUPS: usize = 16;
+
+/// Shapley allocation of a risk measure.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShapleyAllocation {
+    /// Allocation to each group.  The allocations add
+    /// to `total`.
+    pub allocations: Vec<f64>,
+    /// Standalone risk measure of each group.
+    pub standalone: Vec<f64>,
+    /// Risk measure of the portfolio of all groups.
+    pub total: f64,
+}
+
+/// Returns the merged attributes of the groups in a
+/// coalition (represented as a bit mask).
+fn get_coalition(groups: &[EconomicCapitalAttributes], mask: usize) -> EconomicCapitalAttributes {
+    let first = &groups[0];
+    let num_u = first.cf.len() / first.num_w;
+    let mut coalition = EconomicCapitalAttributes::new(num_u, first.num_w);
+    groups
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| mask & (1 << index) != 0)
+        .for_each(|(_, group)| coalition.merge(group));
+    coalition
+}
+
+/// Returns the Shapley allocation of a risk measure
+/// across groups of loans.  The risk measure of the
+/// empty coalition is zero.
+///
+/// # Arguments
+///
+/// * `groups` - The processed attributes of each
+///   group.  All groups must use the same u domain and
+///   number of systemic variables.
+/// * `risk_measure_fn` - Function which computes the
+///   risk measure of a coalition from its attributes,
+///   eg the VaR from
+///   [get_full_cf](../struct.EconomicCapitalAttributes.html#method.get_full_cf).
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// extern crate fang_oost;
+/// extern crate rayon;
+/// extern crate num_complex;
+/// extern crate cf_dist_utils;
+/// use rayon::prelude::*;
+/// use num_complex::Complex;
+/// # fn main(){
+/// let num_u:usize=128;
+/// let x_min=-5000.0;
+/// let x_max=0.0;
+/// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+/// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+/// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+/// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+/// let groups: Vec<loan_ec::EconomicCapitalAttributes> = vec![
+///     vec![1.0, 0.0], vec![0.0, 1.0]
+/// ].into_iter().map(|weight| {
+///     let mut ec_attributes = loan_ec::EconomicCapitalAttributes::new(num_u, 2);
+///     let loan = loan_ec::Loan{
+///         pd: 0.05, lgd: 0.5, lgd_variance: 0.0, balance: 1.0,
+///         r: 0.0, weight, num: 5000.0
+///     };
+///     ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+///     ec_attributes
+/// }).collect();
+/// let v = vec![0.4, 0.3];
+/// let risk_measure_fn = |coalition: &loan_ec::EconomicCapitalAttributes| {
+///     let cf = coalition.get_full_cf(&|u: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u, &v));
+///     let cf_dist_utils::RiskMetric{value_at_risk, ..} = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+///         0.01, x_min, x_max, 100, 0.0001, &cf,
+///     ).unwrap();
+///     value_at_risk
+/// };
+/// let shapley = loan_ec::shapley::get_shapley_allocation(&groups, &risk_measure_fn).unwrap();
+/// assert!(shapley.allocations[0] < shapley.standalone[0]);
+/// # }
+/// ```
+pub fn get_shapley_allocation<T>(
+    groups: &[EconomicCapitalAttributes],
+    risk_measure_fn: T,
+) -> Result<ShapleyAllocation, EconomicCapitalError>
+where
+    T: Fn(&EconomicCapitalAttributes) -> f64,
+{
+    let num_groups = groups.len();
+    if num_groups == 0 || num_groups > MAX_GROUPS {
+        return Err(EconomicCapitalError::new(
+            "Number of groups must be between 1 and 16.",
+        ));
+    }
+    let first = &groups[0];
+    if groups
+        .iter()
+        .any(|group| group.num_w != first.num_w || group.cf.len() != first.cf.len())
+    {
+        return Err(EconomicCapitalError::new(
+            "Groups must have the same u domain and number of systemic variables.",
+        ));
+    }
+    let num_coalitions = 1 << num_groups;
+    let values: Vec<f64> = (0..num_coalitions)
+        .map(|mask| match mask {
+            0 => 0.0,
+            _ => risk_measure_fn(&get_coalition(groups, mask)),
+        })
+        .collect();
+    //weight |S|!(n-|S|-1)!/n! for each coalition size |S|
+    let factorial = |n: usize| -> f64 { (1..=n).map(|k| k as f64).product() };
+    let weights: Vec<f64> = (0..num_groups)
+        .map(|size| factorial(size) * factorial(num_groups - size - 1) / factorial(num_groups))
+        .collect();
+    let allocations = (0..num_groups)
+        .map(|index| {
+            let bit = 1 << index;
+            (0..num_coalitions)
+                .filter(|mask| mask & bit == 0)
+                .map(|mask: usize| {
+                    weights[mask.count_ones() as usize] * (values[mask | bit] - values[mask])
+                })
+                .sum()
+        })
+        .collect();
+    Ok(ShapleyAllocation {
+        allocations,
+        standalone: (0..num_groups).map(|index| values[1 << index]).collect(),
+        total: values[num_coalitions - 1],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_groups(weights: &[Vec<f64>], nums: &[f64]) -> Vec<EconomicCapitalAttributes> {
+        let u_domain = get_test_u_domain(256, -5000.0, 0.0);
+        weights
+            .iter()
+            .zip(nums)
+            .map(|(weight, &num)| {
+                let loan = get_test_loan(weight.clone(), num);
+                process_test_loans(exponential_lgd_cf, 0.0, 0.0, &u_domain, &[loan])
+            })
+            .collect()
+    }
+    fn value_at_risk(coalition: &EconomicCapitalAttributes) -> f64 {
+        let cf = coalition.get_full_cf(&gamma_mgf(&[0.4, 0.3]));
+        let cf_dist_utils::RiskMetric { value_at_risk, .. } =
+            cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+                0.01, -5000.0, 0.0, 100, 0.00001, &cf,
+            )
+            .unwrap();
+        value_at_risk
+    }
+    #[test]
+    fn additive_measure_allocates_standalone() {
+        let groups = get_groups(
+            &[vec![1.0, 0.0], vec![0.5, 0.5], vec![0.0, 1.0]],
+            &[1000.0, 2000.0, 3000.0],
+        );
+        let shapley = get_shapley_allocation(&groups, |coalition: &EconomicCapitalAttributes| {
+            coalition.get_portfolio_expectation(&[1.0, 1.0])
+        })
+        .unwrap();
+        shapley
+            .allocations
+            .iter()
+            .zip(&shapley.standalone)
+            .for_each(|(allocation, standalone)| {
+                assert_abs_diff_eq!(allocation, standalone, epsilon = 0.0000001)
+            });
+        assert_abs_diff_eq!(shapley.total, -150.0, epsilon = 0.0000001);
+    }
+    #[test]
+    fn allocation_is_efficient_and_symmetric() {
+        let groups = get_groups(
+            &[vec![1.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0]],
+            &[2000.0, 2000.0, 3000.0],
+        );
+        let shapley = get_shapley_allocation(&groups, value_at_risk).unwrap();
+        assert_abs_diff_eq!(
+            shapley.allocations.iter().sum::<f64>(),
+            shapley.total,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            shapley.allocations[0],
+            shapley.allocations[1],
+            epsilon = 0.0000001
+        );
+        assert!(shapley.allocations[2] < shapley.standalone[2]);
+    }
+    #[test]
+    fn empty_groups_is_an_error() {
+        assert!(get_shapley_allocation(&[], value_at_risk).is_err());
+    }
+    #[test]
+    fn mismatched_groups_is_an_error() {
+        let mut groups = get_groups(&[vec![1.0, 0.0]], &[1000.0]);
+        groups.push(EconomicCapitalAttributes::new(128, 2));
+        assert!(get_shapley_allocation(&groups, value_at_risk).is_err());
+        let mut groups = get_groups(&[vec![1.0, 0.0]], &[1000.0]);
+        groups.push(EconomicCapitalAttributes::new(256, 3));
+        assert!(get_shapley_allocation(&groups, value_at_risk).is_err());
+    }
+}
@@ -0,0 +1,645 @@
+//! Stress testing of a processed portfolio.  Scenarios
+//! are applied to the accumulated attributes without
+//! reprocessing the loans: probabilities of default are
+//! scaled per systemic variable, losses given default are
+//! scaled, systemic variables can be fixed at a given
+//! value and the liquidity parameters can be bumped.
+//! Liquidity risk is applied to the characteristic
+//! function of the stressed loss, so the loans must be
+//! processed without it.
+
+use crate::cf_transform::get_liquidity_cf;
+use crate::error::EconomicCapitalError;
+use crate::{
+    default_one, expectation_liquidity, portfolio_expectation, portfolio_variance,
+    variance_liquidity, EconomicCapitalAttributes,
+};
+use num_complex::Complex;
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+
+/// Struct representing a stress scenario.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StressScenario {
+    /// Multiplier for the probabilities of default
+    /// attributable to each systemic variable.  Has
+    /// the same size as the weight vector in the
+    /// [Loan](../struct.Loan.html) struct.
+    pub pd_multiplier: Vec<f64>,
+    #[serde(default = "default_one")]
+    /// Multiplier for the loss given default of
+    /// every loan.
+    pub lgd_multiplier: f64,
+    #[serde(default)]
+    /// Fixed value for each systemic variable.  A
+    /// systemic variable which is `None` (or
+    /// missing) is integrated out through the
+    /// moment generating function.
+    pub systemic_value: Vec<Option<f64>>,
+    /// Base loss (in dollars) from a liquidity
+    /// event in the scenario.
+    pub lambda0: f64,
+    /// Probability of liquidity event (scaled by
+    /// the total portfolio loss) in the scenario.
+    pub q: f64,
+}
+
+impl StressScenario {
+    /// Creates a scenario which leaves the portfolio
+    /// unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_w` - The number of systemic random variables.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    pub fn baseline(num_w: usize, lambda0: f64, q: f64) -> Self {
+        StressScenario {
+            pd_multiplier: vec![1.0; num_w],
+            lgd_multiplier: 1.0,
+            systemic_value: vec![],
+            lambda0,
+            q,
+        }
+    }
+    /// Returns the fixed value of a systemic variable, if any.
+    fn get_systemic_value(&self, index: usize) -> Option<f64> {
+        self.systemic_value.get(index).cloned().flatten()
+    }
+}
+
+/// Portfolio metrics under a single scenario.
+#[derive(Debug, Clone)]
+pub struct StressResult {
+    /// Expected value of the portfolio with
+    /// liquidity risk.
+    pub expectation: f64,
+    /// Variance of the portfolio with liquidity
+    /// risk.
+    pub variance: f64,
+    /// The discrete characteristic function for
+    /// the portfolio.
+    pub cf: Vec<Complex<f64>>,
+    /// The value of the risk measure.
+    pub risk_measure: f64,
+}
+
+/// Portfolio metrics under the baseline and
+/// under a stress scenario.
+#[derive(Debug, Clone)]
+pub struct StressComparison {
+    /// Metrics without stress.
+    pub baseline: StressResult,
+    /// Metrics under the stress scenario.
+    pub stressed: StressResult,
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the portfolio metrics under a stress
+    /// scenario.  The scenario's `q` and `lambda0` apply
+    /// to the expectation, the variance and the
+    /// characteristic function, so the loans must be
+    /// processed without liquidity risk, ie with
+    /// [get_liquidity_risk_fn(0.0, 0.0)](../fn.get_liquidity_risk_fn.html).
+    /// Returns an error if the scenario or the systemic
+    /// moments do not match the number of systemic
+    /// variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `scenario` - An instance of the
+    ///   [StressScenario](struct.StressScenario.html) struct.
+    /// * `el_sys` - The vector of expected values for
+    ///   the systemic random variables.
+    /// * `var_sys` - The vector of variances for the
+    ///   systemic random variables.
+    /// * `mgf_systemic` - Moment generating function
+    ///   for the (independent) systemic random
+    ///   variables.  Fixed systemic variables are
+    ///   passed a zero exponent.
+    /// * `x_min` - Lower truncation of the loss
+    ///   distribution.  Only used when the
+    ///   `lgd_multiplier` is not one or `q` is not zero,
+    ///   in which case it should accommodate the
+    ///   stressed losses.
+    /// * `x_max` - Upper truncation of the loss
+    ///   distribution.
+    /// * `risk_measure_fn` - Function which computes
+    ///   the risk measure for the portfolio, e.g. VaR.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_stress_result<U, T>(
+        &self,
+        scenario: &StressScenario,
+        el_sys: &[f64],
+        var_sys: &[f64],
+        mgf_systemic: &U,
+        x_min: f64,
+        x_max: f64,
+        risk_measure_fn: T,
+    ) -> Result<StressResult, EconomicCapitalError>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64,
+    {
+        if scenario.pd_multiplier.len() != self.num_w || scenario.systemic_value.len() > self.num_w
+        {
+            return Err(EconomicCapitalError::new(
+                "Scenario does not match the number of systemic variables.",
+            ));
+        }
+        if el_sys.len() != self.num_w || var_sys.len() != self.num_w {
+            return Err(EconomicCapitalError::new(
+                "Systemic moments do not match the number of systemic variables.",
+            ));
+        }
+        let lgd_multiplier = scenario.lgd_multiplier;
+        let el_vec: Vec<f64> = self
+            .el_vec
+            .iter()
+            .zip(&scenario.pd_multiplier)
+            .map(|(el, m)| el * m * lgd_multiplier)
+            .collect();
+        let var_vec: Vec<f64> = self
+            .var_vec
+            .iter()
+            .zip(&scenario.pd_multiplier)
+            .map(|(var, m)| var * m * lgd_multiplier.powi(2))
+            .collect();
+        let stressed_el_sys: Vec<f64> = el_sys
+            .iter()
+            .enumerate()
+            .map(|(index, e)| scenario.get_systemic_value(index).unwrap_or(*e))
+            .collect();
+        let stressed_var_sys: Vec<f64> = var_sys
+            .iter()
+            .enumerate()
+            .map(|(index, v)| scenario.get_systemic_value(index).map_or(*v, |_| 0.0))
+            .collect();
+        let expectation = portfolio_expectation(&el_vec, &stressed_el_sys);
+        let variance = portfolio_variance(&el_vec, &stressed_el_sys, &var_vec, &stressed_var_sys);
+        let lambda = self.lambda + scenario.lambda0;
+        let cf: Vec<Complex<f64>> = self
+            .cf
+            .par_chunks(self.num_w)
+            .map(|u_weights| {
+                let (fixed_exponent, integrated_weights) = u_weights
+                    .iter()
+                    .zip(&scenario.pd_multiplier)
+                    .enumerate()
+                    .fold(
+                        (Complex::new(0.0, 0.0), Vec::with_capacity(self.num_w)),
+                        |(fixed_exponent, mut integrated_weights), (index, (u, m))| match scenario
+                            .get_systemic_value(index)
+                        {
+                            Some(value) => {
+                                integrated_weights.push(Complex::new(0.0, 0.0));
+                                (fixed_exponent + u * m * value, integrated_weights)
+                            }
+                            None => {
+                                integrated_weights.push(u * m);
+                                (fixed_exponent, integrated_weights)
+                            }
+                        },
+                    );
+                fixed_exponent.exp() * mgf_systemic(&integrated_weights)
+            })
+            .collect();
+        let cf = get_liquidity_cf(&cf, x_min, x_max, lambda, scenario.q, lgd_multiplier);
+        Ok(StressResult {
+            expectation: expectation_liquidity(lambda, scenario.q, expectation),
+            variance: variance_liquidity(lambda, scenario.q, expectation, variance),
+            risk_measure: risk_measure_fn(&cf),
+            cf,
+        })
+    }
+    /// Returns the portfolio metrics under the baseline
+    /// and under a stress scenario side by side.  The
+    /// baseline is the portfolio as processed, with the
+    /// baseline liquidity risk, so a scenario which does
+    /// not change `lambda0` and `q` only shows the effect
+    /// of its other shocks.  The remaining arguments are
+    /// the same as those for
+    /// [get_stress_result](struct.EconomicCapitalAttributes.html#method.get_stress_result).
+    ///
+    /// # Arguments
+    ///
+    /// * `scenario` - An instance of the
+    ///   [StressScenario](struct.StressScenario.html) struct.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event in the baseline.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss) in the baseline.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// extern crate cf_dist_utils;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u:usize=128;
+    /// let x_min=-5000.0;
+    /// let x_max=0.0;
+    /// let lambda0=100.0;
+    /// let q=0.00001;
+    /// let mut ec_attributes=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// let loan=loan_ec::Loan{
+    ///     pd: 0.05,
+    ///     lgd: 0.5,
+    ///     lgd_variance: 0.2,
+    ///     balance: 1.0,
+    ///     r: 0.0,
+    ///     weight: vec![1.0],
+    ///     num: 10000.0
+    /// };
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let v = vec![0.4];
+    /// let systemic_mgf = |u_weights: &[Complex<f64>]| loan_ec::systemic_gamma_mgf(u_weights, &v);
+    /// let risk_measure_fn = |final_cf: &[Complex<f64>]| {
+    ///     let cf_dist_utils::RiskMetric{value_at_risk, ..} = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+    ///         0.01, x_min, x_max, 100, 0.0001, final_cf,
+    ///     ).unwrap();
+    ///     value_at_risk
+    /// };
+    /// let scenario = loan_ec::stress::StressScenario{
+    ///     pd_multiplier: vec![1.5],
+    ///     lgd_multiplier: 1.0,
+    ///     systemic_value: vec![],
+    ///     lambda0,
+    ///     q,
+    /// };
+    /// let comparison = ec_attributes.get_stress_comparison(
+    ///     &scenario, lambda0, q, &[1.0], &v, &systemic_mgf, x_min, x_max, &risk_measure_fn
+    /// ).unwrap();
+    /// assert!(comparison.stressed.risk_measure > comparison.baseline.risk_measure);
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_stress_comparison<U, T>(
+        &self,
+        scenario: &StressScenario,
+        lambda0: f64,
+        q: f64,
+        el_sys: &[f64],
+        var_sys: &[f64],
+        mgf_systemic: &U,
+        x_min: f64,
+        x_max: f64,
+        risk_measure_fn: T,
+    ) -> Result<StressComparison, EconomicCapitalError>
+    where
+        U: Fn(&[Complex<f64>]) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+        T: Fn(&[Complex<f64>]) -> f64,
+    {
+        Ok(StressComparison {
+            baseline: self.get_stress_result(
+                &StressScenario::baseline(self.num_w, lambda0, q),
+                el_sys,
+                var_sys,
+                mgf_systemic,
+                x_min,
+                x_max,
+                &risk_measure_fn,
+            )?,
+            stressed: self.get_stress_result(
+                scenario,
+                el_sys,
+                var_sys,
+                mgf_systemic,
+                x_min,
+                x_max,
+                &risk_measure_fn,
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_test_attributes(
+        num_u: usize,
+        x_min: f64,
+        x_max: f64,
+        lambda0: f64,
+        q: f64,
+    ) -> EconomicCapitalAttributes {
+        process_test_loans(
+            gamma_lgd_cf,
+            lambda0,
+            q,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &get_test_loans(),
+        )
+    }
+    fn expectation_fn(x_min: f64, x_max: f64) -> impl Fn(&[Complex<f64>]) -> f64 {
+        move |cf: &[Complex<f64>]| cf_dist_utils::get_expectation_discrete_cf(x_min, x_max, cf)
+    }
+    #[test]
+    fn baseline_matches_unstressed_portfolio() {
+        let num_u: usize = 256;
+        let x_min = -5000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(num_u, x_min, x_max, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let v_mgf = gamma_mgf(&v);
+        let result = ec
+            .get_stress_result(
+                &StressScenario::baseline(2, 0.0, 0.0),
+                &el_sys,
+                &v,
+                &v_mgf,
+                x_min,
+                x_max,
+                expectation_fn(x_min, x_max),
+            )
+            .unwrap();
+        let full_cf = ec.get_full_cf(&v_mgf);
+        result.cf.iter().zip(&full_cf).for_each(|(r, f)| {
+            assert_abs_diff_eq!(r.re, f.re, epsilon = 0.0000001);
+            assert_abs_diff_eq!(r.im, f.im, epsilon = 0.0000001);
+        });
+        assert_abs_diff_eq!(
+            result.expectation,
+            ec.get_portfolio_expectation(&el_sys),
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            result.variance,
+            ec.get_portfolio_variance(&el_sys, &v),
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn pd_stress_scales_expectation() {
+        let num_u: usize = 1024;
+        let x_min = -10000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(num_u, x_min, x_max, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let scenario = StressScenario {
+            pd_multiplier: vec![2.0, 1.5],
+            ..StressScenario::baseline(2, 0.0, 0.0)
+        };
+        let comparison = ec
+            .get_stress_comparison(
+                &scenario,
+                0.0,
+                0.0,
+                &el_sys,
+                &v,
+                &gamma_mgf(&v),
+                x_min,
+                x_max,
+                expectation_fn(x_min, x_max),
+            )
+            .unwrap();
+        let expected = ec.el_vec[0] * 2.0 + ec.el_vec[1] * 1.5;
+        assert_abs_diff_eq!(
+            comparison.stressed.expectation,
+            expected,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(comparison.stressed.risk_measure, expected, epsilon = 0.001);
+        assert_abs_diff_eq!(
+            comparison.baseline.risk_measure,
+            comparison.baseline.expectation,
+            epsilon = 0.001
+        );
+    }
+    #[test]
+    fn fixed_systemic_value_conditions_cf() {
+        let num_u: usize = 1024;
+        let x_min = -10000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(num_u, x_min, x_max, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let scenario = StressScenario {
+            systemic_value: vec![Some(2.5), Some(0.5)],
+            ..StressScenario::baseline(2, 0.0, 0.0)
+        };
+        let result = ec
+            .get_stress_result(
+                &scenario,
+                &el_sys,
+                &v,
+                &gamma_mgf(&v),
+                x_min,
+                x_max,
+                expectation_fn(x_min, x_max),
+            )
+            .unwrap();
+        ec.cf.chunks(2).zip(&result.cf).for_each(|(u_weights, cf)| {
+            let expected = (u_weights[0] * 2.5 + u_weights[1] * 0.5).exp();
+            assert_abs_diff_eq!(cf.re, expected.re, epsilon = 0.0000001);
+            assert_abs_diff_eq!(cf.im, expected.im, epsilon = 0.0000001);
+        });
+        let expected = ec.el_vec[0] * 2.5 + ec.el_vec[1] * 0.5;
+        assert_abs_diff_eq!(result.expectation, expected, epsilon = 0.0000001);
+        assert_abs_diff_eq!(
+            result.variance,
+            ec.var_vec[0] * 2.5 + ec.var_vec[1] * 0.5,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(result.risk_measure, expected, epsilon = 0.001);
+    }
+    #[test]
+    fn lgd_stress_scales_losses() {
+        let num_u: usize = 256;
+        let x_min = -10000.0;
+        let x_max = 0.0;
+        let ec = get_test_attributes(num_u, x_min, x_max, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let scenario = StressScenario {
+            lgd_multiplier: 1.4,
+            ..StressScenario::baseline(2, 0.0, 0.0)
+        };
+        let comparison = ec
+            .get_stress_comparison(
+                &scenario,
+                0.0,
+                0.0,
+                &el_sys,
+                &v,
+                &gamma_mgf(&v),
+                x_min,
+                x_max,
+                |cf: &[Complex<f64>]| cf_dist_utils::get_variance_discrete_cf(x_min, x_max, cf),
+            )
+            .unwrap();
+        assert_abs_diff_eq!(
+            comparison.stressed.expectation,
+            comparison.baseline.expectation * 1.4,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            comparison.stressed.variance,
+            comparison.baseline.variance * 1.96,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            comparison.stressed.risk_measure,
+            comparison.stressed.variance,
+            epsilon = 1.0
+        );
+    }
+    #[test]
+    fn liquidity_stress_changes_risk_measure() {
+        let num_u: usize = 256;
+        let x_min = -10000.0;
+        let x_max = 0.0;
+        let lambda0 = 500.0;
+        let q = 0.0001;
+        let ec = get_test_attributes(num_u, x_min, x_max, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let value_at_risk_fn = |cf: &[Complex<f64>]| {
+            cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+                0.01, x_min, x_max, 100, 0.0000001, cf,
+            )
+            .unwrap()
+            .value_at_risk
+        };
+        let comparison = ec
+            .get_stress_comparison(
+                &StressScenario::baseline(2, lambda0, q),
+                0.0,
+                0.0,
+                &el_sys,
+                &v,
+                &gamma_mgf(&v),
+                x_min,
+                x_max,
+                value_at_risk_fn,
+            )
+            .unwrap();
+        let expectation = ec.get_portfolio_expectation(&el_sys);
+        let variance = ec.get_portfolio_variance(&el_sys, &v);
+        assert_abs_diff_eq!(
+            comparison.stressed.expectation,
+            expectation_liquidity(lambda0, q, expectation),
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            comparison.stressed.variance,
+            variance_liquidity(lambda0, q, expectation, variance),
+            epsilon = 0.0000001
+        );
+        assert!(comparison.stressed.risk_measure > comparison.baseline.risk_measure);
+        //same as processing the loans with liquidity risk
+        let liquid_ec = get_test_attributes(num_u, x_min, x_max, lambda0, q);
+        assert_abs_diff_eq!(
+            comparison.stressed.risk_measure,
+            value_at_risk_fn(&liquid_ec.get_full_cf(&gamma_mgf(&v))),
+            epsilon = 0.01
+        );
+    }
+    #[test]
+    fn pd_stress_keeps_baseline_liquidity() {
+        let num_u: usize = 256;
+        let x_min = -10000.0;
+        let x_max = 0.0;
+        let lambda0 = 500.0;
+        let q = 0.0001;
+        let ec = get_test_attributes(num_u, x_min, x_max, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let scenario = StressScenario {
+            pd_multiplier: vec![1.5, 1.5],
+            ..StressScenario::baseline(2, lambda0, q)
+        };
+        let comparison = ec
+            .get_stress_comparison(
+                &scenario,
+                lambda0,
+                q,
+                &el_sys,
+                &v,
+                &gamma_mgf(&v),
+                x_min,
+                x_max,
+                expectation_fn(x_min, x_max),
+            )
+            .unwrap();
+        let expectation = ec.get_portfolio_expectation(&el_sys);
+        assert_abs_diff_eq!(
+            comparison.baseline.expectation,
+            expectation_liquidity(lambda0, q, expectation),
+            epsilon = 0.0000001
+        );
+        //the liquidity scaling is the same on both sides
+        assert_abs_diff_eq!(
+            comparison.stressed.expectation,
+            1.5 * comparison.baseline.expectation,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            comparison.baseline.risk_measure,
+            comparison.baseline.expectation,
+            epsilon = 0.01
+        );
+    }
+    #[test]
+    fn mismatched_lengths_are_rejected() {
+        let ec = get_test_attributes(64, -10000.0, 0.0, 0.0, 0.0);
+        let v = vec![0.4, 0.3];
+        let el_sys = vec![1.0, 1.0];
+        let risk_measure_fn = |_cf: &[Complex<f64>]| 0.0;
+        let short_multiplier = StressScenario {
+            pd_multiplier: vec![2.0],
+            ..StressScenario::baseline(2, 0.0, 0.0)
+        };
+        assert!(ec
+            .get_stress_result(
+                &short_multiplier,
+                &el_sys,
+                &v,
+                &gamma_mgf(&v),
+                -10000.0,
+                0.0,
+                risk_measure_fn
+            )
+            .is_err());
+        let long_systemic_value = StressScenario {
+            systemic_value: vec![None, None, Some(1.0)],
+            ..StressScenario::baseline(2, 0.0, 0.0)
+        };
+        assert!(ec
+            .get_stress_result(
+                &long_systemic_value,
+                &el_sys,
+                &v,
+                &gamma_mgf(&v),
+                -10000.0,
+                0.0,
+                risk_measure_fn
+            )
+            .is_err());
+        assert!(ec
+            .get_stress_result(
+                &StressScenario::baseline(2, 0.0, 0.0),
+                &el_sys,
+                &[0.4],
+                &gamma_mgf(&v),
+                -10000.0,
+                0.0,
+                risk_measure_fn
+            )
+            .is_err());
+    }
+}
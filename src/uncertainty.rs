@@ -0,0 +1,634 @@
+//! Confidence intervals on the value at risk and
+//! expected shortfall from uncertainty in the model
+//! parameters.  The probabilities of default
+//! (per systemic variable), the losses given default
+//! and the variances of the (Gamma) systemic variables
+//! are drawn from user-specified distributions.  Each
+//! draw is applied to the accumulated characteristic
+//! function exponents as a
+//! [StressScenario](../stress/struct.StressScenario.html),
+//! since they are additive in the loans and linear in
+//! the probabilities of default, so the loans are
+//! never reprocessed.  Liquidity risk is applied to
+//! each draw, so the loans must be processed without
+//! it.
+
+use crate::distributions::{gamma_inv, normal_inv};
+use crate::error::EconomicCapitalError;
+use crate::stress::StressScenario;
+use crate::{systemic_gamma_mgf, EconomicCapitalAttributes};
+use num_complex::Complex;
+use serde_derive::{Deserialize, Serialize};
+
+/// Distribution of an uncertain parameter, eg
+/// `{"type": "log_normal", "mean": 1.0,
+/// "standard_deviation": 0.2}`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParameterDistribution {
+    /// No uncertainty.
+    Fixed {
+        /// Value of the parameter.
+        value: f64,
+    },
+    /// Uniform between `low` and `high`.
+    Uniform {
+        /// Lower bound.
+        low: f64,
+        /// Upper bound.
+        high: f64,
+    },
+    /// Normal, truncated at zero.
+    Normal {
+        /// Expectation (before truncation).
+        mean: f64,
+        /// Standard deviation (before truncation).
+        standard_deviation: f64,
+    },
+    /// Lognormal with the given expectation and
+    /// standard deviation.
+    LogNormal {
+        /// Expectation, greater than zero.
+        mean: f64,
+        /// Standard deviation.
+        standard_deviation: f64,
+    },
+    /// Gamma with the given expectation and standard
+    /// deviation.
+    Gamma {
+        /// Expectation, greater than zero.
+        mean: f64,
+        /// Standard deviation, greater than zero.
+        standard_deviation: f64,
+    },
+}
+
+impl ParameterDistribution {
+    fn validate(&self) -> Result<(), EconomicCapitalError> {
+        let is_valid = match *self {
+            ParameterDistribution::Fixed { value } => value >= 0.0,
+            ParameterDistribution::Uniform { low, high } => low >= 0.0 && high >= low,
+            ParameterDistribution::Normal {
+                standard_deviation, ..
+            } => standard_deviation >= 0.0,
+            ParameterDistribution::LogNormal {
+                mean,
+                standard_deviation,
+            } => mean > 0.0 && standard_deviation >= 0.0,
+            ParameterDistribution::Gamma {
+                mean,
+                standard_deviation,
+            } => mean > 0.0 && standard_deviation > 0.0,
+        };
+        if is_valid {
+            Ok(())
+        } else {
+            Err(EconomicCapitalError::new(
+                "Parameter distributions must be non-negative with valid moments.",
+            ))
+        }
+    }
+    /// Returns the quantile of the distribution at
+    /// probability `p`.
+    pub fn get_quantile(&self, p: f64) -> f64 {
+        match *self {
+            ParameterDistribution::Fixed { value } => value,
+            ParameterDistribution::Uniform { low, high } => low + (high - low) * p,
+            ParameterDistribution::Normal {
+                mean,
+                standard_deviation,
+            } => (mean + standard_deviation * normal_inv(p)).max(0.0),
+            ParameterDistribution::LogNormal {
+                mean,
+                standard_deviation,
+            } => {
+                let sigma_squared = (1.0 + (standard_deviation / mean).powi(2)).ln();
+                (mean.ln() - 0.5 * sigma_squared + sigma_squared.sqrt() * normal_inv(p)).exp()
+            }
+            ParameterDistribution::Gamma {
+                mean,
+                standard_deviation,
+            } => {
+                let scale = standard_deviation.powi(2) / mean;
+                gamma_inv(p, mean / scale, scale)
+            }
+        }
+    }
+}
+
+/// Distributions of the uncertain parameters.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ParameterUncertainty {
+    /// Multiplier for the probabilities of default
+    /// attributable to each systemic variable.
+    pub pd_multiplier: Vec<ParameterDistribution>,
+    /// Multiplier for the loss given default of every
+    /// loan.
+    pub lgd_multiplier: ParameterDistribution,
+    /// Variance of each systemic variable.
+    pub systemic_variance: Vec<ParameterDistribution>,
+    /// Number of draws.
+    pub num_draws: usize,
+    #[serde(default)]
+    /// Seed of the random number generator.
+    pub seed: u64,
+}
+
+/// Parameters and risk measures of a single draw.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParameterDraw {
+    /// Multiplier for the probabilities of default
+    /// attributable to each systemic variable.
+    pub pd_multiplier: Vec<f64>,
+    /// Multiplier for the loss given default.
+    pub lgd_multiplier: f64,
+    /// Variance of each systemic variable.
+    pub systemic_variance: Vec<f64>,
+    /// Value at risk (positive).
+    pub value_at_risk: f64,
+    /// Expected shortfall (positive).
+    pub expected_shortfall: f64,
+}
+
+/// Summary of the distribution of a risk measure
+/// over the draws.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ConfidenceInterval {
+    /// Risk measure at the point estimates of the
+    /// parameters.
+    pub point: f64,
+    /// Average over the draws.
+    pub mean: f64,
+    /// Standard deviation over the draws.
+    pub standard_deviation: f64,
+    /// Lower bound of the interval.
+    pub lower: f64,
+    /// Upper bound of the interval.
+    pub upper: f64,
+}
+
+/// Distribution of the risk measures under parameter
+/// uncertainty.
+#[derive(Debug, Clone, Serialize)]
+pub struct UncertaintyReport {
+    /// Each draw of the parameters.
+    pub draws: Vec<ParameterDraw>,
+    /// Confidence interval of the value at risk.
+    pub value_at_risk: ConfidenceInterval,
+    /// Confidence interval of the expected shortfall.
+    pub expected_shortfall: ConfidenceInterval,
+}
+
+/// SplitMix64 generator, so that draws are
+/// reproducible from the seed.
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// Returns a uniform number in (0, 1).
+    fn next_uniform(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        ((z >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+}
+
+/// Returns the empirical quantile of sorted values,
+/// interpolating linearly.
+fn get_sorted_quantile(sorted: &[f64], p: f64) -> f64 {
+    let position = p * (sorted.len() - 1) as f64;
+    let index = position.floor() as usize;
+    let next = (index + 1).min(sorted.len() - 1);
+    sorted[index] + (position - index as f64) * (sorted[next] - sorted[index])
+}
+
+fn get_confidence_interval(point: f64, values: &[f64], confidence: f64) -> ConfidenceInterval {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    ConfidenceInterval {
+        point,
+        mean,
+        standard_deviation: variance.sqrt(),
+        lower: get_sorted_quantile(&sorted, 0.5 * (1.0 - confidence)),
+        upper: get_sorted_quantile(&sorted, 0.5 * (1.0 + confidence)),
+    }
+}
+
+impl EconomicCapitalAttributes {
+    /// Returns the value at risk and expected
+    /// shortfall for given multipliers and systemic
+    /// variances.
+    #[allow(clippy::too_many_arguments)]
+    fn get_uncertain_risk_measures(
+        &self,
+        pd_multiplier: &[f64],
+        lgd_multiplier: f64,
+        systemic_variance: &[f64],
+        lambda0: f64,
+        q: f64,
+        x_min: f64,
+        x_max: f64,
+        alpha: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<(f64, f64), EconomicCapitalError> {
+        let scenario = StressScenario {
+            pd_multiplier: pd_multiplier.to_vec(),
+            lgd_multiplier,
+            ..StressScenario::baseline(self.num_w, lambda0, q)
+        };
+        let mgf = |u_weights: &[Complex<f64>]| systemic_gamma_mgf(u_weights, systemic_variance);
+        let result = self.get_stress_result(
+            &scenario,
+            &vec![1.0; self.num_w],
+            systemic_variance,
+            &mgf,
+            x_min,
+            x_max,
+            |_cf: &[Complex<f64>]| 0.0,
+        )?;
+        let cf_dist_utils::RiskMetric {
+            value_at_risk,
+            expected_shortfall,
+        } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+            alpha,
+            x_min,
+            x_max,
+            max_iterations,
+            tolerance,
+            &result.cf,
+        )?;
+        Ok((value_at_risk, expected_shortfall))
+    }
+    /// Returns the distribution of the value at risk
+    /// and expected shortfall when the parameters are
+    /// uncertain.  The systemic variables are Gamma
+    /// distributed.  The liquidity risk is applied to
+    /// each draw, so the loans must be processed
+    /// without liquidity risk, ie with
+    /// [get_liquidity_risk_fn(0.0, 0.0)](../fn.get_liquidity_risk_fn.html).
+    ///
+    /// # Arguments
+    ///
+    /// * `uncertainty` - The distributions of the
+    ///   parameters.
+    /// * `var_sys` - The point estimates of the
+    ///   variances of the systemic random variables.
+    /// * `lambda0` - Base loss (in dollars) from a
+    ///   liquidity event.
+    /// * `q` - Probability of liquidity event (scaled
+    ///   by the total portfolio loss).
+    /// * `x_min` - Lower truncation of the loss
+    ///   distribution.  Should accommodate the largest
+    ///   LGD multiplier.
+    /// * `x_max` - Upper truncation of the loss
+    ///   distribution.
+    /// * `alpha` - Tail probability of the risk
+    ///   measures.
+    /// * `confidence` - Confidence level of the
+    ///   intervals, eg 0.9.
+    /// * `tolerance` - Tolerance of the value at risk.
+    /// * `max_iterations` - Maximum root finding steps
+    ///   for the value at risk.
+    ///
+    /// # Examples
+    /// ```
+    /// extern crate loan_ec;
+    /// extern crate fang_oost;
+    /// extern crate rayon;
+    /// extern crate num_complex;
+    /// use rayon::prelude::*;
+    /// use num_complex::Complex;
+    /// # fn main(){
+    /// let num_u = 128;
+    /// let x_min = -2000.0;
+    /// let x_max = 0.0;
+    /// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, x_min, x_max).collect();
+    /// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+    /// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+    /// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+    /// let loan: loan_ec::Loan = serde_json::from_str(r#"{
+    ///     "pd": 0.05, "lgd": 0.5, "balance": 1.0, "r": 0.0,
+    ///     "lgd_variance": 0.0, "weight": [1.0], "num": 1000.0
+    /// }"#).unwrap();
+    /// let mut ec_attributes = loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+    /// ec_attributes.process_loan(&loan, &u_domain, &log_lpm_cf);
+    /// let uncertainty: loan_ec::uncertainty::ParameterUncertainty = serde_json::from_str(r#"{
+    ///     "pd_multiplier": [{"type": "log_normal", "mean": 1.0, "standard_deviation": 0.2}],
+    ///     "lgd_multiplier": {"type": "fixed", "value": 1.0},
+    ///     "systemic_variance": [{"type": "gamma", "mean": 0.3, "standard_deviation": 0.05}],
+    ///     "num_draws": 20
+    /// }"#).unwrap();
+    /// let report = ec_attributes.get_parameter_uncertainty(
+    ///     &uncertainty, &[0.3], 0.0, 0.0, x_min, x_max, 0.01, 0.9, 0.0001, 100
+    /// ).unwrap();
+    /// assert!(report.value_at_risk.lower < report.value_at_risk.upper);
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_parameter_uncertainty(
+        &self,
+        uncertainty: &ParameterUncertainty,
+        var_sys: &[f64],
+        lambda0: f64,
+        q: f64,
+        x_min: f64,
+        x_max: f64,
+        alpha: f64,
+        confidence: f64,
+        tolerance: f64,
+        max_iterations: usize,
+    ) -> Result<UncertaintyReport, EconomicCapitalError> {
+        if uncertainty.pd_multiplier.len() != self.num_w
+            || uncertainty.systemic_variance.len() != self.num_w
+        {
+            return Err(EconomicCapitalError::new(
+                "Uncertainty requires a distribution for each systemic variable.",
+            ));
+        }
+        if uncertainty.num_draws == 0 || confidence <= 0.0 || confidence >= 1.0 {
+            return Err(EconomicCapitalError::new(
+                "Uncertainty requires draws and a confidence level between 0 and 1.",
+            ));
+        }
+        uncertainty
+            .pd_multiplier
+            .iter()
+            .chain(std::iter::once(&uncertainty.lgd_multiplier))
+            .chain(&uncertainty.systemic_variance)
+            .try_for_each(|distribution| distribution.validate())?;
+        let (point_value_at_risk, point_expected_shortfall) = self.get_uncertain_risk_measures(
+            &vec![1.0; self.num_w],
+            1.0,
+            var_sys,
+            lambda0,
+            q,
+            x_min,
+            x_max,
+            alpha,
+            tolerance,
+            max_iterations,
+        )?;
+        let mut generator = SplitMix64 {
+            state: uncertainty.seed,
+        };
+        let draws = (0..uncertainty.num_draws)
+            .map(|_| {
+                let mut sample = |distribution: &ParameterDistribution| {
+                    distribution.get_quantile(generator.next_uniform())
+                };
+                let pd_multiplier: Vec<f64> =
+                    uncertainty.pd_multiplier.iter().map(&mut sample).collect();
+                let lgd_multiplier = sample(&uncertainty.lgd_multiplier);
+                let systemic_variance: Vec<f64> = uncertainty
+                    .systemic_variance
+                    .iter()
+                    .map(&mut sample)
+                    .collect();
+                let (value_at_risk, expected_shortfall) = self.get_uncertain_risk_measures(
+                    &pd_multiplier,
+                    lgd_multiplier,
+                    &systemic_variance,
+                    lambda0,
+                    q,
+                    x_min,
+                    x_max,
+                    alpha,
+                    tolerance,
+                    max_iterations,
+                )?;
+                if !value_at_risk.is_finite() || !expected_shortfall.is_finite() {
+                    return Err(EconomicCapitalError::new(
+                        "Risk measures are not finite for a parameter draw.",
+                    ));
+                }
+                Ok(ParameterDraw {
+                    pd_multiplier,
+                    lgd_multiplier,
+                    systemic_variance,
+                    value_at_risk,
+                    expected_shortfall,
+                })
+            })
+            .collect::<Result<Vec<ParameterDraw>, EconomicCapitalError>>()?;
+        let value_at_risk: Vec<f64> = draws.iter().map(|draw| draw.value_at_risk).collect();
+        let expected_shortfall: Vec<f64> =
+            draws.iter().map(|draw| draw.expected_shortfall).collect();
+        Ok(UncertaintyReport {
+            value_at_risk: get_confidence_interval(point_value_at_risk, &value_at_risk, confidence),
+            expected_shortfall: get_confidence_interval(
+                point_expected_shortfall,
+                &expected_shortfall,
+                confidence,
+            ),
+            draws,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_attributes(
+        num_u: usize,
+        x_min: f64,
+        x_max: f64,
+        lambda0: f64,
+        q: f64,
+    ) -> EconomicCapitalAttributes {
+        process_test_loans(
+            exponential_lgd_cf,
+            lambda0,
+            q,
+            &get_test_u_domain(num_u, x_min, x_max),
+            &[get_test_loan(vec![0.4, 0.6], 1000.0)],
+        )
+    }
+    fn get_uncertainty(standard_deviation: f64, seed: u64) -> ParameterUncertainty {
+        ParameterUncertainty {
+            pd_multiplier: vec![
+                ParameterDistribution::LogNormal {
+                    mean: 1.0,
+                    standard_deviation,
+                };
+                2
+            ],
+            lgd_multiplier: ParameterDistribution::Fixed { value: 1.0 },
+            systemic_variance: vec![
+                ParameterDistribution::Fixed { value: 0.4 },
+                ParameterDistribution::Fixed { value: 0.3 },
+            ],
+            num_draws: 30,
+            seed,
+        }
+    }
+    #[test]
+    fn quantiles_match_moments() {
+        let n = 2000;
+        let distributions = [
+            ParameterDistribution::Uniform {
+                low: 0.5,
+                high: 1.5,
+            },
+            ParameterDistribution::LogNormal {
+                mean: 1.0,
+                standard_deviation: 0.3,
+            },
+            ParameterDistribution::Gamma {
+                mean: 1.0,
+                standard_deviation: 0.3,
+            },
+        ];
+        distributions.iter().for_each(|distribution| {
+            let mean = (0..n)
+                .map(|i| distribution.get_quantile((i as f64 + 0.5) / n as f64))
+                .sum::<f64>()
+                / n as f64;
+            assert_abs_diff_eq!(mean, 1.0, epsilon = 0.001);
+        });
+        assert!(ParameterDistribution::Gamma {
+            mean: 1.0,
+            standard_deviation: 0.0
+        }
+        .validate()
+        .is_err());
+    }
+    #[test]
+    fn fixed_parameters_give_point_estimate() {
+        let x_min = -1000.0;
+        let x_max = 0.0;
+        let ec = get_attributes(128, x_min, x_max, 0.0, 0.0);
+        let report = ec
+            .get_parameter_uncertainty(
+                &get_uncertainty(0.0, 0),
+                &[0.4, 0.3],
+                0.0,
+                0.0,
+                x_min,
+                x_max,
+                0.01,
+                0.9,
+                0.0001,
+                100,
+            )
+            .unwrap();
+        assert_eq!(report.draws.len(), 30);
+        assert_abs_diff_eq!(
+            report.value_at_risk.lower,
+            report.value_at_risk.point,
+            epsilon = 0.0000001
+        );
+        assert_abs_diff_eq!(
+            report.expected_shortfall.upper,
+            report.expected_shortfall.point,
+            epsilon = 0.0000001
+        );
+        assert!(ec
+            .get_parameter_uncertainty(
+                &get_uncertainty(0.0, 0),
+                &[0.4],
+                0.0,
+                0.0,
+                x_min,
+                x_max,
+                0.01,
+                1.5,
+                0.0001,
+                100,
+            )
+            .is_err());
+    }
+    #[test]
+    fn uncertain_pd_widens_interval() {
+        let x_min = -1500.0;
+        let x_max = 0.0;
+        let ec = get_attributes(128, x_min, x_max, 0.0, 0.0);
+        let get_report = |standard_deviation: f64, seed: u64| {
+            ec.get_parameter_uncertainty(
+                &get_uncertainty(standard_deviation, seed),
+                &[0.4, 0.3],
+                0.0,
+                0.0,
+                x_min,
+                x_max,
+                0.01,
+                0.9,
+                0.0001,
+                100,
+            )
+            .unwrap()
+        };
+        let report = get_report(0.2, 42);
+        let interval = report.value_at_risk;
+        assert!(interval.lower < interval.point && interval.point < interval.upper);
+        assert!(report.expected_shortfall.lower > interval.lower);
+        assert!(get_report(0.4, 42).value_at_risk.standard_deviation > interval.standard_deviation);
+        assert_eq!(get_report(0.2, 42).value_at_risk, interval);
+        assert!(get_report(0.2, 7).value_at_risk != interval);
+    }
+    #[test]
+    fn point_estimate_includes_liquidity() {
+        let x_min = -1500.0;
+        let x_max = 0.0;
+        let lambda0 = 100.0;
+        let q = 0.0001;
+        let v = vec![0.4, 0.3];
+        let report = get_attributes(128, x_min, x_max, 0.0, 0.0)
+            .get_parameter_uncertainty(
+                &get_uncertainty(0.0, 0),
+                &v,
+                lambda0,
+                q,
+                x_min,
+                x_max,
+                0.01,
+                0.9,
+                0.0001,
+                100,
+            )
+            .unwrap();
+        let no_liquidity = get_attributes(128, x_min, x_max, 0.0, 0.0)
+            .get_parameter_uncertainty(
+                &get_uncertainty(0.0, 0),
+                &v,
+                0.0,
+                0.0,
+                x_min,
+                x_max,
+                0.01,
+                0.9,
+                0.0001,
+                100,
+            )
+            .unwrap();
+        assert!(report.value_at_risk.point > no_liquidity.value_at_risk.point);
+        let liquid_ec = get_attributes(128, x_min, x_max, lambda0, q);
+        let cf_dist_utils::RiskMetric {
+            value_at_risk,
+            expected_shortfall,
+        } = cf_dist_utils::get_expected_shortfall_and_value_at_risk_discrete_cf(
+            0.01,
+            x_min,
+            x_max,
+            100,
+            0.0001,
+            &liquid_ec.get_full_cf(&gamma_mgf(&v)),
+        )
+        .unwrap();
+        assert_abs_diff_eq!(report.value_at_risk.point, value_at_risk, epsilon = 0.01);
+        assert_abs_diff_eq!(
+            report.expected_shortfall.point,
+            expected_shortfall,
+            epsilon = 0.01
+        );
+    }
+}
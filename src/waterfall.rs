@@ -0,0 +1,344 @@
+//! Attribution of the change in capital between two
+//! snapshots of the portfolio.  Starting from the
+//! previous snapshot, the loan changes are applied one
+//! driver at a time (new business, run-off, PD
+//! migration, LGD change, exposure change) and the
+//! capital is recomputed after each step.  The model
+//! parameters are changed last and any difference to
+//! the current snapshot is reported as a residual.
+
+use crate::{EconomicCapitalAttributes, Loan};
+use num_complex::Complex;
+use serde_derive::{Deserialize, Serialize};
+
+/// A change to the loans between two snapshots.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoanChange {
+    /// Loan originated since the previous snapshot.
+    New(Loan),
+    /// Loan which matured (or was sold) since the
+    /// previous snapshot.
+    Matured(Loan),
+    /// Loan whose attributes changed, eg after a
+    /// re-rating.
+    Rerated {
+        /// The loan in the previous snapshot.
+        before: Loan,
+        /// The loan in the current snapshot.
+        after: Loan,
+    },
+}
+
+/// Change in capital split by driver.  The previous
+/// capital plus every driver equals the current
+/// capital.
+#[derive(Debug, Clone, Serialize)]
+pub struct CapitalWaterfall {
+    /// Capital of the previous snapshot.
+    pub previous: f64,
+    /// Change from new loans.
+    pub new_business: f64,
+    /// Change from matured loans.
+    pub run_off: f64,
+    /// Change from the probabilities of default of
+    /// re-rated loans.
+    pub pd_migration: f64,
+    /// Change from the loss given default (and its
+    /// variance) of re-rated loans.
+    pub lgd_change: f64,
+    /// Change from the remaining attributes of
+    /// re-rated loans (balance, num, r and weight).
+    pub exposure_change: f64,
+    /// Change from the model parameters.
+    pub model_change: f64,
+    /// Difference between the current snapshot and the
+    /// previous snapshot with every change applied.
+    /// Zero when the changes are complete.
+    pub residual: f64,
+    /// Capital of the current snapshot.
+    pub current: f64,
+}
+
+/// Replaces loans in the portfolio and returns the
+/// change in capital.
+fn replace_loans<U, T>(
+    portfolio: &mut EconomicCapitalAttributes,
+    replacements: &[(Loan, Loan)],
+    u_domain: &[Complex<f64>],
+    log_lpm_cf: &U,
+    capital_fn: &T,
+    capital: &mut f64,
+) -> f64
+where
+    U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    T: Fn(&EconomicCapitalAttributes) -> f64,
+{
+    replacements.iter().for_each(|(before, after)| {
+        portfolio.remove_loan(before, u_domain, log_lpm_cf);
+        portfolio.process_loan(after, u_domain, log_lpm_cf);
+    });
+    let updated = capital_fn(portfolio);
+    let change = updated - *capital;
+    *capital = updated;
+    change
+}
+
+/// Returns the waterfall of the change in capital
+/// between two snapshots.
+///
+/// # Arguments
+///
+/// * `previous` - The previous snapshot.
+/// * `current` - The current snapshot.
+/// * `changes` - The loan changes between the
+///   snapshots.
+/// * `u_domain` - The vector of complex values
+///   provided to the characteristic function.  Must
+///   be the same as for the snapshots.
+/// * `log_lpm_cf` - The result from calling
+///   [get_log_lpm_cf](../fn.get_log_lpm_cf.html).
+/// * `previous_capital_fn` - Function which computes
+///   the capital of a portfolio with the previous
+///   model parameters (eg, systemic variances).
+/// * `current_capital_fn` - Function which computes
+///   the capital of a portfolio with the current
+///   model parameters.
+///
+/// # Examples
+/// ```
+/// extern crate loan_ec;
+/// extern crate fang_oost;
+/// extern crate rayon;
+/// extern crate num_complex;
+/// use rayon::prelude::*;
+/// use num_complex::Complex;
+/// # fn main(){
+/// let num_u:usize=128;
+/// let u_domain: Vec<Complex<f64>> = fang_oost::get_u_domain(num_u, -5000.0, 0.0).collect();
+/// let liquid_fn = loan_ec::get_liquidity_risk_fn(0.0, 0.0);
+/// let lgd_fn = |u: &Complex<f64>, l: f64, _lgd_v: f64| (-u * l).exp();
+/// let log_lpm_cf = loan_ec::get_log_lpm_cf(&lgd_fn, &liquid_fn);
+/// let loan=loan_ec::Loan{
+///     pd: 0.05, lgd: 0.5, lgd_variance: 0.0, balance: 1.0,
+///     r: 0.0, weight: vec![1.0], num: 1000.0
+/// };
+/// let mut previous=loan_ec::EconomicCapitalAttributes::new(num_u, 1);
+/// previous.process_loan(&loan, &u_domain, &log_lpm_cf);
+/// let mut current=previous.clone();
+/// current.process_loan(&loan, &u_domain, &log_lpm_cf);
+/// //capital as the standard deviation of the loss
+/// let capital_fn = |ec: &loan_ec::EconomicCapitalAttributes| {
+///     ec.get_portfolio_variance(&[1.0], &[0.4]).sqrt()
+/// };
+/// let waterfall = loan_ec::waterfall::get_capital_waterfall(
+///     &previous, &current, &[loan_ec::waterfall::LoanChange::New(loan)],
+///     &u_domain, &log_lpm_cf, &capital_fn, &capital_fn
+/// );
+/// assert!(waterfall.new_business > 0.0);
+/// assert!(waterfall.residual.abs() < 0.0000001);
+/// # }
+/// ```
+pub fn get_capital_waterfall<U, T, S>(
+    previous: &EconomicCapitalAttributes,
+    current: &EconomicCapitalAttributes,
+    changes: &[LoanChange],
+    u_domain: &[Complex<f64>],
+    log_lpm_cf: U,
+    previous_capital_fn: T,
+    current_capital_fn: S,
+) -> CapitalWaterfall
+where
+    U: Fn(&Complex<f64>, &Loan) -> Complex<f64> + std::marker::Sync + std::marker::Send,
+    T: Fn(&EconomicCapitalAttributes) -> f64,
+    S: Fn(&EconomicCapitalAttributes) -> f64,
+{
+    let previous_capital = previous_capital_fn(previous);
+    let mut capital = previous_capital;
+    let mut portfolio = previous.clone();
+    let mut step = |replacements: Vec<(Loan, Loan)>, portfolio: &mut EconomicCapitalAttributes| {
+        replace_loans(
+            portfolio,
+            &replacements,
+            u_domain,
+            &log_lpm_cf,
+            &previous_capital_fn,
+            &mut capital,
+        )
+    };
+    let empty = |loan: &Loan| Loan {
+        num: 0.0,
+        ..loan.clone()
+    };
+    let new_business = step(
+        changes
+            .iter()
+            .filter_map(|change| match change {
+                LoanChange::New(loan) => Some((empty(loan), loan.clone())),
+                _ => None,
+            })
+            .collect(),
+        &mut portfolio,
+    );
+    let run_off = step(
+        changes
+            .iter()
+            .filter_map(|change| match change {
+                LoanChange::Matured(loan) => Some((loan.clone(), empty(loan))),
+                _ => None,
+            })
+            .collect(),
+        &mut portfolio,
+    );
+    let rerated: Vec<(&Loan, &Loan)> = changes
+        .iter()
+        .filter_map(|change| match change {
+            LoanChange::Rerated { before, after } => Some((before, after)),
+            _ => None,
+        })
+        .collect();
+    let migrated = |before: &Loan, after: &Loan| Loan {
+        pd: after.pd,
+        ..before.clone()
+    };
+    let lgd_changed = |before: &Loan, after: &Loan| Loan {
+        lgd: after.lgd,
+        lgd_variance: after.lgd_variance,
+        ..migrated(before, after)
+    };
+    let pd_migration = step(
+        rerated
+            .iter()
+            .map(|(before, after)| ((*before).clone(), migrated(before, after)))
+            .collect(),
+        &mut portfolio,
+    );
+    let lgd_change = step(
+        rerated
+            .iter()
+            .map(|(before, after)| (migrated(before, after), lgd_changed(before, after)))
+            .collect(),
+        &mut portfolio,
+    );
+    let exposure_change = step(
+        rerated
+            .iter()
+            .map(|(before, after)| (lgd_changed(before, after), (*after).clone()))
+            .collect(),
+        &mut portfolio,
+    );
+    let updated_capital = current_capital_fn(&portfolio);
+    let current_capital = current_capital_fn(current);
+    CapitalWaterfall {
+        previous: previous_capital,
+        new_business,
+        run_off,
+        pd_migration,
+        lgd_change,
+        exposure_change,
+        model_change: updated_capital - capital,
+        residual: current_capital - updated_capital,
+        current: current_capital,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use approx::*;
+    fn get_loan(pd: f64, lgd: f64, balance: f64, num: f64) -> Loan {
+        Loan {
+            pd,
+            lgd,
+            balance,
+            r: 0.0,
+            lgd_variance: 0.2,
+            weight: vec![0.4, 0.6],
+            num,
+        }
+    }
+    #[test]
+    fn waterfall_explains_change() {
+        let num_u = 128;
+        let u_domain = get_test_u_domain(num_u, -5000.0, 0.0);
+        let log_lpm_cf = get_test_log_lpm_cf(0.0, 0.0);
+        let kept = get_loan(0.05, 0.5, 1.0, 1000.0);
+        let matured = get_loan(0.02, 0.4, 2.0, 500.0);
+        let rerated_before = get_loan(0.03, 0.4, 1.0, 800.0);
+        let rerated_after = get_loan(0.06, 0.5, 1.5, 800.0);
+        let new = get_loan(0.01, 0.3, 3.0, 200.0);
+        let process = |loans: &[&Loan]| {
+            let mut ec = EconomicCapitalAttributes::new(num_u, 2);
+            loans
+                .iter()
+                .for_each(|loan| ec.process_loan(loan, &u_domain, &log_lpm_cf));
+            ec
+        };
+        let previous = process(&[&kept, &matured, &rerated_before]);
+        let current = process(&[&kept, &rerated_after, &new]);
+        let changes = vec![
+            LoanChange::New(new.clone()),
+            LoanChange::Matured(matured.clone()),
+            LoanChange::Rerated {
+                before: rerated_before.clone(),
+                after: rerated_after.clone(),
+            },
+        ];
+        let capital_fn = |variance: f64| {
+            move |ec: &EconomicCapitalAttributes| {
+                ec.get_portfolio_variance(&[1.0, 1.0], &[variance, 0.3])
+                    .sqrt()
+                    * 3.0
+            }
+        };
+        let waterfall = get_capital_waterfall(
+            &previous,
+            &current,
+            &changes,
+            &u_domain,
+            &log_lpm_cf,
+            capital_fn(0.4),
+            capital_fn(0.5),
+        );
+        assert!(waterfall.new_business > 0.0);
+        assert!(waterfall.run_off < 0.0);
+        assert!(waterfall.pd_migration > 0.0);
+        assert!(waterfall.lgd_change > 0.0);
+        assert!(waterfall.exposure_change > 0.0);
+        assert!(waterfall.model_change > 0.0);
+        assert_abs_diff_eq!(waterfall.residual, 0.0, epsilon = 0.0000001);
+        assert_abs_diff_eq!(
+            waterfall.previous
+                + waterfall.new_business
+                + waterfall.run_off
+                + waterfall.pd_migration
+                + waterfall.lgd_change
+                + waterfall.exposure_change
+                + waterfall.model_change
+                + waterfall.residual,
+            waterfall.current,
+            epsilon = 0.0000001
+        );
+    }
+    #[test]
+    fn remove_loan_reverses_process_loan() {
+        let u_domain = get_test_u_domain(64, -5000.0, 0.0);
+        let log_lpm_cf = get_test_log_lpm_cf(100.0, 0.0001);
+        let loan = Loan {
+            r: 0.1,
+            ..get_loan(0.05, 0.5, 1.0, 1000.0)
+        };
+        let mut ec = EconomicCapitalAttributes::new(64, 2);
+        ec.process_loan(&loan, &u_domain, &log_lpm_cf);
+        ec.remove_loan(&loan, &u_domain, &log_lpm_cf);
+        ec.cf.iter().for_each(|cf| {
+            assert_abs_diff_eq!(cf.re, 0.0, epsilon = 0.0000001);
+            assert_abs_diff_eq!(cf.im, 0.0, epsilon = 0.0000001);
+        });
+        ec.el_vec
+            .iter()
+            .for_each(|el| assert_abs_diff_eq!(*el, 0.0, epsilon = 0.0000001));
+        assert_abs_diff_eq!(ec.lambda, 0.0, epsilon = 0.0000001);
+    }
+}